@@ -8,11 +8,15 @@
 //! directing the application's flow based on the user's command-line invocation.
 
 use clap::{ArgGroup, Parser};
+use raze::core::format::ArchiveFormat;
+use raze::core::signature::VerifyingKey;
+use raze::utils::secret::Secret;
+use raze::utils::security::{Cipher, KdfProfile, Padding, Recipient};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Raze: A blazingly fast and lightweight archiving utility with secure encryption.", long_about = None)]
 #[command(propagate_version = true)]
-#[command(group(ArgGroup::new("mode").required(true).args(&["pack", "unpack"])))]
+#[command(group(ArgGroup::new("mode").required(true).args(&["pack", "unpack", "list", "test", "repair", "convert", "keygen", "rekey", "add_password", "remove_password"])))]
 pub struct RazeArgs {
     /// Activate packing mode.
     #[arg(long, help = "Activate packing mode.")]
@@ -22,23 +26,331 @@ pub struct RazeArgs {
     #[arg(long, help = "Activate unpacking mode.")]
     pub unpack: bool,
 
+    /// Activate listing mode, printing the entries of an archive.
+    #[arg(long, help = "List the contents of an archive.")]
+    pub list: bool,
+
+    /// Activate testing mode, verifying that an archive can be fully extracted.
+    #[arg(
+        long,
+        help = "Verify the integrity of an archive without extracting it."
+    )]
+    pub test: bool,
+
+    /// Activate repair mode, rebuilding damaged blocks from the archive's recovery record.
+    #[arg(long, help = "Repair a damaged archive using its recovery record.")]
+    pub repair: bool,
+
+    /// Activate conversion mode, turning a Raze archive into a ZIP file or a ZIP file
+    /// into a Raze archive, depending on the type of the input.
+    #[arg(long, help = "Convert an archive to ZIP, or a ZIP file to an archive.")]
+    pub convert: bool,
+
+    /// Activate key generation mode, writing a new identity file to the output path
+    /// and printing its public key, for use with `--recipient`.
+    #[arg(long, help = "Generate an identity file and print its public key.")]
+    pub keygen: bool,
+
+    /// (Optional for key generation) Generates a signing key for `--sign` instead of
+    /// an identity, printing its public key for use with `--verify-key`.
+    #[arg(long, requires = "keygen")]
+    pub signing: bool,
+
+    /// Activate re-keying mode, replacing the password or key file given with
+    /// `--password`/`--keyfile` by the one given with `--new-password`/`--new-keyfile`.
+    #[arg(
+        long,
+        help = "Change the password or key file of an encrypted archive."
+    )]
+    pub rekey: bool,
+
+    /// Activate password adding mode, adding a key slot for the password or key file
+    /// given with `--new-password`/`--new-keyfile` to an archive that can already be opened.
+    #[arg(
+        long,
+        help = "Add another password or key file to an encrypted archive."
+    )]
+    pub add_password: bool,
+
+    /// Activate password removal mode, removing the key slot that the given
+    /// credentials open. The last key slot of an archive cannot be removed.
+    #[arg(
+        long,
+        help = "Remove a password or key file from an encrypted archive."
+    )]
+    pub remove_password: bool,
+
     /// (Required for packing) The path to the source file or directory to be compressed.
     #[arg(short, long, value_name = "SOURCE", required_if_eq("pack", "true"))]
     pub source: Option<String>,
 
-    /// (Required for packing) The name or path of the output .rz archive file.
-    #[arg(short, long, value_name = "OUTPUT", required_if_eq("pack", "true"))]
+    /// (Required for packing, converting and key generation) The name or path of the output
+    /// .rz archive file, of the ZIP file written by conversion, or of the new identity or signing key file.
+    #[arg(
+        short,
+        long,
+        value_name = "OUTPUT",
+        required_if_eq_any([("pack", "true"), ("convert", "true"), ("keygen", "true")])
+    )]
     pub output: Option<String>,
 
-    /// (Required for unpacking) The path to the .rz archive file to be decompressed.
-    #[arg(short, long, value_name = "ARCHIVE", required_if_eq("unpack", "true"))]
+    /// (Required for all modes but packing and key generation) The path to the
+    /// .rz archive file, or to the first volume (`.001`) of a split archive. When converting,
+    /// this may also be a ZIP file.
+    #[arg(
+        short,
+        long,
+        value_name = "ARCHIVE",
+        required_if_eq_any([
+            ("unpack", "true"),
+            ("list", "true"),
+            ("test", "true"),
+            ("repair", "true"),
+            ("convert", "true"),
+            ("rekey", "true"),
+            ("add_password", "true"),
+            ("remove_password", "true"),
+        ])
+    )]
     pub archive: Option<String>,
 
     /// (Optional for unpacking) The destination directory for extraction. Defaults to the current directory.
     #[arg(short, long, value_name = "DESTINATION")]
     pub destination: Option<String>,
 
+    /// (Optional for unpacking) Extracts entries with absolute paths, `..` components,
+    /// links pointing outside the destination, or parents that are symbolic links,
+    /// instead of refusing the archive. Only use this for archives from trusted sources.
+    #[arg(long, requires = "unpack")]
+    pub unsafe_paths: bool,
+
+    /// (Optional for packing and converting from ZIP) Refuses to replace an existing
+    /// archive at the output path.
+    #[arg(long)]
+    pub no_clobber: bool,
+
+    /// (Optional for unpacking) Extracts into a hidden staging directory and moves the
+    /// entries into place only once the whole archive has been verified, so that a
+    /// failed extraction leaves the destination untouched.
+    #[arg(long, requires = "unpack")]
+    pub atomic: bool,
+
+    /// (Optional for packing, unpacking and re-keying) Skips the checks that the archive,
+    /// or the extracted entries, fit into the free space at the destination before
+    /// starting, and changes the key slots of signed archives, invalidating their signature.
+    #[arg(long)]
+    pub force: bool,
+
+    /// (Optional for packing and unpacking) Skips the free-space checks as `--force`
+    /// does, without its other effects.
+    #[arg(long)]
+    pub no_space_check: bool,
+
+    /// (Optional for packing and unpacking) Archives and extracts block and character
+    /// devices and FIFOs, which are otherwise skipped with a warning when packing and
+    /// refused when unpacking. Not implied by `--unsafe-paths`.
+    #[arg(long)]
+    pub allow_special_files: bool,
+
+    /// (Optional for unpacking) Stops once the extracted entries exceed this size in
+    /// total, e.g. `500G`. Defaults to 64 GiB.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "unpack")]
+    pub max_total_size: Option<u64>,
+
+    /// (Optional for unpacking) Stops at an entry larger than this size. Defaults to 16 GiB.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "unpack")]
+    pub max_file_size: Option<u64>,
+
+    /// (Optional for unpacking) Stops once the archive holds more than this many entries.
+    /// Defaults to 1000000.
+    #[arg(long, value_name = "COUNT", requires = "unpack")]
+    pub max_entries: Option<u64>,
+
+    /// (Optional for unpacking) Stops once the data expands to more than this many times
+    /// the size of the archive. Defaults to 1000.
+    #[arg(long, value_name = "RATIO", requires = "unpack")]
+    pub max_ratio: Option<u64>,
+
+    /// (Optional for unpacking) Stops at an entry whose path has more than this many
+    /// components. Defaults to 128.
+    #[arg(long, value_name = "DEPTH", requires = "unpack")]
+    pub max_depth: Option<usize>,
+
+    /// (Optional for unpacking) Lifts every extraction limit not given explicitly.
+    /// Only use this for archives from trusted sources.
+    #[arg(long, requires = "unpack")]
+    pub no_limits: bool,
+
+    /// (Optional for unpacking) Keeps the setuid, setgid and sticky bits of the stored
+    /// modes, which are cleared by default.
+    #[arg(long, requires = "unpack", overrides_with = "no_same_permissions")]
+    pub preserve_permissions: bool,
+
+    /// (Optional for unpacking) Clears the setuid, setgid and sticky bits of the stored
+    /// modes. This is the default.
+    #[arg(long, requires = "unpack", overrides_with = "preserve_permissions")]
+    pub no_same_permissions: bool,
+
+    /// (Optional for unpacking) Permission bits to clear from the stored modes, in octal,
+    /// e.g. `022`. Defaults to none.
+    #[arg(long, value_name = "MASK", value_parser = parse_umask, requires = "unpack")]
+    pub umask: Option<u32>,
+
+    /// (Optional for unpacking) Gives extracted entries the owner and group stored in the
+    /// archive, which usually requires root.
+    #[arg(long, requires = "unpack", overrides_with = "no_same_owner")]
+    pub same_owner: bool,
+
+    /// (Optional for unpacking) Leaves extracted entries owned by the extracting user.
+    /// This is the default.
+    #[arg(long, requires = "unpack", overrides_with = "same_owner")]
+    pub no_same_owner: bool,
+
     /// (Optional) Password for encryption or decryption.
     #[arg(short, long, value_name = "PASSWORD")]
-    pub password: Option<String>,
+    pub password: Option<Secret<String>>,
+
+    /// (Optional) A key file for encryption or decryption, used in place of a password
+    /// or together with one, so that both are required to open the archive.
+    #[arg(long, value_name = "FILE")]
+    pub keyfile: Option<String>,
+
+    /// (Optional for re-keying and adding passwords) The new password.
+    #[arg(long, value_name = "PASSWORD")]
+    pub new_password: Option<Secret<String>>,
+
+    /// (Optional for re-keying and adding passwords) The new key file, used in place
+    /// of a new password or together with one.
+    #[arg(long, value_name = "FILE")]
+    pub new_keyfile: Option<String>,
+
+    /// (Optional for packing) Encrypts the archive to this public key, alongside or
+    /// instead of a password. Can be repeated; any of the matching identities can decrypt it.
+    #[arg(long = "recipient", value_name = "PUBLIC_KEY")]
+    pub recipients: Vec<Recipient>,
+
+    /// (Optional for unpacking, listing, testing, converting and re-keying) An identity file
+    /// written by `--keygen`, used to open archives encrypted to its public key.
+    /// Can be repeated.
+    #[arg(long = "identity", value_name = "FILE")]
+    pub identities: Vec<String>,
+
+    /// (Optional for packing) Signs the archive with the signing key in this file,
+    /// written by `--keygen --signing`.
+    #[arg(long, value_name = "FILE")]
+    pub sign: Option<String>,
+
+    /// (Optional for unpacking, listing and testing) Refuses the archive unless it is
+    /// signed by this public key. Can be repeated; any of the keys is accepted.
+    #[arg(long = "verify-key", value_name = "PUBLIC_KEY")]
+    pub verify_keys: Vec<VerifyingKey>,
+
+    /// (Optional for unpacking, listing and testing) Refuses the archive unless it is
+    /// signed by one of the public keys stored in the files of this directory.
+    #[arg(long, value_name = "DIR")]
+    pub trusted_keys: Option<String>,
+
+    /// (Optional for packing) Splits the archive into volumes of at most this size,
+    /// e.g. `650M` or `4G`. Volumes are named `<output>.001`, `<output>.002`, ...
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub volume_size: Option<u64>,
+
+    /// (Optional for packing) Appends Reed-Solomon recovery data amounting to this
+    /// percentage of the archive, e.g. `5%`, so that damaged blocks can be repaired.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub recovery: Option<u8>,
+
+    /// (Optional for packing) Writes a self-extracting Linux executable instead of a
+    /// plain archive. The `raze-sfx` stub must be installed next to `raze`.
+    #[arg(long, help = "Create a self-extracting executable.")]
+    pub sfx: bool,
+
+    /// (Optional for packing) The archive format to write: `tar.zst` (the default `.rz`
+    /// format), `tar.gz`, `tar.xz` or `tar`. Unpacking detects the format automatically.
+    #[arg(long, value_name = "FORMAT")]
+    pub format: Option<ArchiveFormat>,
+
+    /// (Optional for encrypted packing and re-keying) A preset for the Argon2id key
+    /// derivation cost: `interactive`, `moderate` (the default) or `paranoid`.
+    #[arg(long, value_name = "PROFILE")]
+    pub kdf_profile: Option<KdfProfile>,
+
+    /// (Optional for encrypted packing and re-keying) The Argon2id memory cost,
+    /// e.g. `64M` or `1G`. Overrides the value of the KDF profile.
+    #[arg(long, value_name = "SIZE", value_parser = parse_kdf_memory)]
+    pub kdf_memory: Option<u32>,
+
+    /// (Optional for encrypted packing and re-keying) The number of Argon2id iterations.
+    /// Overrides the value of the KDF profile.
+    #[arg(long, value_name = "COUNT")]
+    pub kdf_iterations: Option<u32>,
+
+    /// (Optional for encrypted packing and re-keying) The Argon2id degree of parallelism.
+    /// Overrides the value of the KDF profile.
+    #[arg(long, value_name = "LANES")]
+    pub kdf_parallelism: Option<u32>,
+
+    /// (Optional for encrypted packing) The cipher used to encrypt the archive:
+    /// `aes256gcm` or `xchacha20poly1305`. Chosen from the CPU's features by default.
+    #[arg(long, value_name = "CIPHER")]
+    pub cipher: Option<Cipher>,
+
+    /// (Optional for encrypted packing) Pads the archive so that its size reveals less
+    /// about its contents: `padme` (at most 12% larger) or `pow2` (the next power of two).
+    #[arg(long, value_name = "SCHEME")]
+    pub pad: Option<Padding>,
+}
+
+/// Parses an Argon2id memory cost given as a byte size and returns it in KiB.
+fn parse_kdf_memory(value: &str) -> Result<u32, String> {
+    let kib = parse_size(value)? / 1024;
+    match u32::try_from(kib) {
+        Ok(kib) if kib > 0 => Ok(kib),
+        _ => Err(format!("KDF memory '{}' is out of range", value)),
+    }
+}
+
+/// Parses a file mode mask given in octal, with or without a leading `0o`.
+fn parse_umask(value: &str) -> Result<u32, String> {
+    let digits = value.trim();
+    let digits = digits.strip_prefix("0o").unwrap_or(digits);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mask) if mask <= 0o7777 => Ok(mask),
+        _ => Err(format!("'{}' is not an octal mask such as 022", value)),
+    }
+}
+
+/// Parses a percentage between 1 and 100, with or without a trailing `%`.
+fn parse_percent(value: &str) -> Result<u8, String> {
+    let digits = value.trim().trim_end_matches('%');
+    match digits.parse::<u8>() {
+        Ok(percent) if (1..=100).contains(&percent) => Ok(percent),
+        _ => Err(format!(
+            "'{}' is not a percentage between 1% and 100%",
+            value
+        )),
+    }
+}
+
+/// Parses a byte size with an optional binary unit suffix (`K`, `M`, `G` or `T`).
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let number: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{}'", value)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", value))
 }
@@ -0,0 +1,502 @@
+//! # Beneath Module
+//!
+//! This module writes archive entries on Linux without the races that checking
+//! a path and then creating it is open to. When others can write to the
+//! destination, a directory that passed the checks can be swapped for a
+//! symbolic link before the entry is created, sending it anywhere on the
+//! system. Here the destination is opened once, and every entry is created
+//! relative to a handle for its parent directory, which is itself opened
+//! beneath the destination with `openat2(RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS)`.
+//! The kernel then refuses to leave the destination or to follow a symbolic
+//! link anywhere along the way.
+//!
+//! Kernels before Linux 5.6 lack `openat2`, and some sandboxes block it.
+//! [`Beneath::open`] then returns `None`, as it does on other platforms, and
+//! extraction falls back to the path checks of [`extract`](crate::core::extract).
+
+use crate::core::extract::ExtractPermissions;
+use crate::utils::errors::RazeError;
+use std::io::{self, Read};
+use std::path::Path;
+use tar::Entry;
+
+#[cfg(target_os = "linux")]
+use crate::core::extract;
+#[cfg(target_os = "linux")]
+use crate::utils::security;
+#[cfg(target_os = "linux")]
+use std::ffi::{CStr, CString, OsStr};
+#[cfg(target_os = "linux")]
+use std::fs::{File, FileTimes};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::time::{Duration, SystemTime};
+
+/// A handle for the destination directory, beneath which entries are created.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub(crate) struct Beneath {
+    root: OwnedFd,
+}
+
+/// Race-free extraction is only available on Linux.
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub(crate) enum Beneath {}
+
+#[cfg(target_os = "linux")]
+impl Beneath {
+    /// Opens the existing directory `destination` for extraction, or returns
+    /// `None` if the kernel does not support `openat2`.
+    pub(crate) fn open(destination: &Path) -> io::Result<Option<Self>> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let root = OwnedFd::from(
+            File::options()
+                .read(true)
+                .custom_flags(libc::O_DIRECTORY)
+                .open(destination)?,
+        );
+        match openat2(root.as_fd(), c".", libc::O_PATH | libc::O_DIRECTORY, 0) {
+            Ok(_) => Ok(Some(Beneath { root })),
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENOSYS | libc::EPERM | libc::EINVAL)
+                ) =>
+            {
+                log::debug!("openat2 is unavailable, extracting by path: {}", e);
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes `entry` to the relative `path` beneath the destination, applying
+    /// its mode and owner as `permissions` describes.
+    ///
+    /// The path is expected to have passed the checks of `extract` already;
+    /// should a symbolic link appear along it in the meantime, the entry is
+    /// refused with [`RazeError::SymlinkTraversal`] all the same.
+    pub(crate) fn unpack<R: Read>(
+        &self,
+        entry: &mut Entry<'_, R>,
+        path: &Path,
+        permissions: &ExtractPermissions,
+    ) -> Result<(), RazeError> {
+        // Entries naming the destination itself, such as `./`, are skipped as `tar` does.
+        let Some(name) = path.file_name() else {
+            return Ok(());
+        };
+        let parent = self.create_parents(path)?;
+        let name = c_string(name)?;
+        let header = entry.header();
+        let kind = header.entry_type();
+        let old_directory = header.as_ustar().is_none() && entry.path_bytes().ends_with(b"/");
+
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+        {
+            Ok(())
+        } else if kind.is_dir() || old_directory {
+            create_dir(&parent, &name, entry, permissions).map_err(|e| traversal(e, path, path))
+        } else if kind.is_symlink() {
+            create_symlink(&parent, &name, entry, permissions)
+        } else if kind.is_hard_link() {
+            self.create_hard_link(&parent, &name, entry, path)
+        } else if kind.is_block_special() || kind.is_character_special() || kind.is_fifo() {
+            create_special(&parent, &name, entry, permissions)
+        } else {
+            // As `tar` does, unknown entry types are written as regular files.
+            create_file(&parent, &name, entry, path, permissions)
+        }
+    }
+
+    /// Opens the parent directory of the relative `path`, creating any missing
+    /// directories along it one at a time.
+    fn create_parents(&self, path: &Path) -> Result<OwnedFd, RazeError> {
+        let mut dir = self.root.try_clone()?;
+        let mut reached = PathBuf::new();
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            reached.push(component);
+            let name = c_string(component.as_os_str())?;
+            // `mkdirat` never follows a symbolic link in place of the new directory.
+            // SAFETY: `dir` is an open descriptor and `name` a valid NUL-terminated string.
+            if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) } != 0 {
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() != Some(libc::EEXIST) {
+                    return Err(error.into());
+                }
+            }
+            dir = openat2(dir.as_fd(), &name, libc::O_PATH | libc::O_DIRECTORY, 0)
+                .map_err(|e| traversal(e, path, &reached))?;
+        }
+        Ok(dir)
+    }
+
+    /// Creates the hard link described by `entry` as `name` in `parent`. Its
+    /// target is stored relative to the destination, and is resolved beneath it
+    /// without following symbolic links.
+    fn create_hard_link<R: Read>(
+        &self,
+        parent: &OwnedFd,
+        name: &CStr,
+        entry: &Entry<'_, R>,
+        path: &Path,
+    ) -> Result<(), RazeError> {
+        let target = link_name(entry)?;
+        let escape = || RazeError::LinkEscape {
+            path: path.display().to_string(),
+            target: target.display().to_string(),
+        };
+        let Some(target_name) = target.file_name() else {
+            return Err(escape());
+        };
+        let target_dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => {
+                let dir = c_string(dir.as_os_str())?;
+                openat2(self.root.as_fd(), &dir, libc::O_PATH | libc::O_DIRECTORY, 0).map_err(
+                    |e| match e.raw_os_error() {
+                        Some(libc::ELOOP | libc::EXDEV) => escape(),
+                        _ => e.into(),
+                    },
+                )?
+            },
+            _ => self.root.try_clone()?,
+        };
+        let target_name = c_string(target_name)?;
+        // Without `AT_SYMLINK_FOLLOW`, a symbolic link as the target is linked itself.
+        // SAFETY: Both descriptors are open and both names valid NUL-terminated strings.
+        let result = unsafe {
+            libc::linkat(
+                target_dir.as_raw_fd(),
+                target_name.as_ptr(),
+                parent.as_raw_fd(),
+                name.as_ptr(),
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Beneath {
+    /// Returns `None`, as `openat2` is specific to Linux.
+    pub(crate) fn open(_destination: &Path) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Never called, as no `Beneath` can be opened.
+    pub(crate) fn unpack<R: Read>(
+        &self,
+        _entry: &mut Entry<'_, R>,
+        _path: &Path,
+        _permissions: &ExtractPermissions,
+    ) -> Result<(), RazeError> {
+        match *self {}
+    }
+}
+
+/// Opens `path` relative to `dir` with `openat2`, refusing to leave `dir` or
+/// to follow any symbolic link. `O_CLOEXEC` is always added to `flags`.
+#[cfg(target_os = "linux")]
+fn openat2(dir: BorrowedFd<'_>, path: &CStr, flags: libc::c_int, mode: u32) -> io::Result<OwnedFd> {
+    // SAFETY: `open_how` consists of integers only, for which zero is valid.
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (flags | libc::O_CLOEXEC) as u64;
+    how.mode = u64::from(mode);
+    how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_SYMLINKS;
+    loop {
+        // SAFETY: `dir` is an open descriptor, `path` a valid NUL-terminated
+        // string and `how` a valid `open_how` of the size passed; all outlive the call.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                dir.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd >= 0 {
+            // SAFETY: `openat2` returned a new descriptor that nothing else owns.
+            return Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) });
+        }
+        let error = io::Error::last_os_error();
+        // `EAGAIN` reports a concurrent rename that the kernel could not rule out.
+        if !matches!(error.raw_os_error(), Some(libc::EINTR | libc::EAGAIN)) {
+            return Err(error);
+        }
+    }
+}
+
+/// Turns the errors `openat2` reports for a symbolic link, or a path leading
+/// outside, into [`RazeError::SymlinkTraversal`] for `path` at `link`.
+#[cfg(target_os = "linux")]
+fn traversal(error: io::Error, path: &Path, link: &Path) -> RazeError {
+    match error.raw_os_error() {
+        Some(libc::ELOOP | libc::EXDEV) => RazeError::SymlinkTraversal {
+            path: path.display().to_string(),
+            link: link.display().to_string(),
+        },
+        _ => error.into(),
+    }
+}
+
+/// Creates the regular file described by `entry` as `name` in `parent`,
+/// replacing any file of that name, and writes its contents.
+///
+/// A symbolic link put in place of the file after the old one is removed
+/// makes the exclusive open fail rather than be followed, and is reported as
+/// [`RazeError::SymlinkTraversal`] for `path`.
+#[cfg(target_os = "linux")]
+fn create_file<R: Read>(
+    parent: &OwnedFd,
+    name: &CStr,
+    entry: &mut Entry<'_, R>,
+    path: &Path,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    remove(parent, name)?;
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL;
+    let file = openat2(parent.as_fd(), name, flags, 0o600).map_err(|e| {
+        if e.raw_os_error() == Some(libc::EEXIST) && is_symlink(parent, name) {
+            RazeError::SymlinkTraversal {
+                path: path.display().to_string(),
+                link: path.display().to_string(),
+            }
+        } else {
+            traversal(e, path, path)
+        }
+    })?;
+    let mut file = File::from(file);
+    let size = entry.size();
+    let written = io::copy(entry, &mut file).map_err(security::unwrap_crypto_error)?;
+    if written != size {
+        return Err(
+            io::Error::new(io::ErrorKind::UnexpectedEof, "failed to write entire file").into(),
+        );
+    }
+
+    let header = entry.header();
+    let mtime = mtime(header)?;
+    file.set_times(FileTimes::new().set_accessed(mtime).set_modified(mtime))?;
+    if permissions.same_owner {
+        std::os::unix::fs::fchown(
+            &file,
+            Some(header.uid()? as u32),
+            Some(header.gid()? as u32),
+        )?;
+    }
+    file.set_permissions(std::fs::Permissions::from_mode(permissions.mode(header)?))?;
+    Ok(())
+}
+
+/// Creates the directory described by `entry` as `name` in `parent`, or
+/// reuses an existing one, and applies its mode and owner.
+#[cfg(target_os = "linux")]
+fn create_dir<R: Read>(
+    parent: &OwnedFd,
+    name: &CStr,
+    entry: &Entry<'_, R>,
+    permissions: &ExtractPermissions,
+) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // SAFETY: `parent` is an open descriptor and `name` a valid NUL-terminated string.
+    if unsafe { libc::mkdirat(parent.as_raw_fd(), name.as_ptr(), 0o700) } != 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EEXIST) {
+            return Err(error);
+        }
+    }
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+    let dir = File::from(openat2(parent.as_fd(), name, flags, 0)?);
+    let header = entry.header();
+    if permissions.same_owner {
+        std::os::unix::fs::fchown(&dir, Some(header.uid()? as u32), Some(header.gid()? as u32))?;
+    }
+    dir.set_permissions(std::fs::Permissions::from_mode(permissions.mode(header)?))
+}
+
+/// Creates the symbolic link described by `entry` as `name` in `parent`,
+/// replacing any file of that name.
+#[cfg(target_os = "linux")]
+fn create_symlink<R: Read>(
+    parent: &OwnedFd,
+    name: &CStr,
+    entry: &Entry<'_, R>,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    let target = c_string(link_name(entry)?.as_os_str())?;
+    remove(parent, name)?;
+    // SAFETY: `parent` is an open descriptor and both names valid NUL-terminated strings.
+    if unsafe { libc::symlinkat(target.as_ptr(), parent.as_raw_fd(), name.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let header = entry.header();
+    if permissions.same_owner {
+        chown_node(parent, name, header.uid()?, header.gid()?)?;
+    }
+    let mtime = libc::timespec {
+        tv_sec: mtime_secs(header)? as libc::time_t,
+        tv_nsec: 0,
+    };
+    let times = [mtime, mtime];
+    // SAFETY: `parent` is an open descriptor, `name` a valid NUL-terminated
+    // string and `times` holds the two timestamps `utimensat` reads.
+    let result = unsafe {
+        libc::utimensat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Creates the device node or FIFO described by `entry` as `name` in
+/// `parent`, replacing any file of that name, and applies its mode and owner.
+#[cfg(target_os = "linux")]
+fn create_special<R: Read>(
+    parent: &OwnedFd,
+    name: &CStr,
+    entry: &Entry<'_, R>,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    let header = entry.header();
+    let (file_type, device) = extract::special_node(header)?;
+    let mode = permissions.mode(header)?;
+    remove(parent, name)?;
+    // SAFETY: `parent` is an open descriptor and `name` a valid NUL-terminated string.
+    let result = unsafe {
+        libc::mknodat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            file_type | mode as libc::mode_t,
+            device,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if permissions.same_owner {
+        chown_node(parent, name, header.uid()?, header.gid()?)?;
+    }
+    // `mknodat` is subject to the process umask, and ownership changes can
+    // clear special bits. A symbolic link put in its place is not followed.
+    // SAFETY: `parent` is an open descriptor and `name` a valid NUL-terminated string.
+    let result = unsafe {
+        libc::fchmodat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            mode as libc::mode_t,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Gives `name` in `parent` the owner `uid` and group `gid`, without
+/// following it if it is a symbolic link.
+#[cfg(target_os = "linux")]
+fn chown_node(parent: &OwnedFd, name: &CStr, uid: u64, gid: u64) -> io::Result<()> {
+    // SAFETY: `parent` is an open descriptor and `name` a valid NUL-terminated string.
+    let result = unsafe {
+        libc::fchownat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            uid as libc::uid_t,
+            gid as libc::gid_t,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns `true` if `name` in `parent` is a symbolic link.
+#[cfg(target_os = "linux")]
+fn is_symlink(parent: &OwnedFd, name: &CStr) -> bool {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `parent` is an open descriptor, `name` a valid NUL-terminated
+    // string and `stat` valid for writes; all outlive the call.
+    let result = unsafe {
+        libc::fstatat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    // SAFETY: `fstatat` succeeded, so it has filled in `stat`.
+    result == 0 && unsafe { stat.assume_init() }.st_mode & libc::S_IFMT == libc::S_IFLNK
+}
+
+/// Removes the file, link or special file `name` in `parent`, if there is one.
+/// Directories are kept, so that creating an entry in their place fails.
+#[cfg(target_os = "linux")]
+fn remove(parent: &OwnedFd, name: &CStr) -> io::Result<()> {
+    // SAFETY: `parent` is an open descriptor and `name` a valid NUL-terminated string.
+    if unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), 0) } != 0 {
+        let error = io::Error::last_os_error();
+        if !matches!(error.raw_os_error(), Some(libc::ENOENT | libc::EISDIR)) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the link target stored in `entry`, which must not be empty.
+#[cfg(target_os = "linux")]
+fn link_name<R: Read>(entry: &Entry<'_, R>) -> io::Result<PathBuf> {
+    match entry.link_name()? {
+        Some(target) if !target.as_os_str().is_empty() => Ok(target.into_owned()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("link '{}' has no target", entry.path()?.display()),
+        )),
+    }
+}
+
+/// Returns the modification time stored in `header` in seconds. Like `tar`,
+/// a time of zero is moved to one second past the epoch, as some tools
+/// mistake zero for a missing time.
+#[cfg(target_os = "linux")]
+fn mtime_secs(header: &tar::Header) -> io::Result<u64> {
+    Ok(header.mtime()?.max(1))
+}
+
+/// Returns the modification time stored in `header`, as [`mtime_secs`] does.
+#[cfg(target_os = "linux")]
+fn mtime(header: &tar::Header) -> io::Result<SystemTime> {
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime_secs(header)?))
+}
+
+/// Converts `name` into a C string, refusing names containing NUL bytes.
+#[cfg(target_os = "linux")]
+fn c_string(name: &OsStr) -> io::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
@@ -7,14 +7,74 @@
 //! The primary function, `pack`, orchestrates the entire compression process,
 //! handling path validation, archive creation, and error management.
 
+use crate::core::format::{ArchiveFormat, Compressor};
+use crate::core::recovery::RecoveryWriter;
+use crate::core::sfx;
+use crate::core::signature::{SignatureWriter, SigningKey};
+use crate::core::volume::{self, VolumeWriter};
+use crate::utils::atomic::{self, AtomicFile, OutputFiles};
 use crate::utils::errors::RazeError;
-use crate::utils::security;
-use log::info;
-use std::fs::File;
-use std::io::{Seek, SeekFrom};
-use std::path::Path;
+use crate::utils::secret::Secret;
+use crate::utils::security::{
+    Cipher, EncryptWriter, KdfParams, KeyFile, KeySlots, Padding, Recipient,
+};
+use crate::utils::space;
+use log::{info, warn};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use tar::Builder;
-use zstd::Encoder;
+
+/// Options controlling how [`pack_with_options`] writes an archive.
+#[derive(Clone, Default)]
+pub struct PackOptions {
+    /// Password used to encrypt the archive. `None` writes an unencrypted archive,
+    /// unless a `key_file` or `recipients` are given.
+    pub password: Option<Secret<String>>,
+    /// Key file used to encrypt the archive, alone or as a second factor
+    /// together with `password`.
+    pub key_file: Option<KeyFile>,
+    /// Public keys to encrypt the archive to, alongside or instead of a password.
+    /// Each of the matching identities can decrypt it; without a password or
+    /// key file, the writer itself cannot.
+    pub recipients: Vec<Recipient>,
+    /// Splits the archive into volumes of at most this many bytes each.
+    /// The volumes are written next to `output` as `output.001`, `output.002`, ...
+    pub volume_size: Option<u64>,
+    /// Appends a Reed-Solomon recovery record with this much parity, in percent
+    /// of the archive size, so that damaged blocks can later be rebuilt.
+    pub recovery_percent: Option<u8>,
+    /// Writes a self-extracting executable by prepending the extraction stub at
+    /// this path (usually the `raze-sfx` binary found by `sfx::find_stub`).
+    pub sfx_stub: Option<PathBuf>,
+    /// The container format to write. Defaults to Raze's native Zstandard-compressed tarball.
+    pub format: ArchiveFormat,
+    /// Argon2id cost parameters used to derive the encryption key from `password`.
+    pub kdf: KdfParams,
+    /// The cipher used to encrypt the archive. Defaults to the one best suited to the current CPU.
+    pub cipher: Cipher,
+    /// Pads the encrypted stream after its final chunk, so that the archive
+    /// size reveals less about its contents. Requires encryption.
+    pub padding: Padding,
+    /// Signs the archive stream with this key, so that it can be checked with
+    /// `signature::verify` before it is opened.
+    pub signing_key: Option<SigningKey>,
+    /// Archives device nodes and FIFOs found in the source. By default they
+    /// are skipped with a warning. Sockets are always skipped.
+    pub special_files: bool,
+    /// Refuses to replace an existing archive, or existing volumes, at the output path.
+    pub no_clobber: bool,
+    /// Makes sure before packing that the archive fits into the free space at
+    /// the output path, going by an estimate of its size.
+    pub check_space: bool,
+}
+
+impl PackOptions {
+    /// Returns `true` if archives written with these options are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.password.is_some() || self.key_file.is_some() || !self.recipients.is_empty()
+    }
+}
 
 /// Compresses a given file or directory into a `.rz` archive using Zstandard.
 /// Optionally encrypts the archive if a password is provided.
@@ -22,6 +82,23 @@ pub fn pack(
     source: impl AsRef<Path>,
     output: impl AsRef<Path>,
     password: Option<&str>,
+) -> Result<(), RazeError> {
+    let options = PackOptions {
+        password: password.map(Secret::from),
+        ..PackOptions::default()
+    };
+    pack_with_options(source, output, &options)
+}
+
+/// Compresses a given file or directory into a `.rz` archive as described by `options`.
+///
+/// With `options.check_space`, packing is refused with
+/// [`RazeError::InsufficientSpace`] before anything is written if the archive
+/// might not fit into the free space at `output`.
+pub fn pack_with_options(
+    source: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &PackOptions,
 ) -> Result<(), RazeError> {
     let source_path = source.as_ref();
     let output_path = output.as_ref();
@@ -29,85 +106,333 @@ pub fn pack(
     if !source_path.exists() {
         return Err(RazeError::NotFound(source_path.display().to_string()));
     }
+    if options.check_space {
+        if let Some(free) = space::free_space(output_path)? {
+            let estimate = estimate_size(source_path, options)?;
+            free.check(output_path, estimate, "up to")?;
+        }
+    }
 
     info!(
-        "Starting compression of '{}' into '{}'{}...",
+        "Starting compression of '{}' into '{}' ({}){}...",
         source_path.display(),
         output_path.display(),
-        if password.is_some() {
+        options.format,
+        if options.is_encrypted() {
             " with encryption"
         } else {
             ""
         }
     );
 
-    // If we have a password, we'll compress into a buffer first, then encrypt that buffer into the final file.
-    // For simplicity and to avoid large memory usage for very large files, we could use a temp file.
-    // However, for "super complex security", we might want to avoid writing unencrypted data to disk.
-    // Let's use a streaming approach.
-
-    let final_output = File::create(output_path)?;
-
-    if let Some(pwd) = password {
-        // We need to stream Tar -> Zstd -> Encrypt -> File
-        // We can use a pipe-like structure or just a simple intermediary buffer if we don't want to overcomplicate.
-        // Actually, we can implement a custom Writer that encrypts on the fly,
-        // but our encrypt_stream takes a Reader.
-
-        // Let's use a temporary file for the compressed but unencrypted data.
-        // This is a trade-off. To be even more secure, we'd do it all in memory or with a custom writer.
-        let mut temp_file = tempfile::tempfile()?;
-
-        {
-            let encoder = Encoder::new(&temp_file, 3)
-                .map_err(|e| RazeError::CompressionError(e.to_string()))?
-                .auto_finish();
-
-            let mut tar_builder = Builder::new(encoder);
-            append_to_tar(&mut tar_builder, source_path)?;
-            tar_builder.into_inner().map_err(|e| {
-                RazeError::CompressionError(format!("Failed to finish archive: {}", e))
-            })?;
-        }
+    // The output may lie inside the source, and must not end up in itself.
+    let outputs = OutputFiles::default();
+    create_archive(output_path, options, &outputs, |tar_builder| {
+        append_to_tar(tar_builder, source_path, options.special_files, &outputs)
+    })
+}
 
-        // Now encrypt from temp_file to final_output
-        temp_file.seek(SeekFrom::Start(0))?;
-        security::encrypt_stream(temp_file, final_output, pwd)?;
+/// Creates the archive file(s) at `output_path` as described by `options`,
+/// using `fill` to append the entries to the tar stream.
+///
+/// This handles everything around the tar stream itself: compression,
+/// encryption, recovery records, volumes and self-extracting executables.
+/// The archive is written to a temporary file next to `output_path` and
+/// renamed into place only once it is complete and flushed to disk, so a
+/// failure never leaves a partial archive behind or destroys an existing one.
+///
+/// Every file written, and any archive already at `output_path`, is recorded
+/// in `outputs` before `fill` runs or, for volumes, as soon as it is created.
+pub(crate) fn create_archive<F>(
+    output_path: &Path,
+    options: &PackOptions,
+    outputs: &OutputFiles,
+    fill: F,
+) -> Result<(), RazeError>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> Result<(), RazeError>,
+{
+    if options.padding != Padding::None && !options.is_encrypted() {
+        return Err(RazeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Padding requires an encrypted archive.",
+        )));
+    }
+    if options.sfx_stub.is_some() && options.volume_size.is_some() {
+        return Err(RazeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Self-extracting archives cannot be split into volumes.",
+        )));
+    }
+    // Fail before doing any work; the final rename checks again.
+    let first_path = match options.volume_size {
+        Some(_) => volume::volume_path(output_path, 1),
+        None => output_path.to_path_buf(),
+    };
+    if options.no_clobber && first_path.exists() {
+        return Err(atomic::refuse_to_replace(&first_path).into());
+    }
+    // An older archive at the same path is about to be replaced; leave it out too.
+    match options.volume_size {
+        Some(_) => (1..)
+            .map(|index| volume::volume_path(output_path, index))
+            .take_while(|path| path.exists())
+            .for_each(|path| outputs.add_path(&path)),
+        None => outputs.add_path(output_path),
+    }
+
+    if let Some(stub_path) = &options.sfx_stub {
+        let mut final_output = AtomicFile::create(output_path)?;
+        outputs.add(final_output.as_file())?;
+        let stub = sfx::write_stub(&mut final_output, stub_path)?;
+        let mut final_output = write_protected(final_output, options, &stub, fill)?;
+        sfx::write_trailer(&mut final_output, stub.len() as u64)?;
+        sfx::make_executable(final_output.temp_path())?;
+        final_output.commit(options.no_clobber)?;
+        info!(
+            "Successfully created self-extracting archive: {}",
+            output_path.display()
+        );
+    } else if let Some(volume_size) = options.volume_size {
+        let volumes = VolumeWriter::new(output_path, volume_size)?
+            .no_clobber(options.no_clobber)
+            .tracked(outputs.clone());
+        let paths = write_protected(volumes, options, &[], fill)?.finish()?;
+        info!(
+            "Successfully created archive: {} ({} volumes)",
+            paths[0].display(),
+            paths.len()
+        );
     } else {
-        let encoder = Encoder::new(final_output, 3)
-            .map_err(|e| RazeError::CompressionError(e.to_string()))?
-            .auto_finish();
+        let final_output = AtomicFile::create(output_path)?;
+        outputs.add(final_output.as_file())?;
+        write_protected(final_output, options, &[], fill)?.commit(options.no_clobber)?;
+        info!("Successfully created archive: {}", output_path.display());
+    }
+    Ok(())
+}
+
+/// Writes the archive stream into `writer`, followed by a signature and a recovery
+/// record if requested, and returns the writer for finalization. `stub` is the
+/// self-extraction stub already written in front of the archive stream, if any.
+fn write_protected<W: Write, F>(
+    mut writer: W,
+    options: &PackOptions,
+    stub: &[u8],
+    fill: F,
+) -> Result<W, RazeError>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> Result<(), RazeError>,
+{
+    match options.recovery_percent {
+        Some(percent) => {
+            let mut recovery = RecoveryWriter::new(writer, percent)?;
+            write_signed(&mut recovery, options, stub, fill)?;
+            recovery.finish()
+        },
+        None => {
+            write_signed(&mut writer, options, stub, fill)?;
+            Ok(writer)
+        },
+    }
+}
+
+/// Writes the archive stream into `writer`, followed by its signature over
+/// `stub` and the archive stream if a signing key is given.
+fn write_signed<W: Write, F>(
+    writer: W,
+    options: &PackOptions,
+    stub: &[u8],
+    fill: F,
+) -> Result<(), RazeError>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> Result<(), RazeError>,
+{
+    match &options.signing_key {
+        Some(key) => {
+            let mut signer = SignatureWriter::new(writer, key).with_stub(stub);
+            write_archive(&mut signer, options, fill)?;
+            signer.finish()?;
+            info!("Signed the archive with {}", key.verifying_key());
+            Ok(())
+        },
+        None => write_archive(writer, options, fill),
+    }
+}
+
+/// Writes the compressed, and optionally encrypted, archive stream into `writer`.
+fn write_archive<W: Write, F>(
+    mut writer: W,
+    options: &PackOptions,
+    fill: F,
+) -> Result<(), RazeError>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> Result<(), RazeError>,
+{
+    if options.is_encrypted() {
+        // Stream Tar -> Zstd -> Encrypt -> File in a single pass.
+        let mut encryptor = EncryptWriter::with_key_slots(&mut writer, &key_slots(options)?)?
+            .padded(options.padding);
+        write_compressed(&mut encryptor, options.format, fill)?;
+        encryptor.finish()?;
+    } else {
+        write_compressed(&mut writer, options.format, fill)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Builds a key slot for the password and key file in `options`, and one for
+/// each of its recipients.
+fn key_slots(options: &PackOptions) -> Result<KeySlots, RazeError> {
+    let mut slots = KeySlots::new(options.cipher)?;
+    if options.password.is_some() || options.key_file.is_some() {
+        slots.add_secret(
+            options.password.as_ref().map(|p| p.expose().as_str()),
+            options.key_file.as_ref(),
+            options.kdf,
+        )?;
+    }
+    for recipient in &options.recipients {
+        slots.add_recipient(recipient)?;
+    }
+    Ok(slots)
+}
+
+/// Writes the tar stream produced by `fill`, compressed as `format`, into `writer`.
+fn write_compressed<W: Write, F>(writer: W, format: ArchiveFormat, fill: F) -> Result<(), RazeError>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> Result<(), RazeError>,
+{
+    let mut encoder = Compressor::new(format, writer)?;
 
-        let mut tar_builder = Builder::new(encoder);
-        append_to_tar(&mut tar_builder, source_path)?;
+    {
+        let mut tar_builder = Builder::new(&mut encoder as &mut dyn Write);
+        fill(&mut tar_builder)?;
         tar_builder
-            .into_inner()
+            .finish()
             .map_err(|e| RazeError::CompressionError(format!("Failed to finish archive: {}", e)))?;
     }
-
-    info!("Successfully created archive: {}", output_path.display());
+    encoder
+        .finish()
+        .map_err(|e| RazeError::CompressionError(format!("Failed to finish archive: {}", e)))?;
     Ok(())
 }
 
-fn append_to_tar<W: std::io::Write>(
+/// Estimates the largest size the archive of `source_path` written with
+/// `options` may take up, from the sizes of the files it would hold.
+///
+/// Compression usually shrinks the archive well below the size of the
+/// tarball, but incompressible contents do not, so the tarball size is taken
+/// as it is, with room for framing, recovery data, padding and the stub.
+fn estimate_size(source_path: &Path, options: &PackOptions) -> Result<u64, RazeError> {
+    const BLOCK: u64 = 512;
+    let name_len = source_path.file_name().map_or(1, |name| name.len()) as u64;
+    // The two zero blocks that end a tarball.
+    let mut tar_size = 2 * BLOCK;
+    let mut pending = vec![source_path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = fs::metadata(&path)?;
+        let relative = path.strip_prefix(source_path).unwrap_or(Path::new(""));
+        let path_len = name_len + 1 + relative.as_os_str().len() as u64;
+        // One header block, and a long name entry for paths that do not fit into it.
+        tar_size += BLOCK;
+        if path_len > 100 {
+            tar_size += BLOCK + path_len.next_multiple_of(BLOCK);
+        }
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+        } else if metadata.is_file() {
+            tar_size = tar_size.saturating_add(metadata.len().next_multiple_of(BLOCK));
+        }
+    }
+
+    let mut estimate = tar_size.saturating_add(tar_size / 256 + (64 << 10));
+    if let Some(percent) = options.recovery_percent {
+        estimate = estimate.saturating_add(estimate / 100 * (u64::from(percent) + 1));
+    }
+    if options.padding != Padding::None {
+        estimate = options.padding.padded_len(estimate);
+    }
+    if let Some(stub_path) = &options.sfx_stub {
+        estimate = estimate.saturating_add(fs::metadata(stub_path).map_or(0, |m| m.len()));
+    }
+    Ok(estimate)
+}
+
+/// Appends `source_path` under its file name, descending into directories and
+/// following symbolic links as `tar::Builder::append_dir_all` does.
+///
+/// Device nodes and FIFOs are archived only if `special_files` is set and are
+/// otherwise skipped with a warning, as are sockets, which tar cannot store.
+/// The files in `outputs` are left out.
+fn append_to_tar<W: Write>(
     tar_builder: &mut Builder<W>,
     source_path: &Path,
+    special_files: bool,
+    outputs: &OutputFiles,
 ) -> Result<(), RazeError> {
-    if source_path.is_dir() {
-        tar_builder.append_dir_all(
-            source_path
-                .file_name()
-                .unwrap_or_else(|| Path::new(".").as_os_str()),
-            source_path,
-        )?;
-    } else {
-        let mut file = File::open(source_path)?;
-        tar_builder.append_file(
-            source_path
-                .file_name()
-                .unwrap_or_else(|| Path::new(".").as_os_str()),
-            &mut file,
-        )?;
+    let name = Path::new(
+        source_path
+            .file_name()
+            .unwrap_or_else(|| Path::new(".").as_os_str()),
+    );
+    let mut pending = vec![source_path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let relative = path.strip_prefix(source_path).unwrap_or(Path::new(""));
+        let dest = if relative.as_os_str().is_empty() {
+            name.to_path_buf()
+        } else {
+            name.join(relative)
+        };
+        let metadata = fs::metadata(&path)?;
+        if outputs.contains(&metadata) {
+            info!("Leaving out '{}', which is being written", path.display());
+        } else if metadata.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+            tar_builder.append_dir(&dest, &path)?;
+        } else if metadata.is_file() {
+            tar_builder.append_file(&dest, &mut File::open(&path)?)?;
+        } else if !(special_files && append_special(tar_builder, &dest, &metadata)?) {
+            warn!("Skipping special file '{}'", path.display());
+        }
     }
     Ok(())
 }
+
+/// Appends the device node or FIFO described by `metadata` under `dest`.
+/// Returns `false` for sockets, which tar cannot store.
+#[cfg(unix)]
+fn append_special<W: Write>(
+    tar_builder: &mut Builder<W>,
+    dest: &Path,
+    metadata: &fs::Metadata,
+) -> Result<bool, RazeError> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    if metadata.file_type().is_socket() {
+        return Ok(false);
+    }
+    // `set_metadata` derives the entry type from the file type.
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(metadata);
+    header.set_size(0);
+    let rdev = metadata.rdev() as libc::dev_t;
+    header.set_device_major(libc::major(rdev) as u32)?;
+    header.set_device_minor(libc::minor(rdev) as u32)?;
+    tar_builder.append_data(&mut header, dest, std::io::empty())?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn append_special<W: Write>(
+    _tar_builder: &mut Builder<W>,
+    _dest: &Path,
+    _metadata: &fs::Metadata,
+) -> Result<bool, RazeError> {
+    Ok(false)
+}
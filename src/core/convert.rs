@@ -0,0 +1,346 @@
+//! # Conversion Module
+//!
+//! This module converts between Raze archives and ZIP files for tools that only
+//! understand the latter. Entries are streamed directly from one container into
+//! the other, so nothing is extracted to disk along the way. Paths, permission
+//! bits, modification times, directories and symbolic links are carried over.
+//!
+//! Conversion is enabled by the `zip` cargo feature. Encryption is only applied
+//! on the Raze side: an encrypted `.rz` file is decrypted while it is read with
+//! the same credentials that unpacking takes, and a password given when
+//! converting from ZIP encrypts the resulting archive.
+
+use crate::core::compress::{self, PackOptions};
+use crate::core::decompress;
+use crate::utils::atomic::{AtomicFile, OutputFiles};
+use crate::utils::errors::RazeError;
+use crate::utils::security::{self, Credentials};
+use log::{info, warn};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+use tar::{Archive, EntryType, Header};
+use zip::extra_fields::ExtraField;
+use zip::result::ZipError;
+use zip::write::{FullFileOptions, SimpleFileOptions};
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+/// Header ID of the Info-ZIP extended timestamp extra field.
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+/// The magic bytes that begin a ZIP file: a local file header, or the
+/// end-of-central-directory record of an empty archive.
+const ZIP_MAGIC: [&[u8; 4]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+/// Returns `true` if the file at `path` is a ZIP file.
+pub fn is_zip(path: impl AsRef<Path>) -> Result<bool, RazeError> {
+    let path = path.as_ref();
+    let mut file = File::open(path).map_err(|_| RazeError::NotFound(path.display().to_string()))?;
+    let mut magic = [0u8; 4];
+    Ok(file.read_exact(&mut magic).is_ok() && ZIP_MAGIC.contains(&&magic))
+}
+
+/// Converts `input` into the other container format, choosing the direction by
+/// the contents of `input`.
+///
+/// A ZIP file is converted into a Raze archive written as described by `options`;
+/// any other archive is converted into a ZIP file, using `options.password` and
+/// `options.key_file` to decrypt it if needed. Use [`rz_to_zip`] directly to
+/// convert an archive encrypted to recipients.
+pub fn convert(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &PackOptions,
+) -> Result<(), RazeError> {
+    let input = input.as_ref();
+    if is_zip(input)? {
+        zip_to_rz(input, output, options)
+    } else {
+        let credentials = Credentials {
+            password: options.password.clone(),
+            key_file: options.key_file.clone(),
+            identities: Vec::new(),
+        };
+        rz_to_zip(input, output, &credentials)
+    }
+}
+
+/// Streams the entries of a Raze archive into a new ZIP file, opening it with
+/// `credentials` if it is encrypted.
+///
+/// Regular files are compressed with Deflate. Hard links and special files,
+/// which ZIP cannot represent, are skipped with a warning.
+pub fn rz_to_zip(
+    archive: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    credentials: &Credentials,
+) -> Result<(), RazeError> {
+    let archive_path = archive.as_ref();
+    let output_path = output.as_ref();
+    let decoder = decompress::open_archive(archive_path, credentials)?;
+    let mut tar_archive = Archive::new(decoder);
+    let mut zip = ZipWriter::new(AtomicFile::create(output_path)?);
+
+    info!(
+        "Converting '{}' into ZIP file '{}'...",
+        archive_path.display(),
+        output_path.display()
+    );
+
+    let mut converted = 0usize;
+    for entry in tar_archive.entries()? {
+        let mut entry = entry.map_err(security::unwrap_crypto_error)?;
+        let path = entry.path()?.into_owned();
+        let name = zip_name(&path)?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let options = zip_options(
+            header.mode().unwrap_or(0o644) & 0o7777,
+            header.mtime().unwrap_or(0),
+            entry.size(),
+        )?;
+
+        match entry_type {
+            EntryType::Directory => zip.add_directory(name, options).map_err(zip_error)?,
+            EntryType::Regular | EntryType::Continuous => {
+                zip.start_file(name, options).map_err(zip_error)?;
+                io::copy(&mut entry, &mut zip).map_err(security::unwrap_crypto_error)?;
+            },
+            EntryType::Symlink => {
+                let target = entry.link_name()?.ok_or_else(|| {
+                    RazeError::ConversionError(format!(
+                        "Symbolic link '{}' has no target",
+                        path.display()
+                    ))
+                })?;
+                let target = target.to_str().ok_or_else(|| {
+                    RazeError::ConversionError(format!(
+                        "Link target of '{}' is not valid UTF-8",
+                        path.display()
+                    ))
+                })?;
+                zip.add_symlink(name, target, options).map_err(zip_error)?;
+            },
+            EntryType::XGlobalHeader => continue,
+            other => {
+                warn!(
+                    "Skipping '{}': {:?} entries cannot be stored in a ZIP file",
+                    path.display(),
+                    other
+                );
+                continue;
+            },
+        }
+        converted += 1;
+    }
+
+    decompress::finish_stream(tar_archive.into_inner())?;
+    zip.finish().map_err(zip_error)?.commit(false)?;
+    info!(
+        "Successfully converted {} entries into: {}",
+        converted,
+        output_path.display()
+    );
+    Ok(())
+}
+
+/// Streams the entries of a ZIP file into a new Raze archive written as
+/// described by `options`.
+///
+/// Entries whose names would escape the extraction directory are rejected.
+pub fn zip_to_rz(
+    zip_file: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &PackOptions,
+) -> Result<(), RazeError> {
+    let zip_path = zip_file.as_ref();
+    let output_path = output.as_ref();
+    let file =
+        File::open(zip_path).map_err(|_| RazeError::NotFound(zip_path.display().to_string()))?;
+    let mut zip = ZipArchive::new(file).map_err(zip_error)?;
+
+    info!(
+        "Converting ZIP file '{}' into '{}' ({}){}...",
+        zip_path.display(),
+        output_path.display(),
+        options.format,
+        if options.is_encrypted() {
+            " with encryption"
+        } else {
+            ""
+        }
+    );
+
+    let entries = zip.len();
+    let outputs = OutputFiles::default();
+    compress::create_archive(output_path, options, &outputs, |tar_builder| {
+        for index in 0..entries {
+            let mut file = zip.by_index(index).map_err(zip_error)?;
+            let path = file.enclosed_name().ok_or_else(|| {
+                RazeError::ConversionError(format!(
+                    "Unsafe path in ZIP file: '{}'",
+                    String::from_utf8_lossy(file.name_raw())
+                ))
+            })?;
+
+            let mut header = Header::new_gnu();
+            header.set_mtime(zip_mtime(&file));
+            if file.is_dir() {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(file.unix_mode().map_or(0o755, |mode| mode & 0o7777));
+                header.set_size(0);
+                tar_builder.append_data(&mut header, &path, io::empty())?;
+            } else if file.is_symlink() {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                header.set_entry_type(EntryType::Symlink);
+                header.set_mode(file.unix_mode().map_or(0o777, |mode| mode & 0o7777));
+                header.set_size(0);
+                tar_builder.append_link(&mut header, &path, target)?;
+            } else {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(file.unix_mode().map_or(0o644, |mode| mode & 0o7777));
+                header.set_size(file.size());
+                tar_builder.append_data(&mut header, &path, &mut file)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    info!(
+        "Successfully converted {} entries into: {}",
+        entries,
+        output_path.display()
+    );
+    Ok(())
+}
+
+/// Builds the ZIP options for an entry with the given permissions, mtime and size.
+///
+/// The DOS timestamp in the ZIP header only has two-second resolution, so the
+/// exact mtime is also stored in an extended timestamp extra field.
+fn zip_options(
+    mode: u32,
+    mtime: u64,
+    size: u64,
+) -> Result<FullFileOptions<'static, 'static>, RazeError> {
+    let mut options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(mode)
+        .large_file(size >= u64::from(u32::MAX))
+        .into_full_options();
+    if let Some(time) = dos_time(mtime) {
+        options = options.last_modified_time(time);
+    }
+    if let Ok(mtime) = u32::try_from(mtime) {
+        let mut field = vec![0x01];
+        field.extend_from_slice(&mtime.to_le_bytes());
+        options
+            .add_extra_field(EXTENDED_TIMESTAMP_ID, field, false)
+            .map_err(zip_error)?;
+    }
+    Ok(options)
+}
+
+/// Returns the modification time of a ZIP entry in seconds since the Unix epoch.
+fn zip_mtime<R: Read>(file: &zip::read::ZipFile<'_, R>) -> u64 {
+    let extended = file.extra_data_fields().find_map(|field| match field {
+        ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+        _ => None,
+    });
+    match (extended, file.last_modified()) {
+        (Some(mtime), _) => u64::from(mtime),
+        (None, Some(time)) => {
+            let days = days_from_civil(
+                i64::from(time.year()),
+                i64::from(time.month()),
+                i64::from(time.day()),
+            );
+            let seconds = days * 86_400
+                + i64::from(time.hour()) * 3_600
+                + i64::from(time.minute()) * 60
+                + i64::from(time.second());
+            u64::try_from(seconds).unwrap_or(0)
+        },
+        (None, None) => 0,
+    }
+}
+
+/// Converts a Unix timestamp into a DOS timestamp, treating it as UTC.
+/// Returns `None` if the date is outside the 1980-2107 range DOS can represent.
+fn dos_time(mtime: u64) -> Option<DateTime> {
+    let mtime = i64::try_from(mtime).ok()?;
+    let (year, month, day) = civil_from_days(mtime.div_euclid(86_400));
+    let seconds = mtime.rem_euclid(86_400);
+    DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (seconds / 3_600) as u8,
+        (seconds / 60 % 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .ok()
+}
+
+/// Returns the number of days between 1970-01-01 and the given proleptic
+/// Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the proleptic Gregorian `(year, month, day)` that lies `days` days
+/// after 1970-01-01. This is the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts an archive path into a relative ZIP entry name with `/` separators.
+fn zip_name(path: &Path) -> Result<String, RazeError> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().ok_or_else(|| {
+                RazeError::ConversionError(format!(
+                    "Path '{}' is not valid UTF-8 and cannot be stored in a ZIP file",
+                    path.display()
+                ))
+            })?),
+            Component::ParentDir => {
+                return Err(RazeError::ConversionError(format!(
+                    "Unsafe path in archive: '{}'",
+                    path.display()
+                )))
+            },
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {},
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Maps a ZIP library error onto the matching [`RazeError`].
+fn zip_error(error: ZipError) -> RazeError {
+    match error {
+        ZipError::Io(error) => RazeError::Io(error),
+        other => RazeError::ConversionError(other.to_string()),
+    }
+}
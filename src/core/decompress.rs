@@ -3,86 +3,423 @@
 //! This module provides the core functionality for extracting contents from
 //! compressed `.rz` archives. It utilizes the `zstd` crate to decompress
 //! the archived data and the `tar` crate to extract the individual files
-//! and directories from the resulting stream.
+//! and directories from the resulting stream. Standard `.tar.gz`, `.tar.xz`,
+//! `.tar.bz2` and plain `.tar` archives are recognized by their magic bytes
+//! and decoded accordingly.
 //!
 //! The primary function, `unpack`, manages the entire decompression process,
 //! including archive validation, directory creation, and error handling.
 
+use crate::core::extract::{
+    self, ByteCount, CountingReader, ExtractLimits, ExtractPermissions, ExtractPolicy,
+};
+use crate::core::format::{self, ArchiveFormat};
+use crate::core::signature::{self, VerifyingKey};
+use crate::core::trailer::{self, Window};
+use crate::core::volume::{self, VolumeReader};
 use crate::utils::errors::RazeError;
-use crate::utils::security;
-use log::info;
-use std::fs;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use crate::utils::security::{self, Credentials, DecryptReader, KeySlots, SlotKind};
+use crate::utils::space::{self, FreeSpace};
+use log::{debug, info};
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use tar::Archive;
-use zstd::Decoder;
+
+/// Metadata describing a single archive entry, as returned by [`list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The path of the entry inside the archive.
+    pub path: PathBuf,
+    /// The uncompressed size of the entry in bytes.
+    pub size: u64,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+}
+
+/// Options controlling how [`unpack_with_options`], [`list_with_options`] and
+/// [`test_with_options`] read an archive.
+#[derive(Clone, Default)]
+pub struct UnpackOptions {
+    /// The secrets used to open an encrypted archive.
+    pub credentials: Credentials,
+    /// Public keys that the archive must be signed with. If any are given, an
+    /// archive without a valid signature by one of them is refused before
+    /// anything is read from it.
+    pub trusted_keys: Vec<VerifyingKey>,
+    /// Which potentially dangerous entries extraction accepts. The default
+    /// refuses entries that would be written outside the destination.
+    pub policy: ExtractPolicy,
+    /// How far the archive may expand during extraction. The default suits
+    /// archives from untrusted sources.
+    pub limits: ExtractLimits,
+    /// How extraction applies the stored modes and owners. The default clears
+    /// the setuid, setgid and sticky bits and does not restore owners.
+    pub permissions: ExtractPermissions,
+    /// Extracts into a hidden staging directory inside the destination and
+    /// moves the entries into place only once the whole archive has been
+    /// read and verified, so that a failed extraction leaves nothing behind.
+    pub atomic: bool,
+    /// Reads the entry headers of the archive once before extracting it, to
+    /// make sure that its entries fit into the free space at the destination.
+    /// Extraction checks each entry again before writing it, in case the
+    /// free space shrinks in the meantime.
+    pub check_space: bool,
+}
+
+impl UnpackOptions {
+    /// Returns options that open an encrypted archive with `password`, if given.
+    fn with_password(password: Option<&str>) -> Self {
+        UnpackOptions {
+            credentials: password.map(Credentials::from_password).unwrap_or_default(),
+            ..UnpackOptions::default()
+        }
+    }
+}
 
 /// Extracts a `.rz` archive into a specified destination directory.
 /// Optionally decrypts the archive if a password is provided.
+///
+/// If `archive_path` is the first volume of a split archive, the remaining
+/// volumes are located automatically and read as one continuous stream.
 pub fn unpack(
     archive_path: impl AsRef<Path>,
     destination: impl AsRef<Path>,
     password: Option<&str>,
+) -> Result<(), RazeError> {
+    unpack_with_options(
+        archive_path,
+        destination,
+        &UnpackOptions::with_password(password),
+    )
+}
+
+/// Extracts a `.rz` archive into a specified destination directory as described by `options`.
+///
+/// Entries that `options.policy` does not accept stop the extraction with an
+/// error naming the entry, such as [`RazeError::UnsafePath`]. An archive that
+/// exceeds `options.limits` stops it with [`RazeError::LimitExceeded`].
+///
+/// With `options.atomic`, the destination is left untouched unless the whole
+/// archive is extracted successfully. With `options.check_space`, an archive
+/// whose entries do not fit into the free space at the destination is refused
+/// with [`RazeError::InsufficientSpace`] before anything is extracted.
+pub fn unpack_with_options(
+    archive_path: impl AsRef<Path>,
+    destination: impl AsRef<Path>,
+    options: &UnpackOptions,
 ) -> Result<(), RazeError> {
     let archive_path = archive_path.as_ref();
+    let destination_path = destination.as_ref();
+    let mut file = open_verified(archive_path, options)?;
+    let mut free = None;
+    if options.check_space {
+        // The scan reads the same handle that was verified, then hands it on.
+        let shared = SharedSource(Rc::new(RefCell::new(file)));
+        free = check_free_space(Box::new(shared.clone()), destination_path, options)?;
+        file = Box::new(shared);
+    }
+    let stored = ByteCount::default();
+    let decoder = open_counted(file, &options.credentials, &stored)?;
+
+    info!(
+        "Extracting '{}' to '{}'...",
+        archive_path.display(),
+        destination_path.display()
+    );
+    // The staging directory lives inside the destination, so that moving the
+    // entries into place is a rename on the same filesystem.
+    let staging = if options.atomic {
+        fs::create_dir_all(destination_path)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".raze-staging-")
+            .tempdir_in(destination_path)?;
+        debug!("Staging extraction in '{}'", staging.path().display());
+        Some(staging)
+    } else {
+        None
+    };
+    extract::unpack(
+        decoder,
+        &stored,
+        staging.as_ref().map_or(destination_path, |s| s.path()),
+        &options.policy,
+        &options.limits,
+        &options.permissions,
+        free,
+    )?;
+    if let Some(staging) = staging {
+        extract::move_entries(staging.path(), destination_path)?;
+        staging.close()?;
+    }
+
+    info!(
+        "Successfully extracted archive to: {}",
+        destination_path.display()
+    );
+    Ok(())
+}
+
+/// Lists the entries of a `.rz` archive without extracting them.
+pub fn list(
+    archive_path: impl AsRef<Path>,
+    password: Option<&str>,
+) -> Result<Vec<ArchiveEntry>, RazeError> {
+    list_with_options(archive_path, &UnpackOptions::with_password(password))
+}
+
+/// Lists the entries of a `.rz` archive, opened as described by `options`,
+/// without extracting them.
+pub fn list_with_options(
+    archive_path: impl AsRef<Path>,
+    options: &UnpackOptions,
+) -> Result<Vec<ArchiveEntry>, RazeError> {
+    let file = open_verified(archive_path.as_ref(), options)?;
+    let decoder = open_counted(file, &options.credentials, &ByteCount::default())?;
+    let mut tar_archive = Archive::new(decoder);
+
+    let mut entries = Vec::new();
+    for entry in tar_archive.entries()? {
+        let entry = entry.map_err(security::unwrap_crypto_error)?;
+        entries.push(ArchiveEntry {
+            path: entry.path()?.into_owned(),
+            size: entry.size(),
+            is_dir: entry.header().entry_type().is_dir(),
+        });
+    }
+    finish_stream(tar_archive.into_inner())?;
+    Ok(entries)
+}
+
+/// Verifies that a `.rz` archive can be fully decrypted and decompressed,
+/// reading every entry without writing anything to disk.
+pub fn test(archive_path: impl AsRef<Path>, password: Option<&str>) -> Result<(), RazeError> {
+    test_with_options(archive_path, &UnpackOptions::with_password(password))
+}
+
+/// Verifies that a `.rz` archive, opened as described by `options`, can be
+/// fully decrypted and decompressed.
+pub fn test_with_options(
+    archive_path: impl AsRef<Path>,
+    options: &UnpackOptions,
+) -> Result<(), RazeError> {
+    let archive_path = archive_path.as_ref();
+    let file = open_verified(archive_path, options)?;
+    let decoder = open_counted(file, &options.credentials, &ByteCount::default())?;
+    let mut tar_archive = Archive::new(decoder);
+
+    for entry in tar_archive.entries()? {
+        let mut entry = entry.map_err(security::unwrap_crypto_error)?;
+        io::copy(&mut entry, &mut io::sink()).map_err(security::unwrap_crypto_error)?;
+    }
+    finish_stream(tar_archive.into_inner())?;
+    info!("Archive '{}' is intact.", archive_path.display());
+    Ok(())
+}
+
+/// Returns `true` if the archive at `archive_path` is encrypted and needs a
+/// password or an identity.
+pub fn is_encrypted(archive_path: impl AsRef<Path>) -> Result<bool, RazeError> {
+    let mut source = open_source(archive_path.as_ref())?;
+    let mut magic = [0u8; 4];
+    Ok(source.read_exact(&mut magic).is_ok() && magic == *b"RZCR")
+}
+
+/// Returns what each key slot of the archive at `archive_path` takes to open,
+/// or `None` if the archive is not encrypted.
+pub fn slot_kinds(archive_path: impl AsRef<Path>) -> Result<Option<Vec<SlotKind>>, RazeError> {
+    if !is_encrypted(&archive_path)? {
+        return Ok(None);
+    }
+    let mut source = open_source(archive_path.as_ref())?;
+    KeySlots::slot_kinds(&mut source).map(Some)
+}
+
+/// Opens an archive file like [`open_file`] and refuses it unless it is signed
+/// by one of the trusted keys in `options`. Without trusted keys, signatures
+/// are not checked.
+///
+/// The signature is verified through the returned file, so reading the
+/// archive from it reads the same file that was verified, even if another one
+/// has since been moved to `archive_path`.
+fn open_verified(
+    archive_path: &Path,
+    options: &UnpackOptions,
+) -> Result<Box<dyn ArchiveSource>, RazeError> {
+    let mut file = open_file(archive_path)?;
+    if options.trusted_keys.is_empty() {
+        return Ok(file);
+    }
+    let key = signature::verify_file(&mut file, archive_path, &options.trusted_keys)?;
+    info!(
+        "Archive '{}' has a valid signature by {}",
+        archive_path.display(),
+        key
+    );
+    Ok(file)
+}
+
+/// Fails with [`RazeError::InsufficientSpace`] if the entries of the archive
+/// read from `file` need more space than is free at `destination`, and
+/// otherwise returns that free space, or `None` where it cannot be queried.
+///
+/// Archives carry no index of their contents, so the entry headers are read
+/// from the archive without extracting anything. The scan stops as soon as
+/// the entries read so far no longer fit.
+fn check_free_space(
+    file: Box<dyn ArchiveSource>,
+    destination: &Path,
+    options: &UnpackOptions,
+) -> Result<Option<FreeSpace>, RazeError> {
+    let Some(free) = space::free_space(destination)? else {
+        return Ok(None);
+    };
+    let decoder = open_counted(file, &options.credentials, &ByteCount::default())?;
+    let mut tar_archive = Archive::new(decoder);
+    let mut needed = 0u64;
+    for entry in tar_archive.entries()? {
+        let entry = entry.map_err(security::unwrap_crypto_error)?;
+        needed = needed.saturating_add(extract::allocated(&entry, &free));
+        free.check(destination, needed, "at least")?;
+    }
+    debug!(
+        "'{}' needs {} bytes, {} bytes are free",
+        destination.display(),
+        needed,
+        free.available
+    );
+    Ok(Some(free))
+}
+
+/// An archive file read by several readers in turn, such as the free-space
+/// scan and the extraction that follows it.
+#[derive(Clone)]
+struct SharedSource(Rc<RefCell<Box<dyn ArchiveSource>>>);
+
+impl Read for SharedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Seek for SharedSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.borrow_mut().seek(pos)
+    }
+}
+
+/// A seekable byte source holding the raw archive stream.
+pub(crate) trait ArchiveSource: Read + Seek {}
+
+impl<T: Read + Seek> ArchiveSource for T {}
+
+/// Opens the raw archive stream, joining split volumes when necessary and
+/// excluding any self-extraction stub and trailing records such as a recovery record.
+fn open_source(archive_path: &Path) -> Result<Box<dyn ArchiveSource>, RazeError> {
+    payload(open_file(archive_path)?)
+}
+
+/// Restricts an archive file opened with [`open_file`] to its archive stream.
+fn payload(mut file: Box<dyn ArchiveSource>) -> Result<Box<dyn ArchiveSource>, RazeError> {
+    let (start, len) = trailer::payload_range(&mut file)?;
+    Ok(Box::new(Window::new(file, start, len)?))
+}
+
+/// Opens an archive file as a whole, including any self-extraction stub and
+/// trailing records, joining split volumes when necessary.
+pub(crate) fn open_file(archive_path: &Path) -> Result<Box<dyn ArchiveSource>, RazeError> {
     if !archive_path.exists() {
         return Err(RazeError::NotFound(archive_path.display().to_string()));
     }
+    if volume::is_volume(archive_path)? {
+        let volumes = VolumeReader::open(archive_path)?;
+        info!(
+            "Reading split archive '{}' ({} volumes)",
+            archive_path.display(),
+            volumes.volume_count()
+        );
+        Ok(Box::new(volumes))
+    } else {
+        Ok(Box::new(File::open(archive_path)?))
+    }
+}
 
-    let destination_path = destination.as_ref();
-    fs::create_dir_all(destination_path)?;
+/// Opens an archive and returns a reader over its decrypted, decompressed tar stream.
+#[cfg(feature = "zip")]
+pub(crate) fn open_archive(
+    archive_path: &Path,
+    credentials: &Credentials,
+) -> Result<Box<dyn Read>, RazeError> {
+    open_counted(open_file(archive_path)?, credentials, &ByteCount::default())
+}
 
-    let mut archive_file = File::open(archive_path)?;
+/// Returns a reader over the decrypted, decompressed tar stream of an archive
+/// file opened with [`open_file`], counting the bytes read from the stored
+/// archive stream in `stored`.
+fn open_counted(
+    file: Box<dyn ArchiveSource>,
+    credentials: &Credentials,
+    stored: &ByteCount,
+) -> Result<Box<dyn Read>, RazeError> {
+    let mut source = payload(file)?;
 
     // Check if the file is encrypted by reading the magic header
     let mut magic = [0u8; 4];
-    let is_encrypted = if archive_file.read_exact(&mut magic).is_ok() {
+    let is_encrypted = if source.read_exact(&mut magic).is_ok() {
         magic == *b"RZCR"
     } else {
         false
     };
-    archive_file.seek(SeekFrom::Start(0))?;
+    source.seek(SeekFrom::Start(0))?;
+    let source = CountingReader::new(source, stored.clone());
 
     if is_encrypted {
-        let pwd = password.ok_or_else(|| {
-            RazeError::CryptoError("Archive is encrypted but no password was provided".to_string())
-        })?;
-
-        // Decrypt to a temporary file
-        let mut temp_file = tempfile::tempfile()?;
-        security::decrypt_stream(&archive_file, &mut temp_file, pwd)?;
-
-        temp_file.seek(SeekFrom::Start(0))?;
-        let decoder =
-            Decoder::new(temp_file).map_err(|e| RazeError::DecompressionError(e.to_string()))?;
-        let mut tar_archive = Archive::new(decoder);
+        if credentials.is_empty() {
+            return Err(RazeError::CryptoError(
+                "Archive is encrypted but no password, key file or identity was provided"
+                    .to_string(),
+            ));
+        }
 
-        info!(
-            "Extracting encrypted archive '{}' to '{}'...",
-            archive_path.display(),
-            destination_path.display()
-        );
-        tar_archive.unpack(destination_path)?;
+        // Decrypt on the fly, so plaintext never reaches the disk
+        let decryptor = DecryptReader::with_credentials(source, credentials)?;
+        decode(decryptor)
     } else {
-        if password.is_some() {
+        if credentials.password.is_some() {
             info!("Warning: Password provided but archive does not appear to be encrypted.");
         }
-        let decoder =
-            Decoder::new(archive_file).map_err(|e| RazeError::DecompressionError(e.to_string()))?;
-        let mut tar_archive = Archive::new(decoder);
-
-        info!(
-            "Extracting '{}' to '{}'...",
-            archive_path.display(),
-            destination_path.display()
-        );
-        tar_archive.unpack(destination_path)?;
+        decode(source)
     }
+}
 
-    info!(
-        "Successfully extracted archive to: {}",
-        destination_path.display()
-    );
+/// Identifies the container format of `reader` by its magic bytes and wraps
+/// it in the matching decoder.
+fn decode<R: Read + 'static>(mut reader: R) -> Result<Box<dyn Read>, RazeError> {
+    let mut header = Vec::with_capacity(format::DETECT_LEN);
+    reader
+        .by_ref()
+        .take(format::DETECT_LEN as u64)
+        .read_to_end(&mut header)
+        .map_err(security::unwrap_crypto_error)?;
+
+    let format = ArchiveFormat::detect(&header).ok_or_else(|| {
+        RazeError::DecompressionError(
+            "Unrecognized archive format (expected tar.zst, tar.gz, tar.xz, tar.bz2 or tar)"
+                .to_string(),
+        )
+    })?;
+    debug!("Detected {} archive", format);
+
+    // Put the inspected bytes back in front of the rest of the stream.
+    format::decoder(format, Cursor::new(header).chain(reader))
+}
+
+/// Reads whatever follows the end of the tar archive, such as padding, so that
+/// the rest of the stream is decompressed and, for encrypted archives,
+/// authenticated up to its final chunk. This is what detects truncation.
+pub(crate) fn finish_stream<R: Read>(mut reader: R) -> Result<(), RazeError> {
+    io::copy(&mut reader, &mut io::sink()).map_err(security::unwrap_crypto_error)?;
     Ok(())
 }
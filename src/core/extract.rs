@@ -0,0 +1,725 @@
+//! # Extraction Module
+//!
+//! This module writes the entries of a tar stream to disk under an
+//! [`ExtractPolicy`]. Archives from untrusted sources can contain entries that
+//! try to reach outside the destination directory: absolute paths, `..`
+//! components, links pointing elsewhere, or files placed beneath a symbolic
+//! link that an earlier entry created. Device nodes and FIFOs are dangerous
+//! too when extracted as root. By default all of these are refused
+//! before anything is written for the entry, each with its own [`RazeError`]
+//! variant naming the offending entry.
+//!
+//! Extraction is also bounded by [`ExtractLimits`], so that a decompression
+//! bomb expanding to terabytes or holding millions of entries is stopped with
+//! [`RazeError::LimitExceeded`] instead of filling the disk.
+//!
+//! The modes and owners stored in the archive are applied as described by
+//! [`ExtractPermissions`], which by default clears the setuid, setgid and
+//! sticky bits and leaves entries owned by the extracting user.
+
+use crate::core::beneath::Beneath;
+use crate::core::decompress;
+use crate::utils::errors::RazeError;
+use crate::utils::security;
+use crate::utils::space::FreeSpace;
+use log::warn;
+use std::cell::Cell;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use tar::{Archive, Entry, EntryType, Header};
+
+/// The most symbolic links followed while resolving a single link target,
+/// matching the limit of Linux path resolution.
+const MAX_LINK_HOPS: usize = 40;
+
+/// The size of the tar stream below which its compression ratio is not
+/// checked, so that small archives of repetitive data are not taken for bombs.
+const RATIO_GRACE: u64 = 1 << 20;
+
+/// Which kinds of potentially dangerous entries extraction accepts.
+///
+/// The default refuses all of them. [`ExtractPolicy::permissive`] accepts them
+/// all and leaves the entries to the `tar` crate's own checks, which is only
+/// appropriate for archives from trusted sources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractPolicy {
+    /// Accepts entries with absolute paths or `..` components.
+    pub allow_unsafe_paths: bool,
+    /// Accepts symbolic and hard links whose targets lie outside the destination.
+    pub allow_escaping_links: bool,
+    /// Accepts entries placed beneath a symbolic link, such as one created by
+    /// an earlier entry.
+    pub allow_symlink_traversal: bool,
+    /// Accepts block and character devices and FIFOs, which are then created
+    /// as such on Unix.
+    pub allow_special_files: bool,
+}
+
+impl ExtractPolicy {
+    /// Returns a policy that accepts every entry the `tar` crate accepts.
+    pub fn permissive() -> Self {
+        ExtractPolicy {
+            allow_unsafe_paths: true,
+            allow_escaping_links: true,
+            allow_symlink_traversal: true,
+            allow_special_files: true,
+        }
+    }
+}
+
+/// How extraction applies the modes and owners stored in the archive.
+///
+/// The default clears the setuid, setgid and sticky bits, keeps the remaining
+/// permission bits as stored and leaves entries owned by the extracting user,
+/// even when extracting as root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractPermissions {
+    /// Keeps the setuid, setgid and sticky bits of the stored modes.
+    pub preserve: bool,
+    /// Permission bits cleared from every stored mode, like a process umask.
+    pub umask: u32,
+    /// Gives entries the owner and group stored in the archive, which usually
+    /// requires root. Fails the extraction if they cannot be applied.
+    pub same_owner: bool,
+}
+
+impl ExtractPermissions {
+    /// Returns the mode stored in `header` without the bits these permissions clear.
+    pub(crate) fn mode(&self, header: &Header) -> io::Result<u32> {
+        let kept = if self.preserve { 0o7777 } else { 0o777 };
+        Ok(header.mode()? & kept & !self.umask)
+    }
+}
+
+/// How far an archive may expand during extraction.
+///
+/// A limit of `None` is not enforced. The default is meant for archives from
+/// untrusted sources and stops at 64 GiB in total, 16 GiB per file, one
+/// million entries, a path depth of 128 and a tar stream 1000 times larger
+/// than the stored archive. [`ExtractLimits::unlimited`] enforces none of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractLimits {
+    /// The most bytes extracted in total, summed over all entries.
+    pub max_total_size: Option<u64>,
+    /// The most entries of any kind, directories and links included.
+    pub max_entries: Option<u64>,
+    /// The largest size of a single entry in bytes.
+    pub max_file_size: Option<u64>,
+    /// The most times the decompressed tar stream may exceed the stored
+    /// archive stream it was read from.
+    pub max_ratio: Option<u64>,
+    /// The most components in the path of an entry.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_total_size: Some(64 << 30),
+            max_entries: Some(1_000_000),
+            max_file_size: Some(16 << 30),
+            max_ratio: Some(1000),
+            max_depth: Some(128),
+        }
+    }
+}
+
+impl ExtractLimits {
+    /// Returns limits that accept archives of any size.
+    pub fn unlimited() -> Self {
+        ExtractLimits {
+            max_total_size: None,
+            max_entries: None,
+            max_file_size: None,
+            max_ratio: None,
+            max_depth: None,
+        }
+    }
+}
+
+/// A byte count shared between a reader and the code checking on it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ByteCount(Rc<Cell<u64>>);
+
+impl ByteCount {
+    /// Returns the number of bytes counted so far.
+    pub(crate) fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn add(&self, n: usize) {
+        self.0.set(self.0.get().saturating_add(n as u64));
+    }
+}
+
+/// A reader that adds the bytes read through it to a [`ByteCount`].
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: ByteCount,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// Wraps `inner`, counting its bytes in `count`.
+    pub(crate) fn new(inner: R, count: ByteCount) -> Self {
+        CountingReader { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.add(n);
+        Ok(n)
+    }
+}
+
+/// The sizes of the stored and the decompressed stream, compared against
+/// [`ExtractLimits::max_ratio`].
+#[derive(Clone)]
+struct Expansion {
+    stored: ByteCount,
+    expanded: ByteCount,
+    max_ratio: Option<u64>,
+}
+
+impl Expansion {
+    fn check(&self) -> Result<(), RazeError> {
+        let Some(max) = self.max_ratio else {
+            return Ok(());
+        };
+        let expanded = self.expanded.get();
+        if expanded > RATIO_GRACE && expanded > self.stored.get().saturating_mul(max) {
+            return Err(RazeError::LimitExceeded(format!(
+                "the archive expands to more than {} times its compressed size",
+                max
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A reader over the decompressed tar stream that fails as soon as the
+/// stream outgrows the compression ratio allowed by an [`Expansion`].
+struct RatioGuard<R> {
+    inner: R,
+    expansion: Expansion,
+}
+
+impl<R: Read> Read for RatioGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.expansion.expanded.add(n);
+        self.expansion.check().map_err(io::Error::other)?;
+        Ok(n)
+    }
+}
+
+/// The entries extracted so far, checked against [`ExtractLimits`] and the
+/// free space at the destination before anything is written for the next one.
+#[derive(Default)]
+struct Tally {
+    entries: u64,
+    total_size: u64,
+    allocated: u64,
+}
+
+impl Tally {
+    /// Counts the space that `entry` takes up at `target`, refusing it if it
+    /// no longer fits into the free space there.
+    fn reserve<R: Read>(
+        &mut self,
+        entry: &Entry<'_, R>,
+        target: &Target<'_>,
+    ) -> Result<(), RazeError> {
+        let Some(free) = target.free else {
+            return Ok(());
+        };
+        self.allocated = self.allocated.saturating_add(allocated(entry, &free));
+        free.check(target.destination, self.allocated, "at least")
+    }
+
+    /// Counts `entry`, refusing it if it exceeds `limits`.
+    fn add<R: Read>(
+        &mut self,
+        entry: &Entry<'_, R>,
+        limits: &ExtractLimits,
+    ) -> Result<(), RazeError> {
+        let path = entry.path()?;
+        let size = entry.size();
+        self.entries += 1;
+        self.total_size = self.total_size.saturating_add(size);
+
+        let exceeded = |message: String| Err(RazeError::LimitExceeded(message));
+        if let Some(max) = limits.max_entries.filter(|&max| self.entries > max) {
+            return exceeded(format!("the archive holds more than {} entries", max));
+        }
+        let depth = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count();
+        if let Some(max) = limits.max_depth.filter(|&max| depth > max) {
+            return exceeded(format!(
+                "'{}' is {} levels deep, more than the limit of {}",
+                path.display(),
+                depth,
+                max
+            ));
+        }
+        if let Some(max) = limits.max_file_size.filter(|&max| size > max) {
+            return exceeded(format!(
+                "'{}' is {} bytes, more than the limit of {} bytes per file",
+                path.display(),
+                size,
+                max
+            ));
+        }
+        if let Some(max) = limits.max_total_size.filter(|&max| self.total_size > max) {
+            return exceeded(format!(
+                "extracting '{}' would write more than {} bytes in total",
+                path.display(),
+                max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the space that `entry` takes up once extracted onto a filesystem
+/// with `free` space, going by the size in its header.
+pub(crate) fn allocated<R: Read>(entry: &Entry<'_, R>, free: &FreeSpace) -> u64 {
+    let entry_type = entry.header().entry_type();
+    let len = if entry_type.is_file() || entry_type.is_contiguous() {
+        entry.size()
+    } else {
+        0
+    };
+    // Count at least one block per entry, for directories and small files alike.
+    free.allocated(len.max(1))
+}
+
+/// Extracts every entry of the tar stream read from `reader` into
+/// `destination` under `policy` and `limits`, applying modes and owners as
+/// `permissions` describes, then reads the rest of the stream as
+/// [`decompress::finish_stream`] does.
+///
+/// `stored` counts the bytes read from the stored archive stream that
+/// `reader` decompresses, and is what the compression ratio is measured
+/// against. Every other limit is checked from the entry headers before
+/// anything is written for the entry, as is the `free` space at the
+/// destination, if given.
+///
+/// Directories are created last, deepest first, as `tar::Archive::unpack`
+/// does, so that restrictive directory permissions cannot get in the way of
+/// their contents.
+///
+/// Unless `policy` allows symbolic link traversal or escaping links, entries
+/// are created through [`Beneath`] where the kernel supports it, so that
+/// links swapped in by others while extracting cannot redirect them.
+pub(crate) fn unpack<R: Read>(
+    reader: R,
+    stored: &ByteCount,
+    destination: &Path,
+    policy: &ExtractPolicy,
+    limits: &ExtractLimits,
+    permissions: &ExtractPermissions,
+    free: Option<FreeSpace>,
+) -> Result<(), RazeError> {
+    fs::create_dir_all(destination)?;
+    let destination = fs::canonicalize(destination)?;
+    let beneath = if policy.allow_symlink_traversal || policy.allow_escaping_links {
+        None
+    } else {
+        Beneath::open(&destination)?
+    };
+
+    let expansion = Expansion {
+        stored: stored.clone(),
+        expanded: ByteCount::default(),
+        max_ratio: limits.max_ratio,
+    };
+    let mut archive = Archive::new(RatioGuard {
+        inner: reader,
+        expansion: expansion.clone(),
+    });
+    archive.set_preserve_permissions(permissions.preserve);
+    archive.set_mask(permissions.umask);
+    archive.set_preserve_ownerships(permissions.same_owner);
+    let target = Target {
+        destination: &destination,
+        beneath: beneath.as_ref(),
+        free,
+    };
+    let result = unpack_entries(&mut archive, &target, policy, limits, permissions)
+        .and_then(|()| decompress::finish_stream(archive.into_inner()));
+    // A ratio violation inside `tar` reaches us wrapped in its own error.
+    expansion.check()?;
+    result
+}
+
+/// Where extraction writes to.
+#[derive(Clone, Copy)]
+struct Target<'a> {
+    /// The canonical destination directory.
+    destination: &'a Path,
+    /// A handle for the destination to create entries beneath, if available.
+    beneath: Option<&'a Beneath>,
+    /// The free space at the destination, if it is to be checked.
+    free: Option<FreeSpace>,
+}
+
+/// Extracts every entry of `archive` into `target`.
+fn unpack_entries<R: Read>(
+    archive: &mut Archive<R>,
+    target: &Target<'_>,
+    policy: &ExtractPolicy,
+    limits: &ExtractLimits,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    let mut tally = Tally::default();
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry.map_err(security::unwrap_crypto_error)?;
+        let path = entry_path(&entry, policy)?;
+        tally.reserve(&entry, target)?;
+        tally.add(&entry, limits)?;
+        if entry.header().entry_type().is_dir() {
+            directories.push((path, entry));
+        } else {
+            unpack_entry(&mut entry, path.as_deref(), target, policy, permissions)?;
+        }
+    }
+
+    directories.sort_by(|a, b| b.1.path_bytes().cmp(&a.1.path_bytes()));
+    for (path, mut entry) in directories {
+        unpack_entry(&mut entry, path.as_deref(), target, policy, permissions)?;
+    }
+    Ok(())
+}
+
+/// Returns the path of `entry` relative to the destination, refusing absolute
+/// paths and `..` components unless `policy` allows them. `None` means that
+/// the path is unsafe but allowed, in which case the `tar` crate decides how
+/// to treat it.
+fn entry_path<R: Read>(
+    entry: &Entry<'_, R>,
+    policy: &ExtractPolicy,
+) -> Result<Option<PathBuf>, RazeError> {
+    let path = entry.path()?;
+    match relative_path(&path) {
+        Some(relative) => Ok(Some(relative)),
+        None if policy.allow_unsafe_paths => Ok(None),
+        None => Err(RazeError::UnsafePath(path.display().to_string())),
+    }
+}
+
+/// An entry moved by [`move_entries`], recorded so that the move can be undone.
+struct Move {
+    /// Where the entry was moved from, or `None` if moving it failed after the
+    /// file it was to replace had been set aside.
+    source: Option<PathBuf>,
+    /// Where the entry was moved to.
+    target: PathBuf,
+    /// Where the file that the entry replaced was set aside, if any.
+    replaced: Option<PathBuf>,
+}
+
+/// Moves every entry of the directory `from` into the directory `to`,
+/// merging it into directories that already exist there.
+///
+/// Existing files are replaced. Existing symbolic links are never followed:
+/// a directory cannot be merged into one, and the move fails instead.
+///
+/// The move is all or nothing. Replaced files are first set aside in a hidden
+/// directory inside `to`, and if an entry cannot be moved, the entries moved
+/// so far are moved back into `from` and the replaced files are put back
+/// before the error is returned. Should undoing fail as well, the set-aside
+/// files are kept and a warning names what could not be restored.
+pub(crate) fn move_entries(from: &Path, to: &Path) -> Result<(), RazeError> {
+    let replaced = tempfile::Builder::new()
+        .prefix(".raze-replaced-")
+        .tempdir_in(to)?;
+    let mut moves = Vec::new();
+    let Err(error) = move_into(from, to, replaced.path(), &mut moves) else {
+        replaced.close()?;
+        return Ok(());
+    };
+
+    let mut restored = true;
+    for done in moves.into_iter().rev() {
+        let undone = done
+            .source
+            .map_or(Ok(()), |source| fs::rename(&done.target, source))
+            .and_then(|()| {
+                done.replaced
+                    .map_or(Ok(()), |r| fs::rename(r, &done.target))
+            });
+        if let Err(e) = undone {
+            warn!(
+                "Could not restore '{}' after the failed extraction: {}",
+                done.target.display(),
+                e
+            );
+            restored = false;
+        }
+    }
+    if !restored {
+        let kept = replaced.keep();
+        warn!("Replaced files are kept in '{}'", kept.display());
+    }
+    Err(error)
+}
+
+/// Moves the entries of `from` into `to` for [`move_entries`], setting aside
+/// replaced files in `replaced` and recording every move in `moves`.
+fn move_into(
+    from: &Path,
+    to: &Path,
+    replaced: &Path,
+    moves: &mut Vec<Move>,
+) -> Result<(), RazeError> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        let existing = fs::symlink_metadata(&target).ok();
+        if is_dir && existing.as_ref().is_some_and(|m| m.is_dir()) {
+            move_into(&source, &target, replaced, moves)?;
+            continue;
+        }
+
+        // A file replacing one that exists is moved in only once the old one
+        // is out of the way, so that it can be put back.
+        let set_aside = match existing {
+            Some(metadata) if !is_dir && !metadata.is_dir() => {
+                let aside = replaced.join(moves.len().to_string());
+                fs::rename(&target, &aside)?;
+                Some(aside)
+            },
+            _ => None,
+        };
+        let moved = fs::rename(&source, &target);
+        moves.push(Move {
+            source: moved.is_ok().then_some(source),
+            target,
+            replaced: set_aside,
+        });
+        moved?;
+    }
+    Ok(())
+}
+
+/// Returns `path` without `.` components, or `None` if it is absolute or
+/// contains `..`.
+fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {},
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Checks `entry`, found at the relative `path`, against `policy` and writes
+/// it into `target`.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn unpack_entry<R: Read>(
+    entry: &mut Entry<'_, R>,
+    path: Option<&Path>,
+    target: &Target<'_>,
+    policy: &ExtractPolicy,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    let destination = target.destination;
+    let special = is_special(entry.header().entry_type());
+    if special && !policy.allow_special_files {
+        return Err(RazeError::SpecialFile(entry.path()?.display().to_string()));
+    }
+    if let Some(path) = path {
+        if !policy.allow_symlink_traversal {
+            check_parents(path, destination)?;
+        }
+        if !policy.allow_escaping_links {
+            check_link(entry, path, destination)?;
+        }
+        if let Some(beneath) = target.beneath {
+            return beneath.unpack(entry, path, permissions);
+        }
+        // The `tar` crate would write these as empty regular files.
+        #[cfg(unix)]
+        if special {
+            return create_special(entry, &destination.join(path), permissions);
+        }
+    }
+    entry
+        .unpack_in(destination)
+        .map_err(security::unwrap_crypto_error)?;
+    Ok(())
+}
+
+/// Returns `true` for block and character devices and FIFOs.
+fn is_special(entry_type: EntryType) -> bool {
+    entry_type.is_block_special() || entry_type.is_character_special() || entry_type.is_fifo()
+}
+
+/// Creates the device node or FIFO described by `entry` at `target`, applying
+/// its mode and owner as `permissions` describes.
+#[cfg(unix)]
+fn create_special<R: Read>(
+    entry: &Entry<'_, R>,
+    target: &Path,
+    permissions: &ExtractPermissions,
+) -> Result<(), RazeError> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let header = entry.header();
+    let (file_type, device) = special_node(header)?;
+    let mode = permissions.mode(header)?;
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(target) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {},
+    }
+    let c_path = CString::new(target.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `c_path` is a valid NUL-terminated string that outlives the call.
+    let result = unsafe { libc::mknod(c_path.as_ptr(), file_type | mode as libc::mode_t, device) };
+    if result != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    if permissions.same_owner {
+        std::os::unix::fs::lchown(
+            target,
+            Some(header.uid()? as u32),
+            Some(header.gid()? as u32),
+        )?;
+    }
+    // `mknod` is subject to the process umask, and ownership changes can clear special bits.
+    fs::set_permissions(target, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+/// Returns the file type and device number of the device node or FIFO
+/// described by `header`, as `mknod` takes them.
+#[cfg(unix)]
+pub(crate) fn special_node(header: &Header) -> io::Result<(libc::mode_t, libc::dev_t)> {
+    let device = || -> io::Result<libc::dev_t> {
+        let major = header.device_major()?.unwrap_or(0);
+        let minor = header.device_minor()?.unwrap_or(0);
+        Ok(libc::makedev(major as _, minor as _))
+    };
+    // FIFOs have no device numbers, and writers often leave the fields empty.
+    Ok(match header.entry_type() {
+        EntryType::Block => (libc::S_IFBLK, device()?),
+        EntryType::Char => (libc::S_IFCHR, device()?),
+        _ => (libc::S_IFIFO, 0),
+    })
+}
+
+/// Refuses to place `path` beneath a symbolic link inside `destination`.
+fn check_parents(path: &Path, destination: &Path) -> Result<(), RazeError> {
+    let mut current = destination.to_path_buf();
+    for ancestor in path.parent().into_iter().flat_map(Path::components) {
+        current.push(ancestor);
+        if fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(RazeError::SymlinkTraversal {
+                path: path.display().to_string(),
+                link: current
+                    .strip_prefix(destination)
+                    .unwrap_or(&current)
+                    .display()
+                    .to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Refuses symbolic and hard links whose targets lie outside `destination`.
+///
+/// A symbolic link is resolved from the directory it is placed in, and a
+/// hard link from the destination itself, as `tar` stores them.
+fn check_link<R: Read>(
+    entry: &Entry<'_, R>,
+    path: &Path,
+    destination: &Path,
+) -> Result<(), RazeError> {
+    let entry_type = entry.header().entry_type();
+    let base = if entry_type.is_symlink() {
+        path.parent().unwrap_or(Path::new(""))
+    } else if entry_type.is_hard_link() {
+        Path::new("")
+    } else {
+        return Ok(());
+    };
+    let Some(target) = entry.link_name()? else {
+        return Ok(());
+    };
+    if !stays_inside(destination, base, &target) {
+        return Err(RazeError::LinkEscape {
+            path: path.display().to_string(),
+            target: target.display().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns `true` if `target`, resolved from the relative directory `base`,
+/// stays inside `destination` at every step.
+///
+/// Symbolic links that already exist along the way are followed, even if
+/// they dangle, so that a chain of individually harmless links cannot lead
+/// outside. Absolute targets never stay inside.
+fn stays_inside(destination: &Path, base: &Path, target: &Path) -> bool {
+    // Components still to resolve, the next one last.
+    let mut pending = Vec::new();
+    push_components(&mut pending, target);
+    push_components(&mut pending, base);
+
+    let mut current = destination.to_path_buf();
+    let mut hops = 0;
+    while let Some(next) = pending.pop() {
+        match next.components().next() {
+            Some(Component::Normal(name)) => {
+                current.push(name);
+                if let Ok(link) = fs::read_link(&current) {
+                    hops += 1;
+                    if hops > MAX_LINK_HOPS {
+                        return false;
+                    }
+                    current.pop();
+                    push_components(&mut pending, &link);
+                }
+            },
+            Some(Component::ParentDir) => {
+                current.pop();
+            },
+            Some(Component::RootDir | Component::Prefix(_)) => return false,
+            Some(Component::CurDir) | None => {},
+        }
+        if !current.starts_with(destination) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Pushes the components of `path` onto `pending` so that the first one is popped first.
+fn push_components(pending: &mut Vec<PathBuf>, path: &Path) {
+    pending.extend(
+        path.components()
+            .rev()
+            .map(|c| PathBuf::from(c.as_os_str())),
+    );
+}
@@ -0,0 +1,208 @@
+//! # Archive Format Module
+//!
+//! Besides its native Zstandard-compressed `.rz` format, Raze reads and writes
+//! the standard tarball flavours used by other tools. This module identifies
+//! the container of an archive stream by its magic bytes and provides the
+//! matching encoder and decoder.
+//!
+//! Zstandard is always available. The gzip, xz and bzip2 codecs are enabled by
+//! the `gzip`, `xz` and `bzip2` cargo features respectively.
+
+use crate::utils::errors::RazeError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// Number of leading bytes needed by [`ArchiveFormat::detect`].
+pub const DETECT_LEN: usize = 512;
+
+/// The container format of an archive stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArchiveFormat {
+    /// A Zstandard-compressed tarball, Raze's native format.
+    #[default]
+    TarZst,
+    /// A gzip-compressed tarball.
+    TarGz,
+    /// An xz-compressed tarball.
+    TarXz,
+    /// A bzip2-compressed tarball. Only supported for reading.
+    TarBz2,
+    /// An uncompressed POSIX (ustar) tarball.
+    Tar,
+}
+
+impl ArchiveFormat {
+    /// Returns the conventional file name suffix of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarZst => "rz",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarBz2 => "tar.bz2",
+            ArchiveFormat::Tar => "tar",
+        }
+    }
+
+    /// Identifies the format from the first bytes of an archive stream.
+    ///
+    /// `header` should hold at least [`DETECT_LEN`] bytes, or the whole stream
+    /// if it is shorter, so that the ustar signature of a plain tarball can be seen.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(ArchiveFormat::TarZst)
+        } else if header.starts_with(&[0x1F, 0x8B]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveFormat::TarXz)
+        } else if header.starts_with(b"BZh") {
+            Some(ArchiveFormat::TarBz2)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the cargo feature that provides this format's codec, if any.
+    fn feature(self) -> Option<&'static str> {
+        match self {
+            ArchiveFormat::TarGz => Some("gzip"),
+            ArchiveFormat::TarXz => Some("xz"),
+            ArchiveFormat::TarBz2 => Some("bzip2"),
+            ArchiveFormat::TarZst | ArchiveFormat::Tar => None,
+        }
+    }
+
+    fn unsupported(self) -> String {
+        format!(
+            "{} archives are not supported: Raze was built without the `{}` feature",
+            self,
+            self.feature().unwrap_or_default()
+        )
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarBz2 => "tar.bz2",
+            ArchiveFormat::Tar => "tar",
+        })
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rz" | "zst" | "tar.zst" => Ok(ArchiveFormat::TarZst),
+            "gz" | "tgz" | "tar.gz" => Ok(ArchiveFormat::TarGz),
+            "xz" | "txz" | "tar.xz" => Ok(ArchiveFormat::TarXz),
+            "bz2" | "tbz2" | "tar.bz2" => Ok(ArchiveFormat::TarBz2),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => Err(format!(
+                "unknown format '{}' (expected tar.zst, tar.gz, tar.xz or tar)",
+                s
+            )),
+        }
+    }
+}
+
+/// A compressing writer for one of the supported formats.
+pub enum Compressor<W: Write> {
+    /// Zstandard compression.
+    Zstd(zstd::Encoder<'static, W>),
+    /// Gzip compression.
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    /// Xz compression.
+    #[cfg(feature = "xz")]
+    Xz(liblzma::write::XzEncoder<W>),
+    /// No compression.
+    Plain(W),
+}
+
+impl<W: Write> Compressor<W> {
+    /// Creates a compressor writing `format` into `writer`.
+    pub fn new(format: ArchiveFormat, writer: W) -> Result<Self, RazeError> {
+        match format {
+            ArchiveFormat::TarZst => zstd::Encoder::new(writer, 3)
+                .map(Compressor::Zstd)
+                .map_err(|e| RazeError::CompressionError(e.to_string())),
+            #[cfg(feature = "gzip")]
+            ArchiveFormat::TarGz => Ok(Compressor::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "xz")]
+            ArchiveFormat::TarXz => Ok(Compressor::Xz(liblzma::write::XzEncoder::new(writer, 6))),
+            ArchiveFormat::Tar => Ok(Compressor::Plain(writer)),
+            ArchiveFormat::TarBz2 => Err(RazeError::CompressionError(
+                "Writing tar.bz2 archives is not supported".to_string(),
+            )),
+            #[allow(unreachable_patterns)]
+            _ => Err(RazeError::CompressionError(format.unsupported())),
+        }
+    }
+
+    /// Completes the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Compressor::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "gzip")]
+            Compressor::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "xz")]
+            Compressor::Xz(encoder) => encoder.finish(),
+            Compressor::Plain(writer) => Ok(writer),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Compressor::Zstd(encoder) => encoder,
+            #[cfg(feature = "gzip")]
+            Compressor::Gzip(encoder) => encoder,
+            #[cfg(feature = "xz")]
+            Compressor::Xz(encoder) => encoder,
+            Compressor::Plain(writer) => writer,
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+/// Wraps `reader` in the decoder for `format`, returning the raw tar stream.
+pub fn decoder<'a, R: Read + 'a>(
+    format: ArchiveFormat,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>, RazeError> {
+    match format {
+        ArchiveFormat::TarZst => zstd::Decoder::new(reader)
+            .map(|d| Box::new(d) as Box<dyn Read>)
+            .map_err(|e| RazeError::DecompressionError(e.to_string())),
+        #[cfg(feature = "gzip")]
+        ArchiveFormat::TarGz => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+        #[cfg(feature = "xz")]
+        ArchiveFormat::TarXz => Ok(Box::new(liblzma::read::XzDecoder::new_multi_decoder(
+            reader,
+        ))),
+        #[cfg(feature = "bzip2")]
+        ArchiveFormat::TarBz2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader))),
+        ArchiveFormat::Tar => Ok(Box::new(reader)),
+        #[allow(unreachable_patterns)]
+        _ => Err(RazeError::DecompressionError(format.unsupported())),
+    }
+}
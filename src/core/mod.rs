@@ -5,12 +5,33 @@
 //! It serves as the central processing unit for the application's primary
 //! functionalities.
 //!
-//! The module is divided into two main sub-modules:
+//! The module is divided into the following sub-modules:
+//! - `beneath`: Creates archive entries beneath the destination on Linux, immune to symlink races.
 //! - `compress`: Handles the creation of `.rz` archives from files or directories.
+//! - `convert`: Converts between Raze archives and ZIP files (`zip` feature).
 //! - `decompress`: Manages the extraction of contents from `.rz` archives.
+//! - `extract`: Writes archive entries to disk under a hardened extraction policy.
+//! - `format`: Detects and encodes the supported tarball formats (tar.zst, tar.gz, tar.xz, tar).
+//! - `recovery`: Appends Reed-Solomon recovery records and repairs damaged archives.
+//! - `rekey`: Changes the passwords and key files of encrypted archives without re-encrypting them.
+//! - `sfx`: Builds self-extracting archives from the `raze-sfx` extraction stub.
+//! - `signature`: Signs archives with Ed25519 and verifies their signatures.
+//! - `trailer`: Locates optional records stored after the archive stream.
+//! - `volume`: Splits archives into fixed-size volumes and joins them back together.
 //!
 //! These sub-modules work in conjunction with external crates like `tar` for
 //! archiving and `zstd` for high-performance compression, offering a robust
 //! and efficient archiving solution.
+mod beneath;
 pub mod compress;
+#[cfg(feature = "zip")]
+pub mod convert;
 pub mod decompress;
+pub mod extract;
+pub mod format;
+pub mod recovery;
+pub mod rekey;
+pub mod sfx;
+pub mod signature;
+pub mod trailer;
+pub mod volume;
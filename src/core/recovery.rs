@@ -0,0 +1,475 @@
+//! # Recovery Record Module
+//!
+//! This module protects archives against bit rot with Reed-Solomon parity.
+//! The archive stream is cut into fixed-size blocks, and every group of
+//! [`DATA_SHARDS`] blocks receives a configurable number of parity blocks.
+//! Each block is also covered by a CRC32 checksum so that damaged blocks can
+//! be located, which turns silent corruption into erasures that the parity
+//! can rebuild.
+//!
+//! The recovery record is appended after the archive stream as a trailing
+//! record (see [`crate::core::trailer`]), so archives with a recovery record
+//! remain readable by the regular extraction path. Its body is laid out as:
+//!
+//! ```text
+//! [version: u8][block size: u32][data blocks: u16][parity blocks: u16]
+//! [protected length: u64][crc32 of the preceding fields: u32]
+//! for every group: [parity blocks][crc32 per data and parity block][crc32 of that table]
+//! ```
+
+use crate::core::trailer::{self, FOOTER_LEN};
+use crate::utils::errors::RazeError;
+use log::{info, warn};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Magic bytes identifying a recovery record trailer.
+pub const MAGIC_RECOVERY: &[u8; 4] = b"RZRS"; // Raze Recovery Set
+
+/// Number of data blocks covered by one group of parity blocks.
+pub const DATA_SHARDS: usize = 128;
+
+const RECOVERY_VERSION: u8 = 1;
+const SHARD_SIZE: usize = 16 * 1024;
+const PARAMS_LEN: u64 = 1 + 4 + 2 + 2 + 8 + 4;
+
+/// Returns the number of parity blocks per group for a redundancy of `percent`.
+fn parity_shards_for(percent: u8) -> Result<usize, RazeError> {
+    if !(1..=100).contains(&percent) {
+        return Err(RazeError::RecoveryError(format!(
+            "Recovery redundancy must be between 1% and 100%, got {}%",
+            percent
+        )));
+    }
+    Ok((DATA_SHARDS * percent as usize).div_ceil(100))
+}
+
+fn codec(data_shards: usize, parity_shards: usize) -> Result<ReedSolomon, RazeError> {
+    ReedSolomon::new(data_shards, parity_shards)
+        .map_err(|e| RazeError::RecoveryError(format!("{:?}", e)))
+}
+
+/// The fixed parameters stored at the start of a recovery record.
+struct RecoveryParams {
+    shard_size: usize,
+    data_shards: usize,
+    parity_shards: usize,
+    protected_len: u64,
+}
+
+impl RecoveryParams {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PARAMS_LEN as usize);
+        bytes.push(RECOVERY_VERSION);
+        bytes.extend_from_slice(&(self.shard_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.data_shards as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.parity_shards as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.protected_len.to_le_bytes());
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; PARAMS_LEN as usize]) -> Result<Self, RazeError> {
+        let (fields, crc) = bytes.split_at(PARAMS_LEN as usize - 4);
+        if crc32fast::hash(fields).to_le_bytes() != crc {
+            return Err(RazeError::RecoveryError(
+                "The recovery record header is damaged".to_string(),
+            ));
+        }
+        if fields[0] != RECOVERY_VERSION {
+            return Err(RazeError::RecoveryError(format!(
+                "Unsupported recovery record version {}",
+                fields[0]
+            )));
+        }
+        let params = Self {
+            shard_size: u32::from_le_bytes(fields[1..5].try_into().unwrap()) as usize,
+            data_shards: u16::from_le_bytes(fields[5..7].try_into().unwrap()) as usize,
+            parity_shards: u16::from_le_bytes(fields[7..9].try_into().unwrap()) as usize,
+            protected_len: u64::from_le_bytes(fields[9..17].try_into().unwrap()),
+        };
+        if params.shard_size == 0 || params.data_shards == 0 || params.parity_shards == 0 {
+            return Err(RazeError::RecoveryError(
+                "The recovery record header is invalid".to_string(),
+            ));
+        }
+        Ok(params)
+    }
+
+    fn group_count(&self) -> u64 {
+        self.protected_len
+            .div_ceil(self.shard_size as u64)
+            .div_ceil(self.data_shards as u64)
+    }
+
+    fn table_len(&self) -> usize {
+        (self.data_shards + self.parity_shards + 1) * 4
+    }
+
+    fn group_record_len(&self) -> u64 {
+        self.parity_shards as u64 * self.shard_size as u64 + self.table_len() as u64
+    }
+}
+
+/// Computes the checksum table of a group, followed by the table's own checksum.
+fn checksum_table(shards: &[Vec<u8>]) -> Vec<u8> {
+    let mut table: Vec<u8> = shards
+        .iter()
+        .flat_map(|shard| crc32fast::hash(shard).to_le_bytes())
+        .collect();
+    let crc = crc32fast::hash(&table);
+    table.extend_from_slice(&crc.to_le_bytes());
+    table
+}
+
+/// A writer that passes data through unchanged while computing a recovery record.
+///
+/// Parity blocks are accumulated in an anonymous temporary file and appended
+/// to the inner writer, after the archive stream, by [`RecoveryWriter::finish`].
+pub struct RecoveryWriter<W: Write> {
+    inner: W,
+    codec: ReedSolomon,
+    parity_shards: usize,
+    shard: Vec<u8>,
+    group: Vec<Vec<u8>>,
+    records: File,
+    protected_len: u64,
+}
+
+impl<W: Write> RecoveryWriter<W> {
+    /// Wraps `inner`, adding `percent` percent of parity data to the stream.
+    pub fn new(inner: W, percent: u8) -> Result<Self, RazeError> {
+        let parity_shards = parity_shards_for(percent)?;
+        Ok(Self {
+            inner,
+            codec: codec(DATA_SHARDS, parity_shards)?,
+            parity_shards,
+            shard: Vec::with_capacity(SHARD_SIZE),
+            group: Vec::with_capacity(DATA_SHARDS),
+            records: tempfile::tempfile()?,
+            protected_len: 0,
+        })
+    }
+
+    fn absorb(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let take = (SHARD_SIZE - self.shard.len()).min(data.len());
+            self.shard.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.shard.len() == SHARD_SIZE {
+                let shard = std::mem::replace(&mut self.shard, Vec::with_capacity(SHARD_SIZE));
+                self.group.push(shard);
+                if self.group.len() == DATA_SHARDS {
+                    self.flush_group()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_group(&mut self) -> io::Result<()> {
+        let mut shards = std::mem::take(&mut self.group);
+        shards.resize(DATA_SHARDS + self.parity_shards, vec![0u8; SHARD_SIZE]);
+        self.codec
+            .encode(&mut shards)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        for parity in &shards[DATA_SHARDS..] {
+            self.records.write_all(parity)?;
+        }
+        self.records.write_all(&checksum_table(&shards))?;
+        Ok(())
+    }
+
+    /// Appends the recovery record to the inner writer and returns it.
+    pub fn finish(mut self) -> Result<W, RazeError> {
+        if !self.shard.is_empty() {
+            let mut shard = std::mem::take(&mut self.shard);
+            shard.resize(SHARD_SIZE, 0);
+            self.group.push(shard);
+        }
+        if !self.group.is_empty() {
+            self.flush_group()?;
+        }
+
+        let params = RecoveryParams {
+            shard_size: SHARD_SIZE,
+            data_shards: DATA_SHARDS,
+            parity_shards: self.parity_shards,
+            protected_len: self.protected_len,
+        }
+        .encode();
+        let records_len = self.records.stream_position()?;
+        self.records.seek(SeekFrom::Start(0))?;
+
+        self.inner.write_all(&params)?;
+        io::copy(&mut self.records, &mut self.inner)?;
+        trailer::write_footer(&mut self.inner, MAGIC_RECOVERY, PARAMS_LEN + records_len)?;
+        info!(
+            "Added recovery record: {} parity blocks per {} data blocks",
+            self.parity_shards, DATA_SHARDS
+        );
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for RecoveryWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.absorb(&buf[..n])?;
+        self.protected_len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The location of an archive file's recovery record and of the stream it protects.
+struct RecordLayout {
+    params: RecoveryParams,
+    records_start: u64,
+    data_start: u64,
+}
+
+impl RecordLayout {
+    /// Locates and validates the recovery record of `file`, or returns `None`
+    /// if it has none.
+    fn read(file: &mut File) -> Result<Option<Self>, RazeError> {
+        let Some(record) = trailer::read_trailers(file)?
+            .into_iter()
+            .find(|t| &t.magic == MAGIC_RECOVERY)
+        else {
+            return Ok(None);
+        };
+
+        let mut params_bytes = [0u8; PARAMS_LEN as usize];
+        file.seek(SeekFrom::Start(record.offset))?;
+        file.read_exact(&mut params_bytes)?;
+        let params = RecoveryParams::decode(&params_bytes)?;
+        let records_start = record.offset + PARAMS_LEN;
+        // The parameters come from the file, so a crafted header must not overflow.
+        let expected_end = params
+            .group_count()
+            .checked_mul(params.group_record_len())
+            .and_then(|len| len.checked_add(records_start))
+            .and_then(|end| end.checked_add(FOOTER_LEN));
+        if expected_end != Some(record.end()) {
+            return Err(RazeError::RecoveryError(
+                "The recovery record length does not match its header".to_string(),
+            ));
+        }
+        let data_start = record
+            .offset
+            .checked_sub(params.protected_len)
+            .ok_or_else(|| {
+                RazeError::RecoveryError(
+                    "The recovery record does not match the archive".to_string(),
+                )
+            })?;
+        Ok(Some(Self {
+            params,
+            records_start,
+            data_start,
+        }))
+    }
+
+    /// Returns the offset of `group` within the protected stream.
+    fn group_start(&self, group: u64) -> u64 {
+        group * (self.params.data_shards * self.params.shard_size) as u64
+    }
+
+    /// Returns the file offset of the parity blocks of `group`.
+    fn record_start(&self, group: u64) -> u64 {
+        self.records_start + group * self.params.group_record_len()
+    }
+
+    /// Reads the data blocks of `group`, padding the tail of the archive with zeros.
+    fn read_data_shards(&self, file: &mut File, group: u64) -> io::Result<Vec<Vec<u8>>> {
+        let params = &self.params;
+        let mut shards = Vec::with_capacity(params.data_shards + params.parity_shards);
+        for i in 0..params.data_shards {
+            let offset = self.group_start(group) + (i * params.shard_size) as u64;
+            let len = params
+                .protected_len
+                .saturating_sub(offset)
+                .min(params.shard_size as u64) as usize;
+            let mut shard = vec![0u8; params.shard_size];
+            file.seek(SeekFrom::Start(self.data_start + offset))?;
+            file.read_exact(&mut shard[..len])?;
+            shards.push(shard);
+        }
+        Ok(shards)
+    }
+}
+
+/// Recomputes the parity and checksums of the block groups covering the
+/// `len` bytes at file offset `offset`, after they were rewritten on purpose.
+///
+/// This keeps the recovery record in step with deliberate in-place edits,
+/// such as a re-keyed encryption header, which [`repair`] would otherwise
+/// take for damage and revert. Archives without a recovery record are left alone.
+pub(crate) fn refresh(file: &mut File, offset: u64, len: u64) -> Result<(), RazeError> {
+    let Some(layout) = RecordLayout::read(file)? else {
+        return Ok(());
+    };
+    let params = &layout.params;
+    let start = offset.saturating_sub(layout.data_start);
+    let end = (start + len).min(params.protected_len);
+    if len == 0 || start >= end {
+        return Ok(());
+    }
+
+    let codec = codec(params.data_shards, params.parity_shards)?;
+    let group_len = layout.group_start(1);
+    for group in start / group_len..=(end - 1) / group_len {
+        let mut shards = layout.read_data_shards(file, group)?;
+        shards.resize(
+            params.data_shards + params.parity_shards,
+            vec![0u8; params.shard_size],
+        );
+        codec
+            .encode(&mut shards)
+            .map_err(|e| RazeError::RecoveryError(format!("{:?}", e)))?;
+        file.seek(SeekFrom::Start(layout.record_start(group)))?;
+        for parity in &shards[params.data_shards..] {
+            file.write_all(parity)?;
+        }
+        file.write_all(&checksum_table(&shards))?;
+    }
+    Ok(())
+}
+
+/// A summary of the corruption found and corrected by [`repair`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The number of data and parity blocks that were checked.
+    pub checked_blocks: u64,
+    /// The number of blocks that failed their checksum.
+    pub damaged_blocks: u64,
+    /// The number of damaged blocks that were rebuilt from parity.
+    pub repaired_blocks: u64,
+    /// The number of archive and parity bytes rewritten in place.
+    pub repaired_bytes: u64,
+}
+
+/// Verifies an archive against its recovery record and rebuilds damaged blocks in place.
+///
+/// Returns a [`RepairReport`] describing the corruption that was found and
+/// corrected. If some blocks cannot be rebuilt because a group has more
+/// damaged blocks than parity blocks, all repairable groups are still fixed
+/// and a `RazeError::RecoveryError` is returned.
+pub fn repair(archive_path: impl AsRef<Path>) -> Result<RepairReport, RazeError> {
+    let archive_path = archive_path.as_ref();
+    if !archive_path.exists() {
+        return Err(RazeError::NotFound(archive_path.display().to_string()));
+    }
+    if crate::core::volume::is_volume(archive_path)? {
+        return Err(RazeError::RecoveryError(
+            "Split archives cannot be repaired in place; join the volumes first".to_string(),
+        ));
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(archive_path)?;
+    let layout = RecordLayout::read(&mut file)?.ok_or_else(|| {
+        RazeError::RecoveryError(format!(
+            "'{}' has no recovery record",
+            archive_path.display()
+        ))
+    })?;
+    let (params, data_start) = (&layout.params, layout.data_start);
+
+    let codec = codec(params.data_shards, params.parity_shards)?;
+    let mut report = RepairReport::default();
+    let mut unrepairable = Vec::new();
+    for group in 0..params.group_count() {
+        let group_start = layout.group_start(group);
+        let record_start = layout.record_start(group);
+
+        let mut shards = layout.read_data_shards(&mut file, group)?;
+        file.seek(SeekFrom::Start(record_start))?;
+        for _ in 0..params.parity_shards {
+            let mut shard = vec![0u8; params.shard_size];
+            file.read_exact(&mut shard)?;
+            shards.push(shard);
+        }
+        let mut table = vec![0u8; params.table_len()];
+        file.read_exact(&mut table)?;
+        let (checksums, table_crc) = table.split_at(table.len() - 4);
+        if crc32fast::hash(checksums).to_le_bytes() != table_crc {
+            warn!("Checksum table of block group {} is damaged", group);
+            unrepairable.push(group);
+            continue;
+        }
+
+        let damaged: Vec<usize> = shards
+            .iter()
+            .zip(checksums.chunks_exact(4))
+            .enumerate()
+            .filter(|(_, (shard, crc))| crc32fast::hash(shard).to_le_bytes() != **crc)
+            .map(|(i, _)| i)
+            .collect();
+        report.checked_blocks += shards.len() as u64;
+        report.damaged_blocks += damaged.len() as u64;
+        if damaged.is_empty() {
+            continue;
+        }
+        if damaged.len() > params.parity_shards {
+            warn!(
+                "Block group {} has {} damaged blocks but only {} parity blocks",
+                group,
+                damaged.len(),
+                params.parity_shards
+            );
+            unrepairable.push(group);
+            continue;
+        }
+
+        let mut slots: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        for &i in &damaged {
+            slots[i] = None;
+        }
+        codec
+            .reconstruct(&mut slots)
+            .map_err(|e| RazeError::RecoveryError(format!("{:?}", e)))?;
+
+        for &i in &damaged {
+            let shard = slots[i].as_ref().expect("reconstructed shard");
+            let (offset, len) = if i < params.data_shards {
+                let offset = group_start + (i * params.shard_size) as u64;
+                let len = params
+                    .protected_len
+                    .saturating_sub(offset)
+                    .min(params.shard_size as u64);
+                (data_start + offset, len as usize)
+            } else {
+                let parity = i - params.data_shards;
+                (
+                    record_start + (parity * params.shard_size) as u64,
+                    params.shard_size,
+                )
+            };
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&shard[..len])?;
+            report.repaired_blocks += 1;
+            report.repaired_bytes += len as u64;
+        }
+    }
+    file.sync_all()?;
+
+    if !unrepairable.is_empty() {
+        return Err(RazeError::RecoveryError(format!(
+            "{} of {} block groups are too damaged to repair ({} blocks rebuilt)",
+            unrepairable.len(),
+            params.group_count(),
+            report.repaired_blocks
+        )));
+    }
+    Ok(report)
+}
@@ -0,0 +1,171 @@
+//! # Re-keying Module
+//!
+//! This module changes the secrets that open an encrypted archive without
+//! touching its contents. An encrypted archive is sealed with a random data
+//! key, which its header wraps once per key slot (see
+//! [`crate::utils::security::KeySlots`]). Changing a password therefore only
+//! replaces the header, and nothing has to be decrypted or re-encrypted.
+//!
+//! The archive is copied to a temporary file next to it, the new header is
+//! written into the copy, and the copy is renamed over the archive. An
+//! interruption thus leaves either the old or the new archive, never one
+//! with a partly written header that no key slot opens.
+//!
+//! If the archive carries a recovery record, the parity covering the header
+//! is recomputed as well, so that a later repair does not restore the old slots.
+//! A signature covers the header too and cannot be renewed without the signing
+//! key, so signed archives are only re-keyed when forced.
+
+use crate::core::signature::MAGIC_SIGNATURE;
+use crate::core::{decompress, recovery, trailer, volume};
+use crate::utils::atomic::AtomicFile;
+use crate::utils::errors::RazeError;
+use crate::utils::secret::Secret;
+use crate::utils::security::{Credentials, KdfParams, KeyFile, KeySlots};
+use log::{info, warn};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The secrets protecting a new key slot: a password, a key file, or both.
+#[derive(Clone, Default)]
+pub struct NewKey {
+    /// The password that opens the new slot.
+    pub password: Option<Secret<String>>,
+    /// The key file that opens the new slot, alone or together with `password`.
+    pub key_file: Option<KeyFile>,
+    /// Argon2id cost parameters used to derive the slot's key.
+    pub kdf: KdfParams,
+}
+
+/// Replaces the key slot opened by `credentials` with one for `new_key`.
+///
+/// All other slots stay valid, so this changes one password without
+/// affecting the others. A signed archive is refused unless `force` is set,
+/// since its signature would no longer verify.
+pub fn rekey(
+    archive_path: impl AsRef<Path>,
+    credentials: &Credentials,
+    new_key: &NewKey,
+    force: bool,
+) -> Result<(), RazeError> {
+    update_slots(archive_path.as_ref(), credentials, force, |slots| {
+        add_secret(slots, new_key)?;
+        slots.remove_unlocked()
+    })
+}
+
+/// Adds a key slot for `new_key` to an archive that `credentials` can open.
+/// A signed archive is refused unless `force` is set.
+pub fn add_password(
+    archive_path: impl AsRef<Path>,
+    credentials: &Credentials,
+    new_key: &NewKey,
+    force: bool,
+) -> Result<(), RazeError> {
+    update_slots(archive_path.as_ref(), credentials, force, |slots| {
+        add_secret(slots, new_key)
+    })
+}
+
+/// Removes the key slot opened by `credentials`. The last slot of an archive
+/// cannot be removed. A signed archive is refused unless `force` is set.
+pub fn remove_password(
+    archive_path: impl AsRef<Path>,
+    credentials: &Credentials,
+    force: bool,
+) -> Result<(), RazeError> {
+    update_slots(
+        archive_path.as_ref(),
+        credentials,
+        force,
+        KeySlots::remove_unlocked,
+    )
+}
+
+/// Adds a slot for `new_key`, which must include a password or a key file.
+fn add_secret(slots: &mut KeySlots, new_key: &NewKey) -> Result<(), RazeError> {
+    if new_key.password.is_none() && new_key.key_file.is_none() {
+        return Err(RazeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "A new password or key file is required.",
+        )));
+    }
+    slots.add_secret(
+        new_key.password.as_ref().map(|p| p.expose().as_str()),
+        new_key.key_file.as_ref(),
+        new_key.kdf,
+    )
+}
+
+/// Opens the header of an encrypted archive with `credentials`, applies
+/// `change` to its key slots and atomically replaces the archive with a copy
+/// carrying the new header. A signed archive is refused unless `force` is set.
+fn update_slots<F>(
+    archive_path: &Path,
+    credentials: &Credentials,
+    force: bool,
+    change: F,
+) -> Result<(), RazeError>
+where
+    F: FnOnce(&mut KeySlots) -> Result<(), RazeError>,
+{
+    if !archive_path.exists() {
+        return Err(RazeError::NotFound(archive_path.display().to_string()));
+    }
+    if volume::is_volume(archive_path)? {
+        return Err(RazeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Split archives cannot be re-keyed in place; join the volumes first.",
+        )));
+    }
+    if !decompress::is_encrypted(archive_path)? {
+        return Err(RazeError::CryptoError(format!(
+            "'{}' is not encrypted",
+            archive_path.display()
+        )));
+    }
+
+    let mut file = File::open(archive_path)?;
+    let signed = trailer::read_trailers(&mut file)?
+        .iter()
+        .any(|t| &t.magic == MAGIC_SIGNATURE);
+    if signed && !force {
+        return Err(RazeError::SignatureError(format!(
+            "'{}' is signed, and its signature would no longer match the new header; \
+             pack it again to re-sign it, or force re-keying to invalidate the signature",
+            archive_path.display()
+        )));
+    }
+    let (start, _) = trailer::payload_range(&mut file)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut slots = KeySlots::read(&mut file, credentials)?;
+    change(&mut slots)?;
+
+    // The header keeps its length, so the chunks after it stay where they are.
+    let mut header = Vec::with_capacity(slots.header_len());
+    slots.write(&mut header)?;
+    let mut output = AtomicFile::create(archive_path)?;
+    output
+        .as_file()
+        .set_permissions(file.metadata()?.permissions())?;
+    file.rewind()?;
+    io::copy(&mut file, &mut output.as_file())?;
+    output.seek(SeekFrom::Start(start))?;
+    output.write_all(&header)?;
+    recovery::refresh(output.as_file_mut(), start, header.len() as u64)?;
+    output.commit(false)?;
+
+    info!(
+        "Updated the key slots of '{}' ({} remaining)",
+        archive_path.display(),
+        slots.len()
+    );
+    if signed {
+        warn!(
+            "The signature of '{}' no longer matches its new header and will fail verification",
+            archive_path.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! # Self-Extracting Archive Module
+//!
+//! A self-extracting (SFX) archive is a copy of the small `raze-sfx` extraction
+//! stub with a regular archive appended to it. A trailing record (see
+//! [`crate::core::trailer`]) stores the offset at which the archive begins:
+//!
+//! ```text
+//! [raze-sfx stub][archive stream and other records][payload offset: u64 LE][8: u64 LE]["RZSX"]
+//! ```
+//!
+//! When the executable runs, the stub locates the archive inside its own file
+//! and extracts it. The regular extraction path reads the same trailer, so an
+//! SFX file can also be passed to `raze --unpack` like any other archive.
+
+use crate::core::trailer;
+use crate::utils::errors::RazeError;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes identifying the trailer of a self-extracting archive.
+pub const MAGIC_SFX: &[u8; 4] = b"RZSX"; // Raze Self-eXtracting
+
+/// File name of the extraction stub binary installed alongside `raze`.
+pub const STUB_NAME: &str = "raze-sfx";
+
+/// Locates the `raze-sfx` stub next to the currently running executable.
+pub fn find_stub() -> Result<PathBuf, RazeError> {
+    let exe = std::env::current_exe()?;
+    let stub = exe.with_file_name(STUB_NAME);
+    if stub.is_file() {
+        Ok(stub)
+    } else {
+        Err(RazeError::NotFound(format!(
+            "{} (the self-extraction stub must be installed next to '{}')",
+            stub.display(),
+            exe.display()
+        )))
+    }
+}
+
+/// Copies the stub at `stub_path` into `writer` and returns its contents,
+/// whose length is the offset at which the archive payload begins.
+pub fn write_stub<W: Write>(writer: &mut W, stub_path: &Path) -> Result<Vec<u8>, RazeError> {
+    let stub =
+        fs::read(stub_path).map_err(|_| RazeError::NotFound(stub_path.display().to_string()))?;
+    writer.write_all(&stub)?;
+    Ok(stub)
+}
+
+/// Writes the trailer recording that the archive payload begins at `payload_start`.
+pub fn write_trailer<W: Write>(writer: &mut W, payload_start: u64) -> io::Result<()> {
+    writer.write_all(&payload_start.to_le_bytes())?;
+    trailer::write_footer(writer, MAGIC_SFX, 8)
+}
+
+/// Marks the file at `path` as executable by everyone who can read it.
+#[cfg(unix)]
+pub fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | ((mode & 0o444) >> 2));
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Marks the file at `path` as executable. This is a no-op on non-Unix platforms.
+#[cfg(not(unix))]
+pub fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
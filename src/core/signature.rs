@@ -0,0 +1,334 @@
+//! # Signature Module
+//!
+//! Archives can be signed with Ed25519 to prove who produced them and that
+//! they have not been modified since. The signature is stored as a trailing
+//! record (see [`crate::core::trailer`]) directly after the archive stream,
+//! ahead of any recovery record:
+//!
+//! ```text
+//! [archive stream][version: u8][public key: 32 bytes][signature: 64 bytes][97: u64 LE]["RZSG"]
+//! ```
+//!
+//! The signature covers a BLAKE2s-256 digest of the archive stream exactly as
+//! it is stored, so it can be checked before anything is decrypted or
+//! decompressed, and works the same for plain and encrypted archives. For a
+//! self-extracting archive, the digest also covers the stub in front of the
+//! archive stream, so the program that runs the extraction cannot be swapped.
+
+use crate::core::decompress;
+use crate::core::trailer::{self, Window};
+use crate::utils::errors::RazeError;
+use crate::utils::security::{self, fill_random};
+use blake2::{Blake2s256, Digest};
+use ed25519_dalek::{Signature, Signer};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Magic bytes identifying a signature trailer.
+pub const MAGIC_SIGNATURE: &[u8; 4] = b"RZSG"; // Raze SiGnature
+
+const SIGNATURE_VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const RECORD_LEN: u64 = 1 + KEY_LEN as u64 + 64;
+const SIGNATURE_CONTEXT: &[u8] = b"raze-signature-v1";
+const PUBLIC_KEY_PREFIX: &str = "raze-sign-";
+const SECRET_KEY_PREFIX: &str = "RAZE-SIGN-SECRET-";
+
+/// An Ed25519 secret key that signs archives.
+///
+/// It is written as `RAZE-SIGN-SECRET-` followed by 64 hexadecimal digits,
+/// usually in a key file created by [`write_signing_key`].
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    /// Generates a new random signing key.
+    pub fn generate() -> Result<Self, RazeError> {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        fill_random(bytes.as_mut_slice())?;
+        Ok(SigningKey(ed25519_dalek::SigningKey::from_bytes(&bytes)))
+    }
+
+    /// Returns the public key that signatures made with this key verify against.
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+
+    /// Returns the textual form of the secret key.
+    pub fn to_secret_string(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}{}",
+            SECRET_KEY_PREFIX,
+            security::hex_encode(self.0.as_bytes()).to_ascii_uppercase()
+        ))
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    /// Shows only the public key, so that secrets never end up in logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&self.verifying_key())
+            .finish()
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .strip_prefix(SECRET_KEY_PREFIX)
+            .and_then(security::hex_decode_key)
+            .map(|bytes| {
+                SigningKey(ed25519_dalek::SigningKey::from_bytes(&Zeroizing::new(
+                    bytes,
+                )))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "invalid signing key (expected {} followed by 64 hex digits)",
+                    SECRET_KEY_PREFIX
+                )
+            })
+    }
+}
+
+/// The public key of a [`SigningKey`], written as `raze-sign-` followed by
+/// 64 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            PUBLIC_KEY_PREFIX,
+            security::hex_encode(self.0.as_bytes())
+        )
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .and_then(security::hex_decode_key)
+            .and_then(|bytes| ed25519_dalek::VerifyingKey::from_bytes(&bytes).ok())
+            .map(VerifyingKey)
+            .ok_or_else(|| {
+                format!(
+                    "invalid verifying key '{}' (expected {} followed by 64 hex digits)",
+                    s, PUBLIC_KEY_PREFIX
+                )
+            })
+    }
+}
+
+/// Reads the signing key stored in a key file. Empty lines and lines
+/// starting with `#` are ignored.
+pub fn read_signing_key(path: impl AsRef<Path>) -> Result<SigningKey, RazeError> {
+    let path = path.as_ref();
+    let contents = Zeroizing::new(
+        fs::read_to_string(path).map_err(|_| RazeError::NotFound(path.display().to_string()))?,
+    );
+    let line = key_lines(&contents).next().ok_or_else(|| {
+        RazeError::SignatureError(format!("No signing key found in '{}'", path.display()))
+    })?;
+    line.parse()
+        .map_err(|e| RazeError::SignatureError(format!("{} in '{}'", e, path.display())))
+}
+
+/// Writes `key` to a new key file that only its owner can read, noting the
+/// matching public key in a comment. An existing file is never overwritten.
+pub fn write_signing_key(path: impl AsRef<Path>, key: &SigningKey) -> Result<(), RazeError> {
+    let contents = Zeroizing::new(format!(
+        "# Raze signing key. Keep this file secret.\n# public key: {}\n{}\n",
+        key.verifying_key(),
+        key.to_secret_string().as_str()
+    ));
+    security::write_secret_file(path.as_ref(), &contents)
+}
+
+/// Reads the public keys stored in the files of the directory `dir`, one per
+/// line. Empty lines and lines starting with `#` are ignored, and so are
+/// subdirectories.
+pub fn read_trusted_keys(dir: impl AsRef<Path>) -> Result<Vec<VerifyingKey>, RazeError> {
+    let dir = dir.as_ref();
+    let entries = fs::read_dir(dir).map_err(|_| RazeError::NotFound(dir.display().to_string()))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut keys = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(&path)?;
+        for line in key_lines(&contents) {
+            keys.push(line.parse().map_err(|e| {
+                RazeError::SignatureError(format!("{} in '{}'", e, path.display()))
+            })?);
+        }
+    }
+    if keys.is_empty() {
+        return Err(RazeError::SignatureError(format!(
+            "No trusted keys found in '{}'",
+            dir.display()
+        )));
+    }
+    Ok(keys)
+}
+
+/// Returns the lines of a key file that are neither empty nor comments.
+fn key_lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Starts the digest that a signature covers: the length of the
+/// self-extraction stub in front of the archive stream, followed by the stub
+/// and the archive stream themselves. Archives without a stub start with a
+/// length of zero.
+fn signed_digest(stub_len: u64) -> Blake2s256 {
+    let mut hasher = Blake2s256::new();
+    hasher.update(stub_len.to_le_bytes());
+    hasher
+}
+
+/// Returns the message that is signed for an archive stream with `digest`.
+fn signed_message(digest: &[u8]) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend_from_slice(digest);
+    message
+}
+
+/// A writer that passes the archive stream through unchanged while hashing it,
+/// and appends the signature record once it is finished.
+pub struct SignatureWriter<W: Write> {
+    inner: W,
+    key: SigningKey,
+    hasher: Blake2s256,
+}
+
+impl<W: Write> SignatureWriter<W> {
+    /// Wraps `inner`, signing everything written to it with `key`.
+    pub fn new(inner: W, key: &SigningKey) -> Self {
+        Self {
+            inner,
+            key: key.clone(),
+            hasher: signed_digest(0),
+        }
+    }
+
+    /// Also signs the self-extraction `stub` that precedes the archive stream
+    /// in the file. Must be called before anything is written.
+    pub fn with_stub(mut self, stub: &[u8]) -> Self {
+        self.hasher = signed_digest(stub.len() as u64);
+        self.hasher.update(stub);
+        self
+    }
+
+    /// Appends the signature record to the inner writer and returns it.
+    pub fn finish(mut self) -> Result<W, RazeError> {
+        let digest = self.hasher.finalize();
+        let signature = self.key.0.sign(&signed_message(&digest));
+
+        self.inner.write_all(&[SIGNATURE_VERSION])?;
+        self.inner
+            .write_all(self.key.verifying_key().0.as_bytes())?;
+        self.inner.write_all(&signature.to_bytes())?;
+        trailer::write_footer(&mut self.inner, MAGIC_SIGNATURE, RECORD_LEN)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SignatureWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Verifies that the archive at `archive_path` is signed by one of `trusted`,
+/// and returns the key it is signed with.
+///
+/// The whole archive stream is read and hashed, so a successful verification
+/// means that not a single byte has changed since the archive was signed.
+pub fn verify(
+    archive_path: impl AsRef<Path>,
+    trusted: &[VerifyingKey],
+) -> Result<VerifyingKey, RazeError> {
+    let archive_path = archive_path.as_ref();
+    let mut source = decompress::open_file(archive_path)?;
+    verify_file(&mut source, archive_path, trusted)
+}
+
+/// Verifies the signature of the archive file `source`, opened from
+/// `archive_path`, like [`verify`].
+pub(crate) fn verify_file<R: Read + Seek>(
+    source: &mut R,
+    archive_path: &Path,
+    trusted: &[VerifyingKey],
+) -> Result<VerifyingKey, RazeError> {
+    let record = trailer::read_trailers(source)?
+        .pop()
+        .filter(|t| &t.magic == MAGIC_SIGNATURE && t.len == RECORD_LEN)
+        .ok_or_else(|| {
+            RazeError::SignatureError(format!("'{}' is not signed", archive_path.display()))
+        })?;
+
+    let mut fields = [0u8; RECORD_LEN as usize];
+    let mut window = Window::new(&mut *source, record.offset, RECORD_LEN)?;
+    io::Read::read_exact(&mut window, &mut fields)?;
+    if fields[0] != SIGNATURE_VERSION {
+        return Err(RazeError::SignatureError(format!(
+            "Unsupported signature version {}",
+            fields[0]
+        )));
+    }
+    let public_key: [u8; KEY_LEN] = fields[1..1 + KEY_LEN].try_into().expect("key");
+    let signer = ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .map(VerifyingKey)
+        .map_err(|_| RazeError::SignatureError("The signature record is damaged".to_string()))?;
+    if !trusted.contains(&signer) {
+        return Err(RazeError::SignatureError(format!(
+            "'{}' is signed by {}, which is not a trusted key",
+            archive_path.display(),
+            signer
+        )));
+    }
+    let signature = Signature::from_bytes(&fields[1 + KEY_LEN..].try_into().expect("signature"));
+
+    // Any stub in front of the archive stream is hashed along with it.
+    let (start, len) = trailer::payload_range(source)?;
+    let mut hasher = signed_digest(start);
+    io::copy(&mut Window::new(&mut *source, 0, start + len)?, &mut hasher)?;
+    signer
+        .0
+        .verify_strict(&signed_message(&hasher.finalize()), &signature)
+        .map_err(|_| {
+            RazeError::SignatureError(format!(
+                "The signature of '{}' is invalid; the archive has been modified",
+                archive_path.display()
+            ))
+        })?;
+    Ok(signer)
+}
@@ -0,0 +1,159 @@
+//! # Trailer Module
+//!
+//! Archives may carry optional records after the archive stream itself, such
+//! as a signature, Reed-Solomon recovery data or the payload offset of a self-extracting
+//! archive. Each record is followed by a fixed-size footer holding the
+//! record's length and a four-byte magic value, so records can be discovered
+//! by reading backwards from the end of the file:
+//!
+//! ```text
+//! [archive stream][record body][body length: u64 LE][magic: 4 bytes]
+//! ```
+//!
+//! This module writes those footers, locates trailing records, and provides
+//! [`Window`], a reader restricted to the archive stream between them and any
+//! leading self-extraction stub.
+
+use crate::core::recovery::MAGIC_RECOVERY;
+use crate::core::sfx::MAGIC_SFX;
+use crate::core::signature::MAGIC_SIGNATURE;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Size in bytes of the footer that terminates every trailing record.
+pub const FOOTER_LEN: u64 = 12;
+
+/// Magic values of every trailing record known to this version of Raze.
+const KNOWN_TRAILERS: &[&[u8; 4]] = &[MAGIC_RECOVERY, MAGIC_SFX, MAGIC_SIGNATURE];
+
+/// The location of a trailing record within an archive file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    /// The magic value identifying the record type.
+    pub magic: [u8; 4],
+    /// The offset of the first byte of the record body.
+    pub offset: u64,
+    /// The length of the record body, excluding the footer.
+    pub len: u64,
+}
+
+impl Trailer {
+    /// Returns the offset one past the end of the record's footer.
+    pub fn end(&self) -> u64 {
+        self.offset + self.len + FOOTER_LEN
+    }
+}
+
+/// Writes the footer terminating a record body of `body_len` bytes.
+pub fn write_footer<W: Write>(writer: &mut W, magic: &[u8; 4], body_len: u64) -> io::Result<()> {
+    writer.write_all(&body_len.to_le_bytes())?;
+    writer.write_all(magic)
+}
+
+/// Reads the record whose footer ends at `end`, if it is a known record type.
+pub fn read_trailer<R: Read + Seek>(source: &mut R, end: u64) -> io::Result<Option<Trailer>> {
+    if end < FOOTER_LEN {
+        return Ok(None);
+    }
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    source.seek(SeekFrom::Start(end - FOOTER_LEN))?;
+    source.read_exact(&mut footer)?;
+
+    let mut magic = [0u8; 4];
+    magic.copy_from_slice(&footer[8..]);
+    if !KNOWN_TRAILERS.contains(&&magic) {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(footer[..8].try_into().expect("footer length field"));
+    match (end - FOOTER_LEN).checked_sub(len) {
+        Some(offset) => Ok(Some(Trailer { magic, offset, len })),
+        None => Ok(None),
+    }
+}
+
+/// Lists all trailing records of `source`, outermost first.
+pub fn read_trailers<R: Read + Seek>(source: &mut R) -> io::Result<Vec<Trailer>> {
+    let mut end = source.seek(SeekFrom::End(0))?;
+    let mut trailers = Vec::new();
+    while let Some(trailer) = read_trailer(source, end)? {
+        end = trailer.offset;
+        trailers.push(trailer);
+    }
+    Ok(trailers)
+}
+
+/// Returns the start offset and length of the archive stream, skipping any
+/// leading self-extraction stub and any trailing records.
+pub fn payload_range<R: Read + Seek>(source: &mut R) -> io::Result<(u64, u64)> {
+    let trailers = read_trailers(source)?;
+    let end = match trailers.last() {
+        Some(innermost) => innermost.offset,
+        None => source.seek(SeekFrom::End(0))?,
+    };
+
+    let start = match trailers
+        .iter()
+        .find(|t| &t.magic == MAGIC_SFX && t.len == 8)
+    {
+        Some(sfx) => {
+            let mut offset = [0u8; 8];
+            source.seek(SeekFrom::Start(sfx.offset))?;
+            source.read_exact(&mut offset)?;
+            u64::from_le_bytes(offset)
+        },
+        None => 0,
+    };
+    if start > end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "self-extracting archive payload offset is out of range",
+        ));
+    }
+    Ok((start, end - start))
+}
+
+/// A reader exposing only the byte range `[start, start + len)` of `inner`.
+pub struct Window<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> Window<R> {
+    /// Restricts `inner` to `len` bytes starting at `start`.
+    pub fn new(mut inner: R, start: u64, len: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            len,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = (self.len - self.position.min(self.len)).min(buf.len() as u64) as usize;
+        if remaining == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..remaining])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+        self.inner.seek(SeekFrom::Start(self.start + target))?;
+        self.position = target;
+        Ok(target)
+    }
+}
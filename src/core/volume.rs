@@ -0,0 +1,423 @@
+//! # Multi-Volume Module
+//!
+//! This module splits an archive stream into fixed-size volumes and joins them
+//! back into one continuous stream. Volumes are named after the archive with a
+//! three-digit sequence suffix (`name.rz.001`, `name.rz.002`, ...).
+//!
+//! Every volume starts with a small header carrying a random set identifier,
+//! the volume's sequence number and a flag marking the last volume of the set.
+//! This lets the reader detect missing, foreign and out-of-order volumes before
+//! any data is handed to the decryption or decompression layers.
+
+use crate::utils::atomic::{self, AtomicFile, OutputFiles, StagedFile};
+use crate::utils::errors::RazeError;
+use crate::utils::security;
+use log::warn;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes identifying a Raze volume.
+pub const MAGIC_VOLUME: &[u8] = b"RZVL"; // Raze VoLume
+
+const SET_ID_LEN: usize = 16;
+const LAST_FLAG_OFFSET: u64 = (MAGIC_VOLUME.len() + SET_ID_LEN + 4) as u64;
+
+/// Size in bytes of the header written at the start of every volume.
+pub const VOLUME_HEADER_LEN: u64 = LAST_FLAG_OFFSET + 1;
+
+/// Returns the path of the volume with the given 1-based `index`.
+///
+/// For a base path of `backup.rz`, index `1` yields `backup.rz.001`.
+pub fn volume_path(base: &Path, index: u32) -> PathBuf {
+    let mut name = base.as_os_str().to_os_string();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+/// Returns `true` if the file at `path` starts with a volume header.
+pub fn is_volume(path: &Path) -> Result<bool, RazeError> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && magic == MAGIC_VOLUME)
+}
+
+struct VolumeHeader {
+    set_id: [u8; SET_ID_LEN],
+    index: u32,
+    last: bool,
+}
+
+impl VolumeHeader {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC_VOLUME)?;
+        writer.write_all(&self.set_id)?;
+        writer.write_all(&self.index.to_le_bytes())?;
+        writer.write_all(&[self.last as u8])
+    }
+
+    fn read_from(path: &Path, file: &mut File) -> Result<Self, RazeError> {
+        let mut magic = [0u8; 4];
+        let mut set_id = [0u8; SET_ID_LEN];
+        let mut index = [0u8; 4];
+        let mut last = [0u8; 1];
+        file.read_exact(&mut magic)
+            .and_then(|_| file.read_exact(&mut set_id))
+            .and_then(|_| file.read_exact(&mut index))
+            .and_then(|_| file.read_exact(&mut last))
+            .map_err(|_| {
+                RazeError::VolumeError(format!("'{}' has a truncated header", path.display()))
+            })?;
+        if magic != MAGIC_VOLUME {
+            return Err(RazeError::VolumeError(format!(
+                "'{}' is not a Raze volume",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            set_id,
+            index: u32::from_le_bytes(index),
+            last: last[0] != 0,
+        })
+    }
+}
+
+/// A writer that spreads its input over consecutive volume files.
+///
+/// Each volume, including its header, is at most `volume_size` bytes long.
+/// [`VolumeWriter::finish`] must be called once all data has been written so
+/// that the last volume is flagged as such.
+pub struct VolumeWriter {
+    base: PathBuf,
+    volume_size: u64,
+    set_id: [u8; SET_ID_LEN],
+    index: u32,
+    current: Option<AtomicFile>,
+    written: u64,
+    staged: Vec<StagedFile>,
+    no_clobber: bool,
+    outputs: OutputFiles,
+}
+
+impl VolumeWriter {
+    /// Creates a writer producing `base.001`, `base.002`, ... with volumes of
+    /// at most `volume_size` bytes.
+    pub fn new(base: impl AsRef<Path>, volume_size: u64) -> Result<Self, RazeError> {
+        if volume_size <= VOLUME_HEADER_LEN {
+            return Err(RazeError::VolumeError(format!(
+                "Volume size must be larger than {} bytes",
+                VOLUME_HEADER_LEN
+            )));
+        }
+        let mut set_id = [0u8; SET_ID_LEN];
+        security::fill_random(&mut set_id)?;
+        Ok(Self {
+            base: base.as_ref().to_path_buf(),
+            volume_size,
+            set_id,
+            index: 0,
+            current: None,
+            written: 0,
+            staged: Vec::new(),
+            no_clobber: false,
+            outputs: OutputFiles::default(),
+        })
+    }
+
+    /// Records each volume in `outputs` as soon as it is created.
+    pub fn tracked(mut self, outputs: OutputFiles) -> Self {
+        self.outputs = outputs;
+        self
+    }
+
+    /// Refuses to replace existing volumes when [`VolumeWriter::finish`]
+    /// renames the new ones into place.
+    pub fn no_clobber(mut self, no_clobber: bool) -> Self {
+        self.no_clobber = no_clobber;
+        self
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        if let Some(file) = self.current.take() {
+            self.staged.push(file.close()?);
+        }
+        self.index += 1;
+        let path = volume_path(&self.base, self.index);
+        let mut file = AtomicFile::create(&path)?;
+        self.outputs.add(file.as_file())?;
+        VolumeHeader {
+            set_id: self.set_id,
+            index: self.index,
+            last: false,
+        }
+        .write_to(&mut file)?;
+        self.current = Some(file);
+        self.written = VOLUME_HEADER_LEN;
+        Ok(())
+    }
+
+    /// Flags the final volume, renames all volumes into place and returns their paths.
+    ///
+    /// The volumes are written under temporary names and flushed to disk
+    /// before any of them is renamed, so nothing appears at their final paths
+    /// until all of them are complete. The volumes they replace, including
+    /// higher-numbered ones left over from a larger set, are set aside first
+    /// and only removed once every new volume is in place; if a rename fails,
+    /// the new volumes are removed again and the old set is put back.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, RazeError> {
+        if self.current.is_none() {
+            self.open_next()?;
+        }
+        if let Some(mut file) = self.current.take() {
+            file.seek(SeekFrom::Start(LAST_FLAG_OFFSET))?;
+            file.write_all(&[1])?;
+            self.staged.push(file.close()?);
+        }
+        let count = self.staged.len() as u32;
+        if self.no_clobber {
+            let existing = (1..=count)
+                .map(|index| volume_path(&self.base, index))
+                .find(|path| path.exists());
+            if let Some(path) = existing {
+                return Err(atomic::refuse_to_replace(&path).into());
+            }
+        }
+
+        let mut aside = Vec::new();
+        for path in self.replaced_volumes(count) {
+            match atomic::set_aside(&path) {
+                Ok(temp) => aside.push((temp, path)),
+                Err(e) => {
+                    restore_volumes(aside);
+                    return Err(e.into());
+                },
+            }
+        }
+        let mut paths = Vec::with_capacity(self.staged.len());
+        for staged in self.staged {
+            match staged.commit(self.no_clobber) {
+                Ok(path) => paths.push(path),
+                Err(e) => {
+                    for path in &paths {
+                        let _ = fs::remove_file(path);
+                    }
+                    restore_volumes(aside);
+                    return Err(e.into());
+                },
+            }
+        }
+        // Dropping the volumes set aside removes the replaced set.
+        drop(aside);
+        Ok(paths)
+    }
+
+    /// Returns the existing files that `count` new volumes replace: those at
+    /// their paths, and any higher-numbered volumes belonging to the same set
+    /// as the existing first volume. Directories are left alone.
+    fn replaced_volumes(&self, count: u32) -> Vec<PathBuf> {
+        let is_file = |path: &Path| fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir());
+        let mut replaced: Vec<_> = (1..=count)
+            .map(|index| volume_path(&self.base, index))
+            .filter(|path| is_file(path))
+            .collect();
+        if let Some(old_set) = set_id(&volume_path(&self.base, 1)) {
+            let stale = (count + 1..)
+                .map(|index| volume_path(&self.base, index))
+                .take_while(|path| set_id(path) == Some(old_set));
+            replaced.extend(stale);
+        }
+        replaced
+    }
+}
+
+/// Returns the set identifier of the volume at `path`, or `None` if there is
+/// no readable volume there.
+fn set_id(path: &Path) -> Option<[u8; SET_ID_LEN]> {
+    let mut file = File::open(path).ok()?;
+    VolumeHeader::read_from(path, &mut file)
+        .ok()
+        .map(|header| header.set_id)
+}
+
+/// Puts volumes set aside by [`VolumeWriter::finish`] back in place.
+fn restore_volumes(aside: Vec<(tempfile::TempPath, PathBuf)>) {
+    for (temp, path) in aside {
+        if let Err(e) = atomic::restore(temp, &path) {
+            warn!("Could not restore the volume '{}': {}", path.display(), e);
+        }
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.written >= self.volume_size {
+            self.open_next()?;
+        }
+        let room = (self.volume_size - self.written).min(buf.len() as u64) as usize;
+        let file = self.current.as_mut().expect("volume is open");
+        let n = file.write(&buf[..room])?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Volume {
+    file: File,
+    len: u64,
+}
+
+/// A reader presenting a complete volume set as one continuous stream.
+///
+/// All volumes are located, opened and validated when the reader is opened,
+/// so a missing, foreign or out-of-order volume is reported before any
+/// payload byte is read. The volumes are only ever read through the handles
+/// opened then, so a volume replaced afterwards, for instance once the
+/// signature of the set has been verified, is never read.
+pub struct VolumeReader {
+    volumes: Vec<Volume>,
+    total_len: u64,
+    position: u64,
+    current: Option<usize>,
+}
+
+impl VolumeReader {
+    /// Opens the volume set starting at `first`, which must be volume `001`.
+    pub fn open(first: impl AsRef<Path>) -> Result<Self, RazeError> {
+        let first = first.as_ref();
+        let base = base_path(first)?;
+
+        let mut volumes = Vec::new();
+        let mut set_id = None;
+        let mut index = 1;
+        loop {
+            let path = if index == 1 {
+                first.to_path_buf()
+            } else {
+                volume_path(&base, index)
+            };
+            if !path.exists() {
+                return Err(RazeError::VolumeError(format!(
+                    "Volume {} ('{}') is missing",
+                    index,
+                    path.display()
+                )));
+            }
+
+            let mut file = File::open(&path)?;
+            let header = VolumeHeader::read_from(&path, &mut file)?;
+            if *set_id.get_or_insert(header.set_id) != header.set_id {
+                return Err(RazeError::VolumeError(format!(
+                    "'{}' belongs to a different archive",
+                    path.display()
+                )));
+            }
+            if header.index != index {
+                return Err(RazeError::VolumeError(format!(
+                    "'{}' is out of order: expected volume {}, found volume {}",
+                    path.display(),
+                    index,
+                    header.index
+                )));
+            }
+
+            let len = file.metadata()?.len() - VOLUME_HEADER_LEN;
+            volumes.push(Volume { file, len });
+            if header.last {
+                break;
+            }
+            index += 1;
+        }
+
+        let total_len = volumes.iter().map(|v| v.len).sum();
+        Ok(Self {
+            volumes,
+            total_len,
+            position: 0,
+            current: None,
+        })
+    }
+
+    /// Returns the number of volumes in the set.
+    pub fn volume_count(&self) -> usize {
+        self.volumes.len()
+    }
+
+    /// Maps the logical stream position onto a volume and an offset within it.
+    fn locate(&self, position: u64) -> Option<(usize, u64)> {
+        let mut start = 0;
+        for (i, volume) in self.volumes.iter().enumerate() {
+            if position < start + volume.len {
+                return Some((i, position - start));
+            }
+            start += volume.len;
+        }
+        None
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some((index, offset)) = self.locate(self.position) else {
+            return Ok(0);
+        };
+        let volume = &mut self.volumes[index];
+        if self.current != Some(index) {
+            volume
+                .file
+                .seek(SeekFrom::Start(VOLUME_HEADER_LEN + offset))?;
+            self.current = Some(index);
+        }
+        let remaining = (volume.len - offset).min(buf.len() as u64) as usize;
+        let n = volume.file.read(&mut buf[..remaining])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.total_len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+
+        if let Some((index, offset)) = self.locate(target) {
+            if self.current == Some(index) {
+                self.volumes[index]
+                    .file
+                    .seek(SeekFrom::Start(VOLUME_HEADER_LEN + offset))?;
+            }
+        }
+        self.position = target;
+        Ok(target)
+    }
+}
+
+/// Derives the archive's base path from the path of its first volume.
+fn base_path(first: &Path) -> Result<PathBuf, RazeError> {
+    match first.extension().and_then(|e| e.to_str()) {
+        Some("001") => Ok(first.with_extension("")),
+        Some(ext) if ext.len() == 3 && ext.bytes().all(|b| b.is_ascii_digit()) => {
+            Err(RazeError::VolumeError(format!(
+                "'{}' is not the first volume; open the '.001' volume instead",
+                first.display()
+            )))
+        },
+        _ => Err(RazeError::VolumeError(format!(
+            "'{}' does not follow the '<archive>.001' volume naming",
+            first.display()
+        ))),
+    }
+}
@@ -24,11 +24,25 @@
 //! The library is organized into the following key modules:
 //!
 //! - `core`: Contains the fundamental logic for compression and decompression.
+//!   - `core::beneath`: Creates extracted entries beneath the destination on Linux (internal).
 //!   - `core::compress`: Implements the `pack` function for creating `.rz` archives.
+//!   - `core::convert`: Converts between Raze archives and ZIP files (`zip` feature).
 //!   - `core::decompress`: Implements the `unpack` function for extracting `.rz` archives.
+//!   - `core::extract`: Writes archive entries to disk under a hardened extraction policy.
+//!   - `core::format`: Detects and encodes the supported tarball formats.
+//!   - `core::recovery`: Appends Reed-Solomon recovery records and repairs damaged archives.
+//!   - `core::rekey`: Changes the passwords and key files of encrypted archives.
+//!   - `core::sfx`: Builds self-extracting archives from the `raze-sfx` stub.
+//!   - `core::signature`: Signs archives with Ed25519 and verifies their signatures.
+//!   - `core::trailer`: Locates optional records stored after the archive stream.
+//!   - `core::volume`: Splits archives into fixed-size volumes and joins them back together.
 //! - `utils`: Provides utility functions, error definitions, and logging setup.
+//!   - `utils::atomic`: Writes output files that appear complete or not at all.
 //!   - `utils::errors`: Defines custom error types (`RazeError`) for the library.
 //!   - `utils::logger`: Handles the initialization and configuration of the logging environment.
+//!   - `utils::secret`: Wipes passwords, keys and plaintext from memory and keeps them out of core dumps.
+//!   - `utils::security`: Implements the encryption and decryption logic.
+//!   - `utils::space`: Checks for free space before archives are written or extracted.
 //!
 //! ## Usage
 //!
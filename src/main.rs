@@ -13,9 +13,18 @@ mod cli; // Declares the `cli` module, containing argument parsing logic.
 
 use self::cli::args::RazeArgs;
 use clap::Parser;
-use log::error;
-use raze::core::{compress, decompress};
-use raze::utils::{errors::RazeError, logger};
+use log::{error, info, warn};
+use raze::core::compress::{self, PackOptions};
+#[cfg(feature = "zip")]
+use raze::core::convert;
+use raze::core::decompress::{self, UnpackOptions};
+use raze::core::extract::{ExtractLimits, ExtractPermissions, ExtractPolicy};
+use raze::core::format::ArchiveFormat;
+use raze::core::rekey::{self, NewKey};
+use raze::core::signature::{self, SigningKey};
+use raze::core::{recovery, sfx};
+use raze::utils::security::{self, Credentials, Identity, KdfParams, KeyFile};
+use raze::utils::{errors::RazeError, logger, secret};
 use std::path::PathBuf;
 
 /// The main entry point for the Raze CLI application.
@@ -36,7 +45,16 @@ fn main() {
     logger::init();
 
     // Parse command-line arguments provided by the user and execute the main logic.
-    if let Err(e) = run(RazeArgs::parse()) {
+    let args = RazeArgs::parse();
+
+    // Passwords and keys are held until the process exits; keep them out of core dumps.
+    if holds_secrets(&args) {
+        if let Err(e) = secret::disable_core_dumps() {
+            warn!("Could not disable core dumps: {}", e);
+        }
+    }
+
+    if let Err(e) = run(args) {
         // If an operation fails, log the error message and exit with an error code.
         error!("Operation failed: {}", e);
         std::process::exit(1);
@@ -46,8 +64,9 @@ fn main() {
 /// Executes the main application logic based on the parsed command-line arguments.
 ///
 /// This function acts as the central dispatcher for Raze's operations. It matches
-/// the provided subcommand (e.g., `Pack` or `Unpack`) and calls the corresponding
-/// function from the `raze::core` library to perform the archiving task.
+/// the provided mode flag (`--pack`, `--unpack`, `--list`, `--test`, `--repair`, `--convert`,
+/// `--keygen`, `--rekey`, `--add-password` or `--remove-password`) and calls the corresponding function from the `raze::core` library
+/// to perform the archiving task.
 ///
 /// # Arguments
 ///
@@ -60,6 +79,7 @@ fn main() {
 /// if any part of the archiving or compression/decompression process fails.
 fn run(args: RazeArgs) -> Result<(), RazeError> {
     if args.pack {
+        let options = pack_options(&args)?;
         let source = args.source.ok_or_else(|| {
             RazeError::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -73,12 +93,10 @@ fn run(args: RazeArgs) -> Result<(), RazeError> {
             ))
         })?;
 
-        let mut output_path = PathBuf::from(output);
-        if output_path.extension().is_none() || output_path.extension().unwrap() != "rz" {
-            output_path.set_extension("rz");
-        }
-        compress::pack(source, output_path, args.password.as_deref())
+        let output_path = archive_output_path(output, &options);
+        compress::pack_with_options(source, output_path, &options)
     } else if args.unpack {
+        let options = unpack_options(&args)?;
         let archive = args.archive.ok_or_else(|| {
             RazeError::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -87,8 +105,260 @@ fn run(args: RazeArgs) -> Result<(), RazeError> {
         })?;
         let destination = args.destination.unwrap_or_else(|| ".".to_string());
 
-        decompress::unpack(archive, destination, args.password.as_deref())
+        decompress::unpack_with_options(archive, destination, &options)
+    } else if args.list || args.test {
+        let options = unpack_options(&args)?;
+        let archive = args.archive.ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing archive path.",
+            ))
+        })?;
+
+        if args.test {
+            return decompress::test_with_options(archive, &options);
+        }
+        for entry in decompress::list_with_options(archive, &options)? {
+            if entry.is_dir {
+                println!("{:>12}  {}/", "-", entry.path.display());
+            } else {
+                println!("{:>12}  {}", entry.size, entry.path.display());
+            }
+        }
+        Ok(())
+    } else if args.repair {
+        let archive = args.archive.ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing archive path for repairing.",
+            ))
+        })?;
+
+        let report = recovery::repair(&archive)?;
+        if report.damaged_blocks == 0 {
+            info!(
+                "No damage found in '{}' ({} blocks checked).",
+                archive, report.checked_blocks
+            );
+        } else {
+            info!(
+                "Repaired {} of {} damaged blocks ({} bytes corrected) in '{}'.",
+                report.repaired_blocks, report.damaged_blocks, report.repaired_bytes, archive
+            );
+        }
+        Ok(())
+    } else if args.convert {
+        let input = args.archive.clone().ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing input path for converting.",
+            ))
+        })?;
+        let output = args.output.clone().ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing output path for converting.",
+            ))
+        })?;
+
+        convert_archive(input, output, &args)
+    } else if args.keygen {
+        let output = args.output.ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing output path for the key file.",
+            ))
+        })?;
+
+        if args.signing {
+            let key = SigningKey::generate()?;
+            signature::write_signing_key(&output, &key)?;
+            info!("Wrote a new signing key to '{}'. Keep it secret.", output);
+            println!("{}", key.verifying_key());
+            return Ok(());
+        }
+        let identity = Identity::generate()?;
+        security::write_identity_file(&output, &identity)?;
+        info!("Wrote a new identity to '{}'. Keep it secret.", output);
+        println!("{}", identity.recipient());
+        Ok(())
+    } else if args.rekey || args.add_password || args.remove_password {
+        let credentials = unpack_options(&args)?.credentials;
+        let archive = args.archive.clone().ok_or_else(|| {
+            RazeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Missing archive path.",
+            ))
+        })?;
+
+        if args.remove_password {
+            return rekey::remove_password(archive, &credentials, args.force);
+        }
+        let new_key = NewKey {
+            password: args.new_password.clone(),
+            key_file: args.new_keyfile.as_deref().map(KeyFile::read).transpose()?,
+            kdf: kdf_params(&args),
+        };
+        if args.rekey {
+            rekey::rekey(archive, &credentials, &new_key, args.force)
+        } else {
+            rekey::add_password(archive, &credentials, &new_key, args.force)
+        }
     } else {
         unreachable!();
     }
 }
+
+/// Returns `true` if the arguments give or generate a password or secret key.
+fn holds_secrets(args: &RazeArgs) -> bool {
+    args.password.is_some()
+        || args.new_password.is_some()
+        || args.keyfile.is_some()
+        || args.new_keyfile.is_some()
+        || !args.identities.is_empty()
+        || args.sign.is_some()
+        || args.keygen
+}
+
+/// Builds the options for writing an archive from the command-line arguments.
+fn pack_options(args: &RazeArgs) -> Result<PackOptions, RazeError> {
+    Ok(PackOptions {
+        password: args.password.clone(),
+        key_file: key_file(args)?,
+        recipients: args.recipients.clone(),
+        volume_size: args.volume_size,
+        recovery_percent: args.recovery,
+        sfx_stub: if args.sfx {
+            Some(sfx::find_stub()?)
+        } else {
+            None
+        },
+        format: args.format.unwrap_or_default(),
+        kdf: kdf_params(args),
+        cipher: args.cipher.unwrap_or_default(),
+        padding: args.pad.unwrap_or_default(),
+        signing_key: args
+            .sign
+            .as_deref()
+            .map(signature::read_signing_key)
+            .transpose()?,
+        special_files: args.allow_special_files,
+        no_clobber: args.no_clobber,
+        check_space: !(args.force || args.no_space_check),
+    })
+}
+
+/// Builds the options for reading an archive from the command-line arguments,
+/// loading any identity files given with `--identity` and trusted keys given
+/// with `--trusted-keys`.
+fn unpack_options(args: &RazeArgs) -> Result<UnpackOptions, RazeError> {
+    let mut identities = Vec::new();
+    for path in &args.identities {
+        identities.extend(security::read_identity_file(path)?);
+    }
+    let mut trusted_keys = args.verify_keys.clone();
+    if let Some(dir) = &args.trusted_keys {
+        trusted_keys.extend(signature::read_trusted_keys(dir)?);
+    }
+    Ok(UnpackOptions {
+        credentials: Credentials {
+            password: args.password.clone(),
+            key_file: key_file(args)?,
+            identities,
+        },
+        trusted_keys,
+        policy: ExtractPolicy {
+            allow_special_files: args.allow_special_files,
+            ..if args.unsafe_paths {
+                ExtractPolicy::permissive()
+            } else {
+                ExtractPolicy::default()
+            }
+        },
+        limits: extract_limits(args),
+        permissions: ExtractPermissions {
+            preserve: args.preserve_permissions,
+            umask: args.umask.unwrap_or_default(),
+            same_owner: args.same_owner,
+        },
+        atomic: args.atomic,
+        check_space: !(args.force || args.no_space_check),
+    })
+}
+
+/// Builds the extraction limits from `--no-limits` and the individual `--max-*` options.
+fn extract_limits(args: &RazeArgs) -> ExtractLimits {
+    let limits = if args.no_limits {
+        ExtractLimits::unlimited()
+    } else {
+        ExtractLimits::default()
+    };
+    ExtractLimits {
+        max_total_size: args.max_total_size.or(limits.max_total_size),
+        max_entries: args.max_entries.or(limits.max_entries),
+        max_file_size: args.max_file_size.or(limits.max_file_size),
+        max_ratio: args.max_ratio.or(limits.max_ratio),
+        max_depth: args.max_depth.or(limits.max_depth),
+    }
+}
+
+/// Reads the key file given with `--keyfile`, if any.
+fn key_file(args: &RazeArgs) -> Result<Option<KeyFile>, RazeError> {
+    args.keyfile.as_deref().map(KeyFile::read).transpose()
+}
+
+/// Builds the Argon2id parameters from the KDF profile and any individual overrides.
+fn kdf_params(args: &RazeArgs) -> KdfParams {
+    let profile = args.kdf_profile.unwrap_or_default().params();
+    KdfParams {
+        memory_kib: args.kdf_memory.unwrap_or(profile.memory_kib),
+        iterations: args.kdf_iterations.unwrap_or(profile.iterations),
+        parallelism: args.kdf_parallelism.unwrap_or(profile.parallelism),
+    }
+}
+
+/// Returns the path of a new archive, adding the file name suffix of its format
+/// if `output` does not already end with it.
+fn archive_output_path(output: String, options: &PackOptions) -> PathBuf {
+    let suffix = format!(".{}", options.format.extension());
+    if options.sfx_stub.is_some() {
+        // Self-extracting archives are executables and keep the name they were given.
+        PathBuf::from(output)
+    } else if options.format == ArchiveFormat::TarZst {
+        let mut output_path = PathBuf::from(output);
+        if output_path.extension().is_none() || output_path.extension().unwrap() != "rz" {
+            output_path.set_extension("rz");
+        }
+        output_path
+    } else if output.ends_with(&suffix) {
+        PathBuf::from(output)
+    } else {
+        PathBuf::from(output + &suffix)
+    }
+}
+
+/// Converts `input` to ZIP, or from ZIP into a new archive, naming the output accordingly.
+#[cfg(feature = "zip")]
+fn convert_archive(input: String, output: String, args: &RazeArgs) -> Result<(), RazeError> {
+    if convert::is_zip(&input)? {
+        let options = pack_options(args)?;
+        let output_path = archive_output_path(output, &options);
+        convert::zip_to_rz(input, output_path, &options)
+    } else {
+        let output_path = if output.to_ascii_lowercase().ends_with(".zip") {
+            PathBuf::from(output)
+        } else {
+            PathBuf::from(output + ".zip")
+        };
+        let credentials = unpack_options(args)?.credentials;
+        convert::rz_to_zip(input, output_path, &credentials)
+    }
+}
+
+/// Reports that ZIP conversion was not compiled into this build.
+#[cfg(not(feature = "zip"))]
+fn convert_archive(_input: String, _output: String, _args: &RazeArgs) -> Result<(), RazeError> {
+    Err(RazeError::ConversionError(
+        "Raze was built without the `zip` feature".to_string(),
+    ))
+}
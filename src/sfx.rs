@@ -0,0 +1,131 @@
+//! # Raze Self-Extraction Stub
+//!
+//! This is the minimal extraction program that `raze --pack --sfx` prepends to
+//! an archive to produce a self-extracting executable. When run, it locates the
+//! archive appended to its own executable file and extracts it into the current
+//! directory, or into the directory given with `--dest`.
+//!
+//! An encrypted archive is opened with the key file given with `--keyfile` or
+//! `RAZE_KEYFILE`, or with the identity file given with `--identity` or
+//! `RAZE_IDENTITY`. The password is read from the terminal only if none of
+//! these open the archive and one of its key slots takes a password.
+
+use log::{error, warn};
+use raze::core::decompress::{self, UnpackOptions};
+use raze::utils::secret::{self, Secret};
+use raze::utils::security::{self, Credentials, KeyFile, SlotKind};
+use raze::utils::{errors::RazeError, logger};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: <archive> [--dest <DIRECTORY>] [--keyfile <FILE>] [--identity <FILE>]";
+
+/// The entry point of the self-extraction stub.
+fn main() {
+    logger::init();
+
+    if let Err(e) = run() {
+        error!("Extraction failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Parses the stub's arguments and extracts the embedded archive.
+fn run() -> Result<(), RazeError> {
+    let mut destination = PathBuf::from(".");
+    let mut key_file = std::env::var_os("RAZE_KEYFILE").map(PathBuf::from);
+    let mut identity = std::env::var_os("RAZE_IDENTITY").map(PathBuf::from);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" | "--dest" => destination = value(&mut args, "directory", &arg)?,
+            "-k" | "--keyfile" => key_file = Some(value(&mut args, "file", &arg)?),
+            "-i" | "--identity" => identity = Some(value(&mut args, "file", &arg)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            },
+            _ => {
+                return Err(RazeError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Unexpected argument '{}'. {}", arg, USAGE),
+                )))
+            },
+        }
+    }
+
+    let executable = std::env::current_exe()?;
+    let mut credentials = Credentials::default();
+    if let Some(slots) = decompress::slot_kinds(&executable)? {
+        if let Err(e) = secret::disable_core_dumps() {
+            warn!("Could not disable core dumps: {}", e);
+        }
+        credentials.key_file = key_file.as_deref().map(KeyFile::read).transpose()?;
+        if let Some(path) = &identity {
+            credentials.identities = security::read_identity_file(path)?;
+        }
+        if needs_password(&slots, &credentials)? {
+            let password = Secret::new(&mut rpassword::prompt_password("Password: ")?);
+            credentials.password = Some(password);
+        }
+    }
+
+    let options = UnpackOptions {
+        credentials,
+        ..UnpackOptions::default()
+    };
+    decompress::unpack_with_options(&executable, destination, &options)
+}
+
+/// Returns the path following the option `arg`, which names a `what`.
+fn value(
+    args: &mut impl Iterator<Item = String>,
+    what: &str,
+    arg: &str,
+) -> Result<PathBuf, RazeError> {
+    args.next().map(PathBuf::from).ok_or_else(|| {
+        RazeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Missing {} after {}.", what, arg),
+        ))
+    })
+}
+
+/// Decides whether the password has to be read to open one of `slots`.
+///
+/// No password is asked for if the given key file or identity opens a slot on
+/// its own, or if no slot takes a password that could be used together with
+/// the given key file. In the latter case the credentials the archive needs
+/// are reported instead.
+fn needs_password(slots: &[SlotKind], credentials: &Credentials) -> Result<bool, RazeError> {
+    let has_key_file = credentials.key_file.is_some();
+    let has_identity = !credentials.identities.is_empty();
+    let opens_without_password = slots.iter().any(|slot| match *slot {
+        SlotKind::Secret { password, key_file } => !password && key_file && has_key_file,
+        SlotKind::Recipient => has_identity,
+    });
+    if opens_without_password {
+        return Ok(false);
+    }
+    let takes_password = slots.iter().any(|slot| match *slot {
+        SlotKind::Secret { password, key_file } => password && (!key_file || has_key_file),
+        SlotKind::Recipient => false,
+    });
+    if takes_password {
+        return Ok(true);
+    }
+
+    let mut needed = Vec::new();
+    if slots
+        .iter()
+        .any(|slot| matches!(slot, SlotKind::Secret { key_file: true, .. }))
+    {
+        needed.push("a key file (--keyfile or RAZE_KEYFILE)");
+    }
+    if slots.contains(&SlotKind::Recipient) {
+        needed.push("an identity (--identity or RAZE_IDENTITY)");
+    }
+    Err(RazeError::CryptoError(format!(
+        "The archive is encrypted and can only be opened with {}",
+        needed.join(" or ")
+    )))
+}
@@ -0,0 +1,225 @@
+//! # Atomic File Module
+//!
+//! This module writes output files so that they either appear complete or not
+//! at all. An [`AtomicFile`] is written under a hidden temporary name in the
+//! directory of its final path, flushed to disk and only then renamed into
+//! place. If writing fails, the temporary file is removed and whatever already
+//! existed at the final path is left untouched.
+//!
+//! [`OutputFiles`] records the identity of the files being written, so that
+//! packing a directory that contains the output can leave them out.
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tempfile::TempPath;
+
+/// A file written under a temporary name and renamed to its final path by
+/// [`AtomicFile::commit`].
+///
+/// Dropping it without committing removes the temporary file.
+pub struct AtomicFile {
+    file: File,
+    staged: StagedFile,
+}
+
+impl AtomicFile {
+    /// Creates an empty temporary file next to `path`, which must not be a directory.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a file path", path.display()),
+            )
+        })?;
+        let prefix = format!(".{}.", name.to_string_lossy());
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&prefix).suffix(".tmp");
+        // Give the file the mode `File::create` would, rather than the owner-only default.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(std::fs::Permissions::from_mode(0o666));
+        }
+        let (file, temp) = builder.tempfile_in(dir)?.into_parts();
+        Ok(AtomicFile {
+            file,
+            staged: StagedFile {
+                temp,
+                path: path.to_path_buf(),
+            },
+        })
+    }
+
+    /// Returns the path of the temporary file being written.
+    pub fn temp_path(&self) -> &Path {
+        &self.staged.temp
+    }
+
+    /// Returns the temporary file being written.
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns the temporary file being written, for edits that need a `File`.
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Flushes the file to disk and closes it, keeping its temporary name
+    /// until the returned [`StagedFile`] is committed.
+    pub fn close(self) -> io::Result<StagedFile> {
+        self.file.sync_all()?;
+        Ok(self.staged)
+    }
+
+    /// Flushes the file to disk and renames it to its final path. With
+    /// `no_clobber`, an existing file at that path is never replaced.
+    pub fn commit(self, no_clobber: bool) -> io::Result<PathBuf> {
+        self.close()?.commit(no_clobber)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+/// A complete file on disk under its temporary name, waiting to be renamed
+/// to its final path. Dropping it removes the file.
+pub struct StagedFile {
+    temp: TempPath,
+    path: PathBuf,
+}
+
+impl StagedFile {
+    /// Renames the file to its final path and returns that path. With
+    /// `no_clobber`, an existing file at that path is never replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `io::ErrorKind::AlreadyExists` if `no_clobber`
+    /// is set and the final path already exists.
+    pub fn commit(self, no_clobber: bool) -> io::Result<PathBuf> {
+        let StagedFile { temp, path } = self;
+        let result = if no_clobber {
+            temp.persist_noclobber(&path)
+        } else {
+            temp.persist(&path)
+        };
+        if let Err(e) = result {
+            if no_clobber && e.error.kind() == io::ErrorKind::AlreadyExists {
+                return Err(refuse_to_replace(&path));
+            }
+            return Err(e.error);
+        }
+        sync_parent(&path)?;
+        Ok(path)
+    }
+}
+
+/// Moves the file at `path` aside to a hidden temporary name next to it, so
+/// that it can be put back with [`restore`] if replacing it fails. Dropping
+/// the returned path removes the file.
+pub fn set_aside(path: &Path) -> io::Result<TempPath> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    let temp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", name.to_string_lossy()))
+        .suffix(".old")
+        .tempfile_in(dir)?
+        .into_temp_path();
+    fs::rename(path, &temp)?;
+    Ok(temp)
+}
+
+/// Moves a file set aside by [`set_aside`] back to `path`.
+pub fn restore(temp: TempPath, path: &Path) -> io::Result<()> {
+    temp.persist(path).map_err(|e| e.error)
+}
+
+/// Returns the error for an existing file at `path` that must not be replaced.
+pub fn refuse_to_replace(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("Refusing to replace the existing file '{}'", path.display()),
+    )
+}
+
+/// Flushes the directory entry of `path` to disk, so that the rename survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened for syncing on non-Unix platforms.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The identities of the files being written, shared between the writer and
+/// a directory walk running at the same time.
+///
+/// Files are identified by device and inode number rather than by path, so
+/// that links and differently spelled paths are recognised too. On platforms
+/// without inode numbers nothing is recorded.
+#[derive(Debug, Clone, Default)]
+pub struct OutputFiles(Rc<RefCell<Vec<(u64, u64)>>>);
+
+impl OutputFiles {
+    /// Records the open file `file`.
+    pub fn add(&self, file: &File) -> io::Result<()> {
+        if let Some(id) = file_id(&file.metadata()?) {
+            self.0.borrow_mut().push(id);
+        }
+        Ok(())
+    }
+
+    /// Records the file at `path`, if there is one.
+    pub fn add_path(&self, path: &Path) {
+        if let Some(id) = fs::metadata(path).ok().as_ref().and_then(file_id) {
+            self.0.borrow_mut().push(id);
+        }
+    }
+
+    /// Returns `true` if `metadata` belongs to one of the recorded files.
+    pub fn contains(&self, metadata: &fs::Metadata) -> bool {
+        file_id(metadata).is_some_and(|id| self.0.borrow().contains(&id))
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
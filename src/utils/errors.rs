@@ -51,4 +51,93 @@ pub enum RazeError {
     /// Indicates a cryptographic error.
     #[error("Cryptographic error: {0}")]
     CryptoError(String),
+
+    /// Indicates that an encrypted archive has been cut short.
+    ///
+    /// This error is returned when the encrypted stream ends before its
+    /// authenticated final chunk, for example after a failed upload or when
+    /// trailing chunks were removed deliberately.
+    #[error("Truncated archive: {0}")]
+    TruncationError(String),
+
+    /// Indicates a problem with a multi-volume archive.
+    ///
+    /// This error is returned when a volume of a split archive is missing,
+    /// belongs to a different archive, or is found out of sequence.
+    #[error("Volume error: {0}")]
+    VolumeError(String),
+
+    /// Indicates a problem with an archive's recovery record.
+    ///
+    /// This error is returned when an archive has no usable recovery record,
+    /// or when it is too damaged to be rebuilt from the available parity data.
+    #[error("Recovery error: {0}")]
+    RecoveryError(String),
+
+    /// Indicates that an archive's signature could not be verified.
+    ///
+    /// This error is returned when an archive that must be signed carries no
+    /// signature, is signed by a key that is not trusted, or has been modified
+    /// since it was signed.
+    #[error("Signature error: {0}")]
+    SignatureError(String),
+
+    /// Indicates a failure while converting between archive formats.
+    ///
+    /// This error is returned when a ZIP file cannot be read or written, or
+    /// when an entry cannot be represented in the target format.
+    #[error("Conversion error: {0}")]
+    ConversionError(String),
+
+    /// Indicates that an archive entry has an absolute path or a path
+    /// containing `..` components.
+    ///
+    /// This error is returned by extraction under the default
+    /// [`ExtractPolicy`](crate::core::extract::ExtractPolicy), before anything
+    /// is written for the entry.
+    #[error("Refusing to extract '{0}': absolute paths and '..' components are not allowed")]
+    UnsafePath(String),
+
+    /// Indicates that a symbolic or hard link in an archive points outside
+    /// the destination directory.
+    ///
+    /// `path` is the entry that holds the link and `target` the path it points to.
+    #[error(
+        "Refusing to extract '{path}': its link target '{target}' points outside the destination"
+    )]
+    LinkEscape { path: String, target: String },
+
+    /// Indicates that an archive entry would be written through a symbolic
+    /// link, such as one created by an earlier entry.
+    ///
+    /// `path` is the entry being extracted and `link` the symbolic link among
+    /// its parent directories.
+    #[error(
+        "Refusing to extract '{path}': it would be written through the symbolic link '{link}'"
+    )]
+    SymlinkTraversal { path: String, link: String },
+
+    /// Indicates that an archive holds a block or character device or a FIFO.
+    ///
+    /// This error is returned by extraction unless the
+    /// [`ExtractPolicy`](crate::core::extract::ExtractPolicy) allows special
+    /// files, before anything is written for the entry.
+    #[error("Refusing to extract '{0}': device nodes and FIFOs are not allowed")]
+    SpecialFile(String),
+
+    /// Indicates that an archive exceeds one of the limits on extraction,
+    /// such as its total size, number of entries or compression ratio.
+    ///
+    /// This error is returned while the archive is being extracted, as soon as
+    /// the limit is exceeded, so that a decompression bomb cannot fill the disk.
+    /// See [`ExtractLimits`](crate::core::extract::ExtractLimits).
+    #[error("Extraction limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// Indicates that the destination filesystem lacks the space needed.
+    ///
+    /// This error is returned by the free-space check that packing and
+    /// unpacking run before they start writing, if it is enabled.
+    #[error("Not enough free space: {0}")]
+    InsufficientSpace(String),
 }
@@ -6,10 +6,21 @@
 //! promoting modularity and maintainability.
 //!
 //! The module is structured into the following sub-modules:
+//! - `atomic`: Writes output files under a temporary name and renames them into
+//!   place once complete, so that a failed write never leaves a partial file.
 //! - `errors`: Defines a comprehensive set of custom error types (`RazeError`)
 //!   used for consistent error reporting throughout the application.
 //! - `logger`: Handles the initialization and configuration of the application's
 //!   logging system, allowing for configurable diagnostic output.
+//! - `secret`: Holds passwords, keys and plaintext in memory that is wiped when
+//!   dropped, and keeps them out of swap and core dumps.
+//! - `security`: Implements password-based encryption, including the streaming
+//!   `EncryptWriter` and `DecryptReader` adapters.
+//! - `space`: Checks that a filesystem has room for an archive or its
+//!   contents before writing starts.
+pub mod atomic;
 pub mod errors;
 pub mod logger;
+pub mod secret;
 pub mod security;
+pub mod space;
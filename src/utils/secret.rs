@@ -0,0 +1,257 @@
+//! # Secret Module
+//!
+//! This module keeps passwords, keys and decrypted data from leaking out of the
+//! process. A [`Secret`] takes its value out of the caller's variable, wiping
+//! what it leaves behind, holds it on the heap, where moving it leaves no
+//! copies behind, and overwrites it with zeros when it is dropped. With the
+//! `mlock` feature, which is enabled by default, its memory is also locked so
+//! that it is never written to swap.
+//!
+//! [`disable_core_dumps`] keeps secrets out of core dumps, for as long as the
+//! process runs.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use zeroize::Zeroize;
+
+/// A value that a [`Secret`] can hold: one whose memory can be located, moved
+/// to the heap without leaving a copy, and overwritten with [`Zeroize`].
+///
+/// It is implemented for byte arrays, byte vectors and strings.
+pub trait SecretValue: Zeroize {
+    /// Returns the address and length of the memory holding the value,
+    /// including any unused capacity.
+    fn memory(&self) -> (*const u8, usize);
+
+    /// Moves the value into a new heap allocation, leaving `self` empty or
+    /// zeroed.
+    fn take(&mut self) -> Box<Self>;
+}
+
+impl<const N: usize> SecretValue for [u8; N] {
+    fn memory(&self) -> (*const u8, usize) {
+        (self.as_ptr(), N)
+    }
+
+    fn take(&mut self) -> Box<Self> {
+        let mut value = Box::new([0u8; N]);
+        value.copy_from_slice(self);
+        self.zeroize();
+        value
+    }
+}
+
+impl SecretValue for Vec<u8> {
+    fn memory(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+
+    fn take(&mut self) -> Box<Self> {
+        Box::new(mem::take(self))
+    }
+}
+
+impl SecretValue for String {
+    fn memory(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+
+    fn take(&mut self) -> Box<Self> {
+        Box::new(mem::take(self))
+    }
+}
+
+/// A password, key or buffer of plaintext that is wiped from memory when
+/// dropped.
+///
+/// The value lives in its own heap allocation and is only reachable through
+/// [`Secret::expose`] and [`Secret::expose_mut`]. Its `Debug` output never
+/// shows it. With the `mlock` feature, its memory is locked for as long as the
+/// secret lives; this is best effort, and a warning is logged once if the
+/// system refuses it.
+pub struct Secret<T: SecretValue>(Box<T>);
+
+impl<T: SecretValue> Secret<T> {
+    /// Moves `value` into a new secret. An array is copied to the heap and
+    /// zeroed in place; a vector or string keeps its buffer and is left empty.
+    pub fn new(value: &mut T) -> Self {
+        let secret = Secret(value.take());
+        lock(secret.0.memory());
+        secret
+    }
+
+    /// Returns the value.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Returns the value for modification.
+    ///
+    /// A vector or string must not grow beyond its capacity, because the
+    /// memory it is moved out of would then be neither wiped nor locked.
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: SecretValue> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock(self.0.memory());
+    }
+}
+
+impl<T: SecretValue + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret::new(&mut self.0.as_ref().clone())
+    }
+}
+
+impl<T: SecretValue> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl From<String> for Secret<String> {
+    fn from(mut value: String) -> Self {
+        Secret::new(&mut value)
+    }
+}
+
+/// The borrowed string cannot be wiped, so this is meant for literals.
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Secret::new(&mut value.to_string())
+    }
+}
+
+/// The number of live secrets using each locked page, by page address. The
+/// system does not count locks, so a page is only unlocked once the last
+/// secret on it is dropped.
+#[cfg(all(unix, feature = "mlock"))]
+static LOCKED_PAGES: std::sync::Mutex<std::collections::BTreeMap<usize, usize>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Locks `memory` so that it is never written to swap.
+#[cfg(all(unix, feature = "mlock"))]
+fn lock(memory: (*const u8, usize)) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    update_pages(memory, true, |address, len| {
+        // SAFETY: `address` and `len` describe whole pages that hold memory
+        // owned by a live value, so they are mapped.
+        if unsafe { libc::mlock(address, len) } != 0 && !WARNED.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "Could not lock secrets in memory, so they may be written to swap: {}",
+                io::Error::last_os_error()
+            );
+        }
+    });
+}
+
+/// Unlocks memory locked by `lock`, except for the pages that other live
+/// secrets still use.
+#[cfg(all(unix, feature = "mlock"))]
+fn unlock(memory: (*const u8, usize)) {
+    update_pages(memory, false, |address, len| {
+        // SAFETY: `address` and `len` describe whole pages that hold memory
+        // owned by a live value, so they are mapped.
+        unsafe { libc::munlock(address, len) };
+    });
+}
+
+/// Counts one more (`locking`) or one fewer secret on each page of `memory`,
+/// and calls `apply` on each run of pages whose count rose from or fell to
+/// zero.
+#[cfg(all(unix, feature = "mlock"))]
+fn update_pages(
+    (address, len): (*const u8, usize),
+    locking: bool,
+    apply: impl Fn(*const libc::c_void, usize),
+) {
+    if len == 0 {
+        return;
+    }
+    // SAFETY: `sysconf` has no preconditions.
+    let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    };
+    let start = address as usize / page_size * page_size;
+    let end = address as usize + len;
+
+    let mut pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut run: Option<(usize, usize)> = None;
+    for page in (start..end).step_by(page_size) {
+        let changed = if locking {
+            let count = pages.entry(page).or_insert(0);
+            *count += 1;
+            *count == 1
+        } else {
+            match pages.get_mut(&page) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    false
+                },
+                Some(_) => {
+                    pages.remove(&page);
+                    true
+                },
+                None => false,
+            }
+        };
+        if !changed {
+            continue;
+        }
+        run = match run {
+            Some((first, last)) if last == page => Some((first, page + page_size)),
+            run => {
+                if let Some((first, last)) = run {
+                    apply(first as *const libc::c_void, last - first);
+                }
+                Some((page, page + page_size))
+            },
+        };
+    }
+    if let Some((first, last)) = run {
+        apply(first as *const libc::c_void, last - first);
+    }
+}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn lock(_memory: (*const u8, usize)) {}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn unlock(_memory: (*const u8, usize)) {}
+
+/// Stops the process from writing core dumps, and on Linux from being
+/// attached to by debuggers of the same user, so that a crash cannot leave
+/// the secrets it holds on disk. This cannot be undone.
+#[cfg(unix)]
+pub fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid `rlimit` that outlives the call.
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // SAFETY: `PR_SET_DUMPABLE` takes a single integer argument.
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Core dumps are not controlled by the process on non-Unix platforms.
+#[cfg(not(unix))]
+pub fn disable_core_dumps() -> io::Result<()> {
+    Ok(())
+}
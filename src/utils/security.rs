@@ -1,111 +1,1322 @@
 //! # Security Module
 //!
 //! This module provides cryptographic operations for Raze, including:
-//! - Password-based key derivation using Argon2id.
-//! - Authenticated encryption and decryption using AES-256-GCM.
-//! - Secure chunk-based streaming for large files.
+//! - Key derivation using Argon2id from a password, a key file, or both.
+//! - Key slots that each wrap the random data key of a stream, so passwords
+//!   can be added, changed and removed without re-encrypting anything.
+//! - Public-key encryption to one or more X25519 recipients, so that an archive
+//!   can be written by a party that is unable to read it back.
+//! - Authenticated encryption and decryption using AES-256-GCM or XChaCha20-Poly1305.
+//! - Secure chunk-based streaming for large files, through the [`EncryptWriter`]
+//!   and [`DecryptReader`] adapters, so plaintext never has to be staged on disk.
+//! - Optional authenticated padding after the final chunk, which hides the
+//!   exact size of the encrypted stream.
+//! - Reading streams written by Raze 1.1.0, which lack the format version,
+//!   the key slots and the final-chunk flag.
+//!
+//! Passwords, keys and decrypted chunks are held in [`Secret`]s, so they are
+//! wiped from memory as soon as they are no longer needed.
 
 use crate::utils::errors::RazeError;
+use crate::utils::secret::Secret;
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{self, Aead, AeadInPlace, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
 use argon2::{password_hash::SaltString, Argon2, Params};
-use rand::{rngs::OsRng, RngCore};
-use std::io::{Read, Write};
-use zeroize::Zeroize;
+use blake2::{digest::Mac, Blake2s256, Blake2sMac256, Digest};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use log::warn;
+use rand::{rngs::SysRng, TryRng};
+use sha2::Sha256;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
 
+const KEY_LEN: usize = 32;
 const SALT_LEN: usize = 16;
-const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
 const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
 const MAGIC_ENCRYPTED: &[u8] = b"RZCR"; // Raze CRypt
+const FORMAT_VERSION: u8 = 2;
+const DIGEST_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+const SLOT_SECRET: u8 = 1; // Data key wrapped with a password and/or key file via Argon2id, version 0x13
+const SLOT_RECIPIENT: u8 = 2; // Data key wrapped for an X25519 recipient
+const FACTOR_PASSWORD: u8 = 0b01;
+const FACTOR_KEY_FILE: u8 = 0b10;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+const SECRET_SLOT_LEN: usize = 1 + 12 + SALT_LEN + WRAPPED_KEY_LEN; // Factors, KDF parameters, salt, wrapped key
+const RECIPIENT_SLOT_LEN: usize = KEY_LEN + WRAPPED_KEY_LEN; // Ephemeral public key and wrapped key
+const SLOT_RESERVE: usize = 4 * (1 + SECRET_SLOT_LEN); // Room for adding slots after encryption
+const MAX_SLOT_AREA: usize = 16 * 1024 * 1024;
+const X25519_INFO: &[u8] = b"raze-x25519-v1";
+const HEADER_MAC_INFO: &[u8] = b"raze-header-mac-v1";
+const PADDING_MAC_INFO: &[u8] = b"raze-padding-mac-v1";
+const PADDING_MARKER: u32 = u32::MAX; // Takes the place of a chunk length before the padding
+const PADDING_OVERHEAD: u64 = 4 + 8 + MAC_LEN as u64; // Marker, padding length and MAC
+const PUBLIC_KEY_PREFIX: &str = "raze-pub-";
+const SECRET_KEY_PREFIX: &str = "RAZE-SECRET-";
+const LEGACY_NONCE_LEN: usize = 12;
+/// The fixed Argon2id parameters of streams written by Raze 1.1.0.
+const LEGACY_KDF: KdfParams = KdfParams {
+    memory_kib: 65536,
+    iterations: 3,
+    parallelism: 4,
+};
+
+/// The largest Argon2id memory cost accepted when decrypting (4 GiB).
+pub const MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+/// The largest Argon2id iteration count accepted when decrypting.
+pub const MAX_KDF_ITERATIONS: u32 = 64;
+/// The largest Argon2id parallelism accepted when decrypting.
+pub const MAX_KDF_PARALLELISM: u32 = 64;
 
-/// Derives a 32-byte key from a password and salt using Argon2id.
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32], RazeError> {
-    let mut password_bytes = password.as_bytes().to_vec();
-    let params =
-        Params::new(65536, 3, 4, Some(32)).map_err(|e| RazeError::CryptoError(e.to_string()))?;
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+/// Fills `buf` with random bytes from the operating system's CSPRNG.
+pub fn fill_random(buf: &mut [u8]) -> Result<(), RazeError> {
+    SysRng
+        .try_fill_bytes(buf)
+        .map_err(|e| RazeError::CryptoError(e.to_string()))
+}
 
-    let mut key = [0u8; 32];
-    let salt_str =
-        SaltString::encode_b64(salt).map_err(|e| RazeError::CryptoError(e.to_string()))?;
+/// Cost parameters of the Argon2id key derivation.
+///
+/// The parameters are recorded in the header of every encrypted archive, so
+/// they can be tuned per archive without affecting the readability of others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB.
+    pub memory_kib: u32,
+    /// Number of passes over the memory.
+    pub iterations: u32,
+    /// Degree of parallelism (lanes).
+    pub parallelism: u32,
+}
 
-    argon2
-        .hash_password_into(
-            password_bytes.as_slice(),
-            salt_str.as_str().as_bytes(),
-            &mut key,
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfProfile::default().params()
+    }
+}
+
+impl KdfParams {
+    /// Returns the Argon2 parameters, or an error if the parameters are invalid
+    /// or exceed the safety limits accepted when decrypting.
+    fn argon2_params(&self) -> Result<Params, RazeError> {
+        if self.memory_kib > MAX_KDF_MEMORY_KIB
+            || self.iterations > MAX_KDF_ITERATIONS
+            || self.parallelism > MAX_KDF_PARALLELISM
+        {
+            return Err(RazeError::CryptoError(format!(
+                "KDF parameters ({}) exceed the safety limit (memory {} KiB, {} iterations, parallelism {})",
+                self, MAX_KDF_MEMORY_KIB, MAX_KDF_ITERATIONS, MAX_KDF_PARALLELISM
+            )));
+        }
+        Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
         )
-        .map_err(|e| RazeError::CryptoError(e.to_string()))?;
+        .map_err(|e| RazeError::CryptoError(format!("Invalid KDF parameters ({}): {}", self, e)))
+    }
+}
 
-    password_bytes.zeroize();
-    Ok(key)
+impl fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "m={} KiB, t={}, p={}",
+            self.memory_kib, self.iterations, self.parallelism
+        )
+    }
 }
 
-/// Encrypts a stream using AES-256-GCM with a password.
-pub fn encrypt_stream<R: Read, W: Write>(
-    mut reader: R,
-    mut writer: W,
+/// Preset Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KdfProfile {
+    /// 19 MiB, 2 iterations, 1 lane: fast enough for frequently opened archives.
+    Interactive,
+    /// 64 MiB, 3 iterations, 4 lanes: the default.
+    #[default]
+    Moderate,
+    /// 1 GiB, 4 iterations, 4 lanes: for long-term storage of sensitive data.
+    Paranoid,
+}
+
+impl KdfProfile {
+    /// Returns the cost parameters of this profile.
+    pub fn params(self) -> KdfParams {
+        let (memory_kib, iterations, parallelism) = match self {
+            KdfProfile::Interactive => (19 * 1024, 2, 1),
+            KdfProfile::Moderate => (64 * 1024, 3, 4),
+            KdfProfile::Paranoid => (1024 * 1024, 4, 4),
+        };
+        KdfParams {
+            memory_kib,
+            iterations,
+            parallelism,
+        }
+    }
+}
+
+impl FromStr for KdfProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "interactive" => Ok(KdfProfile::Interactive),
+            "moderate" => Ok(KdfProfile::Moderate),
+            "paranoid" => Ok(KdfProfile::Paranoid),
+            _ => Err(format!(
+                "unknown KDF profile '{}' (expected interactive, moderate or paranoid)",
+                s
+            )),
+        }
+    }
+}
+
+/// A key file used as a second factor next to a password, or in place of one.
+///
+/// Any file with unpredictable contents will do. Only a BLAKE2s-256 digest
+/// of the contents is kept, which is mixed into the key derivation.
+#[derive(Clone)]
+pub struct KeyFile(Secret<[u8; DIGEST_LEN]>);
+
+impl KeyFile {
+    /// Reads and digests the key file at `path`. Empty files are rejected.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, RazeError> {
+        let path = path.as_ref();
+        let mut file =
+            fs::File::open(path).map_err(|_| RazeError::NotFound(path.display().to_string()))?;
+        let mut hasher = Blake2s256::new();
+        let mut buffer = Secret::new(&mut vec![0u8; CHUNK_SIZE]);
+        let mut len = 0u64;
+        loop {
+            let n = file.read(buffer.expose_mut())?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer.expose()[..n]);
+            len += n as u64;
+        }
+        if len == 0 {
+            return Err(RazeError::CryptoError(format!(
+                "Key file '{}' is empty",
+                path.display()
+            )));
+        }
+        let mut digest = Secret::new(&mut [0u8; DIGEST_LEN]);
+        hasher.finalize_into(digest.expose_mut().into());
+        Ok(KeyFile(digest))
+    }
+
+    /// Returns a key file with the given contents.
+    pub fn from_bytes(contents: &[u8]) -> Self {
+        let mut digest = Secret::new(&mut [0u8; DIGEST_LEN]);
+        Blake2s256::new_with_prefix(contents).finalize_into(digest.expose_mut().into());
+        KeyFile(digest)
+    }
+}
+
+impl fmt::Debug for KeyFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyFile(..)")
+    }
+}
+
+/// Derives a 32-byte key from a password and salt using Argon2id with the given parameters.
+///
+/// If a `key_file` is given, its digest is mixed in as the Argon2 secret, so
+/// the key can only be derived again with both the password and the key file.
+/// An empty password may be used when the key file is the only factor.
+pub fn derive_key(
     password: &str,
-) -> Result<(), RazeError> {
-    // 1. Write Magic Header
-    writer.write_all(MAGIC_ENCRYPTED)?;
+    key_file: Option<&KeyFile>,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<Secret<[u8; KEY_LEN]>, RazeError> {
+    let secret: &[u8] = key_file.map_or(&[], |key_file| key_file.0.expose());
+    let argon2 = Argon2::new_with_secret(
+        secret,
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        kdf.argon2_params()?,
+    )
+    .map_err(|e| RazeError::CryptoError(e.to_string()))?;
 
-    // 2. Generate and write Salt
-    let mut salt = [0u8; SALT_LEN];
-    OsRng.fill_bytes(&mut salt);
-    writer.write_all(&salt)?;
+    let mut key = Secret::new(&mut [0u8; KEY_LEN]);
+    argon2
+        .hash_password_into(password.as_bytes(), salt, key.expose_mut())
+        .map_err(|e| RazeError::CryptoError(e.to_string()))?;
+    Ok(key)
+}
 
-    // 3. Derive Key
-    let key = derive_key(password, &salt)?;
-    let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| RazeError::CryptoError(e.to_string()))?;
+/// The AEAD cipher used to seal the chunks of an encrypted archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    /// AES-256 in Galois/Counter Mode, the fastest choice on CPUs with AES instructions.
+    Aes256Gcm,
+    /// XChaCha20-Poly1305, which is fast in software on CPUs without AES instructions.
+    XChaCha20Poly1305,
+}
 
-    // 4. Generate and write base Nonce
-    let mut base_nonce = [0u8; NONCE_LEN];
-    OsRng.fill_bytes(&mut base_nonce);
-    writer.write_all(&base_nonce)?;
+impl Default for Cipher {
+    /// Returns the cipher best suited to the current CPU, see [`Cipher::detect`].
+    fn default() -> Self {
+        Cipher::detect()
+    }
+}
 
-    // 5. Encrypt chunks
-    let mut buffer = vec![0u8; CHUNK_SIZE];
-    let mut chunk_index: u64 = 0;
+impl Cipher {
+    /// Chooses AES-256-GCM if the CPU has AES and carry-less multiplication
+    /// instructions, and XChaCha20-Poly1305 otherwise.
+    pub fn detect() -> Self {
+        if has_aes_instructions() {
+            Cipher::Aes256Gcm
+        } else {
+            Cipher::XChaCha20Poly1305
+        }
+    }
 
-    loop {
-        let n = reader.read(&mut buffer)?;
-        if n == 0 {
-            break;
+    /// Returns the identifier of the cipher in the encryption header.
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::XChaCha20Poly1305 => 2,
         }
+    }
 
-        // Create a unique nonce for each chunk using the base nonce and chunk index
-        let mut nonce_bytes = base_nonce;
-        let index_bytes = chunk_index.to_le_bytes();
-        for i in 0..8 {
-            nonce_bytes[i] ^= index_bytes[i];
+    /// Returns the cipher with the given header identifier, if it is known.
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Cipher::Aes256Gcm),
+            2 => Some(Cipher::XChaCha20Poly1305),
+            _ => None,
         }
-        let nonce = Nonce::from_slice(&nonce_bytes);
+    }
 
-        let encrypted_data = cipher
-            .encrypt(nonce, &buffer[..n])
-            .map_err(|e| RazeError::CryptoError(e.to_string()))?;
+    /// Returns the nonce length of the cipher in bytes.
+    fn nonce_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+}
 
-        // Write chunk length (4 bytes) and then encrypted data
-        writer.write_all(&(encrypted_data.len() as u32).to_le_bytes())?;
-        writer.write_all(&encrypted_data)?;
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cipher::Aes256Gcm => "aes256gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20poly1305",
+        })
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "aes256gcm" | "aes" => Ok(Cipher::Aes256Gcm),
+            "xchacha20poly1305" | "xchacha" => Ok(Cipher::XChaCha20Poly1305),
+            _ => Err(format!(
+                "unknown cipher '{}' (expected aes256gcm or xchacha20poly1305)",
+                s
+            )),
+        }
+    }
+}
+
+/// How an encrypted stream is padded after its final chunk, so that its
+/// length reveals less about the size of the plaintext.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+    /// No padding. The length of the stream closely follows the compressed size.
+    #[default]
+    None,
+    /// PADMÉ, which rounds lengths up by at most 12% and leaves only
+    /// O(log log n) bits of the length visible.
+    Padme,
+    /// Rounds lengths up to the next power of two, which hides more of the
+    /// length but can nearly double it.
+    PowerOfTwo,
+}
 
-        chunk_index += 1;
+impl Padding {
+    /// Returns the length that a stream of `len` bytes is padded to.
+    pub fn padded_len(self, len: u64) -> u64 {
+        match self {
+            Padding::None => len,
+            Padding::Padme => padme(len),
+            Padding::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(len),
+        }
     }
+}
+
+/// Rounds `len` up so that only the top O(log log len) bits of its binary
+/// representation can be non-zero.
+fn padme(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+    let exponent = 63 - len.leading_zeros(); // floor(log2(len))
+    let exponent_bits = 32 - exponent.leading_zeros(); // floor(log2(exponent)) + 1
+    let mask = (1u64 << (exponent - exponent_bits)) - 1;
+    len.checked_add(mask).map_or(len, |len| len & !mask)
+}
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Padding::None => "none",
+            Padding::Padme => "padme",
+            Padding::PowerOfTwo => "pow2",
+        })
+    }
+}
 
+impl FromStr for Padding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "none" => Ok(Padding::None),
+            "padme" => Ok(Padding::Padme),
+            "pow2" | "poweroftwo" => Ok(Padding::PowerOfTwo),
+            _ => Err(format!(
+                "unknown padding '{}' (expected padme, pow2 or none)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn has_aes_instructions() -> bool {
+    std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn has_aes_instructions() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes")
+        && std::arch::is_aarch64_feature_detected!("pmull")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn has_aes_instructions() -> bool {
+    false
+}
+
+/// A [`Cipher`] initialized with a key.
+enum AeadCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+}
+
+impl AeadCipher {
+    fn new(cipher: Cipher, key: &[u8]) -> Result<Self, RazeError> {
+        let keyed = match cipher {
+            Cipher::Aes256Gcm => {
+                Aes256Gcm::new_from_slice(key).map(|c| AeadCipher::Aes256Gcm(Box::new(c)))
+            },
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new_from_slice(key).map(AeadCipher::XChaCha20Poly1305)
+            },
+        };
+        keyed.map_err(|e| RazeError::CryptoError(e.to_string()))
+    }
+
+    fn encrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>> {
+        match self {
+            AeadCipher::Aes256Gcm(cipher) => cipher.encrypt(Nonce::from_slice(nonce), payload),
+            AeadCipher::XChaCha20Poly1305(cipher) => {
+                cipher.encrypt(XNonce::from_slice(nonce), payload)
+            },
+        }
+    }
+
+    /// Replaces the ciphertext in `buffer` with its plaintext, so that no
+    /// copy of the plaintext is made.
+    fn decrypt_in_place(&self, nonce: &[u8], aad: &[u8], buffer: &mut Vec<u8>) -> aead::Result<()> {
+        match self {
+            AeadCipher::Aes256Gcm(cipher) => {
+                cipher.decrypt_in_place(Nonce::from_slice(nonce), aad, buffer)
+            },
+            AeadCipher::XChaCha20Poly1305(cipher) => {
+                cipher.decrypt_in_place(XNonce::from_slice(nonce), aad, buffer)
+            },
+        }
+    }
+}
+
+/// The public key of an archive recipient, written as `raze-pub-` followed by
+/// 64 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, hex_encode(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .and_then(hex_decode_key)
+            .map(|bytes| Recipient(PublicKey::from(bytes)))
+            .ok_or_else(|| {
+                format!(
+                    "invalid recipient '{}' (expected {} followed by 64 hex digits)",
+                    s, PUBLIC_KEY_PREFIX
+                )
+            })
+    }
+}
+
+/// An X25519 secret key that opens archives encrypted to its [`Recipient`].
+///
+/// It is written as `RAZE-SECRET-` followed by 64 hexadecimal digits, usually
+/// in an identity file created by [`write_identity_file`].
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    /// Generates a new random identity.
+    pub fn generate() -> Result<Self, RazeError> {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        fill_random(bytes.as_mut_slice())?;
+        Ok(Identity(StaticSecret::from(*bytes)))
+    }
+
+    /// Returns the public key that archives must be encrypted to for this identity.
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+
+    /// Returns the textual form of the secret key.
+    pub fn to_secret_string(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}{}",
+            SECRET_KEY_PREFIX,
+            hex_encode(self.0.as_bytes()).to_ascii_uppercase()
+        ))
+    }
+}
+
+impl fmt::Debug for Identity {
+    /// Shows only the public key, so that secrets never end up in logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Identity").field(&self.recipient()).finish()
+    }
+}
+
+impl FromStr for Identity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .strip_prefix(SECRET_KEY_PREFIX)
+            .and_then(hex_decode_key)
+            .map(|bytes| Identity(StaticSecret::from(bytes)))
+            .ok_or_else(|| {
+                format!(
+                    "invalid identity (expected {} followed by 64 hex digits)",
+                    SECRET_KEY_PREFIX
+                )
+            })
+    }
+}
+
+/// Reads the identities stored in an identity file, one per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn read_identity_file(path: impl AsRef<Path>) -> Result<Vec<Identity>, RazeError> {
+    let path = path.as_ref();
+    let contents = Zeroizing::new(
+        fs::read_to_string(path).map_err(|_| RazeError::NotFound(path.display().to_string()))?,
+    );
+    let identities = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<Identity>()
+                .map_err(|e| RazeError::CryptoError(format!("{} in '{}'", e, path.display())))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if identities.is_empty() {
+        return Err(RazeError::CryptoError(format!(
+            "No identity found in '{}'",
+            path.display()
+        )));
+    }
+    Ok(identities)
+}
+
+/// Writes `identity` to a new identity file that only its owner can read,
+/// noting the matching public key in a comment. An existing file is never overwritten.
+pub fn write_identity_file(path: impl AsRef<Path>, identity: &Identity) -> Result<(), RazeError> {
+    let contents = Zeroizing::new(format!(
+        "# Raze identity. Keep this file secret.\n# public key: {}\n{}\n",
+        identity.recipient(),
+        identity.to_secret_string().as_str()
+    ));
+    write_secret_file(path.as_ref(), &contents)
+}
+
+/// Writes `contents` to a new file that only its owner can read. An existing
+/// file is never overwritten.
+pub(crate) fn write_secret_file(path: &Path, contents: &str) -> Result<(), RazeError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
     Ok(())
 }
 
-/// Decrypts a stream using AES-256-GCM with a password.
-pub fn decrypt_stream<R: Read, W: Write>(
-    mut reader: R,
-    mut writer: W,
-    password: &str,
-) -> Result<(), RazeError> {
-    // 1. Read and verify Magic Header
+/// The secrets offered for opening an encrypted archive. Which of them is
+/// needed depends on how the archive was encrypted.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    /// The password of a password-protected archive.
+    pub password: Option<Secret<String>>,
+    /// The key file of an archive protected by one, alone or with a password.
+    pub key_file: Option<KeyFile>,
+    /// Identities tried against the recipients of an archive encrypted to public keys.
+    pub identities: Vec<Identity>,
+}
+
+impl Credentials {
+    /// Returns credentials consisting of just a password.
+    pub fn from_password(password: &str) -> Self {
+        Credentials {
+            password: Some(password.into()),
+            ..Credentials::default()
+        }
+    }
+
+    /// Returns `true` if no secret at all was offered.
+    pub fn is_empty(&self) -> bool {
+        self.password.is_none() && self.key_file.is_none() && self.identities.is_empty()
+    }
+}
+
+/// Encodes `bytes` as lowercase hexadecimal digits.
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a key written as 64 hexadecimal digits of either case.
+pub(crate) fn hex_decode_key(digits: &str) -> Option<[u8; KEY_LEN]> {
+    if digits.len() != 2 * KEY_LEN || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Derives the key that wraps the data key for one recipient from the
+/// X25519 shared secret, binding both public keys involved.
+fn wrapping_key(
+    shared: &SharedSecret,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Secret<[u8; KEY_LEN]> {
+    let mut salt = [0u8; 2 * KEY_LEN];
+    salt[..KEY_LEN].copy_from_slice(ephemeral.as_bytes());
+    salt[KEY_LEN..].copy_from_slice(recipient.as_bytes());
+    let mut key = Secret::new(&mut [0u8; KEY_LEN]);
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(X25519_INFO, key.expose_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// Wraps `data_key` with `key`, binding the immutable part of the header
+/// through `aad`. Every wrapping key is used once, so the nonce is fixed.
+fn wrap_key(
+    key: &[u8; KEY_LEN],
+    data_key: &[u8; KEY_LEN],
+    aad: &[u8],
+) -> Result<Vec<u8>, RazeError> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(&Default::default(), Payload { msg: data_key, aad })
+        .map_err(|e| RazeError::CryptoError(e.to_string()))
+}
+
+/// Recovers a data key wrapped by [`wrap_key`], or returns `None` if `key` is wrong.
+fn unwrap_key(key: &[u8; KEY_LEN], wrapped: &[u8], aad: &[u8]) -> Option<Secret<[u8; KEY_LEN]>> {
+    let mut unwrapped = Secret::new(&mut wrapped.to_vec());
+    ChaCha20Poly1305::new(key.into())
+        .decrypt_in_place(&Default::default(), aad, unwrapped.expose_mut())
+        .ok()?;
+    let mut data_key = Secret::new(&mut [0u8; KEY_LEN]);
+    data_key.expose_mut().copy_from_slice(unwrapped.expose());
+    Some(data_key)
+}
+
+/// What opening one key slot of an encrypted stream takes, as recorded in
+/// its header. See [`KeySlots::slot_kinds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotKind {
+    /// A slot opened by a password, a key file, or both together.
+    Secret { password: bool, key_file: bool },
+    /// A slot opened by the identity of one recipient.
+    Recipient,
+}
+
+/// One way of recovering the data key of an encrypted stream.
+#[derive(Clone)]
+enum Slot {
+    /// The data key wrapped with a key derived from a password and/or key file.
+    Secret {
+        factors: u8,
+        kdf: KdfParams,
+        salt: [u8; SALT_LEN],
+        wrapped: Vec<u8>,
+    },
+    /// The data key wrapped for an X25519 recipient with an ephemeral key.
+    Recipient {
+        ephemeral: PublicKey,
+        wrapped: Vec<u8>,
+    },
+}
+
+impl Slot {
+    /// Wraps `data_key` with a key derived from `password` and/or `key_file`.
+    fn secret(
+        data_key: &[u8; KEY_LEN],
+        password: Option<&str>,
+        key_file: Option<&KeyFile>,
+        kdf: KdfParams,
+        aad: &[u8],
+    ) -> Result<Self, RazeError> {
+        let mut factors = 0;
+        if password.is_some() {
+            factors |= FACTOR_PASSWORD;
+        }
+        if key_file.is_some() {
+            factors |= FACTOR_KEY_FILE;
+        }
+        if factors == 0 {
+            return Err(RazeError::CryptoError(
+                "A key slot needs a password, a key file or both".to_string(),
+            ));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        fill_random(&mut salt)?;
+        let key = derive_key(password.unwrap_or_default(), key_file, &salt, &kdf)?;
+        Ok(Slot::Secret {
+            factors,
+            kdf,
+            salt,
+            wrapped: wrap_key(key.expose(), data_key, aad)?,
+        })
+    }
+
+    /// Wraps `data_key` for `recipient` with a fresh ephemeral key.
+    fn recipient(
+        data_key: &[u8; KEY_LEN],
+        recipient: &Recipient,
+        aad: &[u8],
+    ) -> Result<Self, RazeError> {
+        let mut ephemeral_bytes = Zeroizing::new([0u8; KEY_LEN]);
+        fill_random(ephemeral_bytes.as_mut_slice())?;
+        let ephemeral = StaticSecret::from(*ephemeral_bytes);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&recipient.0);
+        if !shared.was_contributory() {
+            return Err(RazeError::CryptoError(format!(
+                "Recipient {} is not a usable X25519 public key",
+                recipient
+            )));
+        }
+
+        let key = wrapping_key(&shared, &ephemeral_public, &recipient.0);
+        Ok(Slot::Recipient {
+            ephemeral: ephemeral_public,
+            wrapped: wrap_key(key.expose(), data_key, aad)?,
+        })
+    }
+
+    /// Returns what opening the slot takes.
+    fn kind(&self) -> SlotKind {
+        match self {
+            Slot::Secret { factors, .. } => SlotKind::Secret {
+                password: factors & FACTOR_PASSWORD != 0,
+                key_file: factors & FACTOR_KEY_FILE != 0,
+            },
+            Slot::Recipient { .. } => SlotKind::Recipient,
+        }
+    }
+
+    /// Returns the number of bytes the slot takes up in the header.
+    fn encoded_len(&self) -> usize {
+        1 + match self {
+            Slot::Secret { .. } => SECRET_SLOT_LEN,
+            Slot::Recipient { .. } => RECIPIENT_SLOT_LEN,
+        }
+    }
+
+    /// Appends the slot type and its fields to `out`.
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Slot::Secret {
+                factors,
+                kdf,
+                salt,
+                wrapped,
+            } => {
+                out.push(SLOT_SECRET);
+                out.push(*factors);
+                for value in [kdf.memory_kib, kdf.iterations, kdf.parallelism] {
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                out.extend_from_slice(salt);
+                out.extend_from_slice(wrapped);
+            },
+            Slot::Recipient { ephemeral, wrapped } => {
+                out.push(SLOT_RECIPIENT);
+                out.extend_from_slice(ephemeral.as_bytes());
+                out.extend_from_slice(wrapped);
+            },
+        }
+    }
+
+    /// Reads one slot from the front of `area`.
+    fn decode(area: &mut &[u8]) -> Result<Self, RazeError> {
+        let mut take = |len: usize| -> Result<&[u8], RazeError> {
+            if area.len() < len {
+                return Err(RazeError::CryptoError(
+                    "Key slot area of the encryption header is truncated".to_string(),
+                ));
+            }
+            let (field, rest) = area.split_at(len);
+            *area = rest;
+            Ok(field)
+        };
+        match take(1)?[0] {
+            SLOT_SECRET => {
+                let fields = take(SECRET_SLOT_LEN)?;
+                let factors = fields[0];
+                if factors == 0 || factors & !(FACTOR_PASSWORD | FACTOR_KEY_FILE) != 0 {
+                    return Err(RazeError::CryptoError(format!(
+                        "Unsupported key factors {:#04b}",
+                        factors
+                    )));
+                }
+                // Parameters beyond the safety limits are refused by `derive_key`.
+                let field =
+                    |i: usize| u32::from_le_bytes(fields[i..i + 4].try_into().expect("u32"));
+                let salt_start = 1 + 12;
+                let wrapped_start = salt_start + SALT_LEN;
+                Ok(Slot::Secret {
+                    factors,
+                    kdf: KdfParams {
+                        memory_kib: field(1),
+                        iterations: field(5),
+                        parallelism: field(9),
+                    },
+                    salt: fields[salt_start..wrapped_start].try_into().expect("salt"),
+                    wrapped: fields[wrapped_start..].to_vec(),
+                })
+            },
+            SLOT_RECIPIENT => {
+                let fields = take(RECIPIENT_SLOT_LEN)?;
+                let ephemeral: [u8; KEY_LEN] = fields[..KEY_LEN].try_into().expect("key");
+                Ok(Slot::Recipient {
+                    ephemeral: PublicKey::from(ephemeral),
+                    wrapped: fields[KEY_LEN..].to_vec(),
+                })
+            },
+            other => Err(RazeError::CryptoError(format!(
+                "Unsupported key slot type {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Describes the factors a key is derived from, e.g. "a password and a key file".
+fn describe_factors(factors: u8) -> &'static str {
+    match (
+        factors & FACTOR_PASSWORD != 0,
+        factors & FACTOR_KEY_FILE != 0,
+    ) {
+        (true, true) => "a password and a key file",
+        (false, true) => "a key file",
+        _ => "a password",
+    }
+}
+
+/// Tries `credentials` against each of `slots` in turn, returning the index
+/// of the first slot they open together with the data key.
+///
+/// When nothing matches, the error names what the archive needs: a slot is
+/// only tried if all of its factors were provided.
+fn unlock(
+    slots: &[Slot],
+    credentials: &Credentials,
+    aad: &[u8],
+) -> Result<(usize, Secret<[u8; KEY_LEN]>), RazeError> {
+    let mut missing = None;
+    let mut tried_secret = false;
+    for (index, slot) in slots.iter().enumerate() {
+        match slot {
+            Slot::Secret {
+                factors,
+                kdf,
+                salt,
+                wrapped,
+            } => {
+                let mut absent = Vec::new();
+                if factors & FACTOR_PASSWORD != 0 && credentials.password.is_none() {
+                    absent.push("password");
+                }
+                if factors & FACTOR_KEY_FILE != 0 && credentials.key_file.is_none() {
+                    absent.push("key file");
+                }
+                if !absent.is_empty() {
+                    missing.get_or_insert((*factors, absent));
+                    continue;
+                }
+
+                tried_secret = true;
+                let password = match factors & FACTOR_PASSWORD {
+                    0 => "",
+                    _ => credentials.password.as_ref().map_or("", |p| p.expose()),
+                };
+                let key_file = match factors & FACTOR_KEY_FILE {
+                    0 => None,
+                    _ => credentials.key_file.as_ref(),
+                };
+                let key = derive_key(password, key_file, salt, kdf)?;
+                if let Some(data_key) = unwrap_key(key.expose(), wrapped, aad) {
+                    return Ok((index, data_key));
+                }
+            },
+            Slot::Recipient { ephemeral, wrapped } => {
+                for identity in &credentials.identities {
+                    let shared = identity.0.diffie_hellman(ephemeral);
+                    if !shared.was_contributory() {
+                        continue;
+                    }
+                    let key = wrapping_key(&shared, ephemeral, &identity.recipient().0);
+                    if let Some(data_key) = unwrap_key(key.expose(), wrapped, aad) {
+                        return Ok((index, data_key));
+                    }
+                }
+            },
+        }
+    }
+
+    let has_recipients = slots
+        .iter()
+        .any(|slot| matches!(slot, Slot::Recipient { .. }));
+    let message = if tried_secret {
+        "Authentication failed (wrong password or key file?): no key slot could be opened"
+            .to_string()
+    } else if has_recipients && !credentials.identities.is_empty() {
+        "None of the given identities is a recipient of this archive".to_string()
+    } else if let Some((factors, absent)) = missing {
+        format!(
+            "Archive is protected by {}, but the {} was not provided",
+            describe_factors(factors),
+            absent.join(" and ")
+        )
+    } else {
+        "Archive is encrypted to public keys; an identity is needed to open it".to_string()
+    };
+    Err(RazeError::CryptoError(message))
+}
+
+/// The key slots in the header of an encrypted stream.
+///
+/// The chunks of a stream are sealed with a random data key, and each slot
+/// wraps that key for one password, key file or recipient, much like LUKS.
+/// Anyone able to open a slot can add or remove slots without touching the
+/// chunks: the header reserves room for a few more slots, and
+/// [`KeySlots::write`] always rewrites it at the same length.
+///
+/// The slots are followed by a MAC keyed with the data key, so only holders
+/// of a slot can produce a header that is accepted.
+pub struct KeySlots {
+    cipher: Cipher,
+    base_nonce: Vec<u8>,
+    data_key: Secret<[u8; KEY_LEN]>,
+    slots: Vec<Slot>,
+    area_len: Option<usize>,
+    unlocked: Option<usize>,
+}
+
+impl KeySlots {
+    /// Creates the header of a new stream sealed with `cipher`, with a fresh
+    /// data key and base nonce but no slots yet.
+    pub fn new(cipher: Cipher) -> Result<Self, RazeError> {
+        let mut data_key = Secret::new(&mut [0u8; KEY_LEN]);
+        fill_random(data_key.expose_mut())?;
+        let mut base_nonce = vec![0u8; cipher.nonce_len()];
+        fill_random(&mut base_nonce)?;
+        Ok(KeySlots {
+            cipher,
+            base_nonce,
+            data_key,
+            slots: Vec::new(),
+            area_len: None,
+            unlocked: None,
+        })
+    }
+
+    /// Reads the header of an encrypted stream from `reader` and recovers the
+    /// data key with whichever of the `credentials` opens one of its slots.
+    ///
+    /// Afterwards `reader` is positioned at the first chunk.
+    pub fn read<R: Read>(reader: &mut R, credentials: &Credentials) -> Result<Self, RazeError> {
+        let version = read_version(reader)?;
+        Self::read_versioned(reader, version, credentials)
+    }
+
+    /// Reads the rest of the header after its format `version` from `reader`,
+    /// and recovers the data key like [`KeySlots::read`].
+    fn read_versioned<R: Read>(
+        reader: &mut R,
+        version: u8,
+        credentials: &Credentials,
+    ) -> Result<Self, RazeError> {
+        let (mut header_slots, area, mac) = Self::read_locked(reader, version)?;
+
+        // 4. Recover the data key and verify the header
+        let (index, data_key) = unlock(&header_slots.slots, credentials, &header_slots.digest())?;
+        header_slots.data_key = data_key;
+        header_slots.unlocked = Some(index);
+
+        let mut header = header_slots.prefix();
+        header.extend_from_slice(&(area.len() as u32).to_le_bytes());
+        header.extend_from_slice(&area);
+        header_slots
+            .mac()
+            .chain_update(&header)
+            .verify_slice(&mac)
+            .map_err(|_| {
+                RazeError::CryptoError(
+                    "Authentication failed: the encryption header has been modified".to_string(),
+                )
+            })?;
+        Ok(header_slots)
+    }
+
+    /// Reads the header of an encrypted stream from `reader` and returns what
+    /// each of its slots takes to open, without opening any of them.
+    ///
+    /// The header is not authenticated until a slot is opened, so the result
+    /// only serves to decide which credentials to ask for. A stream written by
+    /// Raze 1.1.0 is reported as having a single password slot, as is one
+    /// whose header cannot be parsed: its salt may begin with the format
+    /// version by chance.
+    pub fn slot_kinds<R: Read>(reader: &mut R) -> Result<Vec<SlotKind>, RazeError> {
+        let legacy = vec![SlotKind::Secret {
+            password: true,
+            key_file: false,
+        }];
+        let version = read_version(reader)?;
+        if version != FORMAT_VERSION {
+            return Ok(legacy);
+        }
+        match Self::read_locked(reader, version) {
+            Ok((header_slots, _, _)) => Ok(header_slots.slots.iter().map(Slot::kind).collect()),
+            Err(_) => Ok(legacy),
+        }
+    }
+
+    /// Reads the rest of the header after its format `version` from `reader`
+    /// without opening any slot, returning it along with the raw slot area
+    /// and the header MAC.
+    fn read_locked<R: Read>(
+        reader: &mut R,
+        version: u8,
+    ) -> Result<(Self, Vec<u8>, [u8; MAC_LEN]), RazeError> {
+        // 1. Verify the format version
+        if version != FORMAT_VERSION {
+            return Err(RazeError::CryptoError(format!(
+                "Unsupported encrypted archive version {}",
+                version
+            )));
+        }
+
+        // 2. Read the cipher and the base nonce
+        let mut cipher_id = [0u8; 1];
+        reader.read_exact(&mut cipher_id)?;
+        let cipher = Cipher::from_id(cipher_id[0]).ok_or_else(|| {
+            RazeError::CryptoError(format!("Unsupported cipher {}", cipher_id[0]))
+        })?;
+        let mut base_nonce = vec![0u8; cipher.nonce_len()];
+        reader.read_exact(&mut base_nonce)?;
+
+        // 3. Read the key slots
+        let mut area_len = [0u8; 4];
+        reader.read_exact(&mut area_len)?;
+        let area_len = u32::from_le_bytes(area_len) as usize;
+        if !(2..=MAX_SLOT_AREA).contains(&area_len) {
+            return Err(RazeError::CryptoError(format!(
+                "Invalid key slot area of {} bytes",
+                area_len
+            )));
+        }
+        let mut area = vec![0u8; area_len];
+        reader.read_exact(&mut area)?;
+        let mut fields = &area[2..];
+        let count = u16::from_le_bytes([area[0], area[1]]);
+        let slots = (0..count)
+            .map(|_| Slot::decode(&mut fields))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut mac = [0u8; MAC_LEN];
+        reader.read_exact(&mut mac)?;
+
+        let header_slots = KeySlots {
+            cipher,
+            base_nonce,
+            data_key: Secret::new(&mut [0u8; KEY_LEN]),
+            slots,
+            area_len: Some(area_len),
+            unlocked: None,
+        };
+        Ok((header_slots, area, mac))
+    }
+
+    /// Returns the cipher the chunks are sealed with.
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Returns the number of slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns `true` if there are no slots, so the stream cannot be opened.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the length of the header in bytes, which stays the same as
+    /// slots are added and removed.
+    pub fn header_len(&self) -> usize {
+        MAGIC_ENCRYPTED.len() + 2 + self.base_nonce.len() + 4 + self.area_len() + MAC_LEN
+    }
+
+    /// Adds a slot that opens with `password` and/or `key_file`, using the
+    /// Argon2id parameters `kdf`.
+    pub fn add_secret(
+        &mut self,
+        password: Option<&str>,
+        key_file: Option<&KeyFile>,
+        kdf: KdfParams,
+    ) -> Result<(), RazeError> {
+        let slot = Slot::secret(
+            self.data_key.expose(),
+            password,
+            key_file,
+            kdf,
+            &self.digest(),
+        )?;
+        self.push(slot)
+    }
+
+    /// Adds a slot that opens with the [`Identity`] matching `recipient`.
+    pub fn add_recipient(&mut self, recipient: &Recipient) -> Result<(), RazeError> {
+        let slot = Slot::recipient(self.data_key.expose(), recipient, &self.digest())?;
+        self.push(slot)
+    }
+
+    /// Removes the slot opened by [`KeySlots::read`], refusing to remove the
+    /// last one.
+    pub fn remove_unlocked(&mut self) -> Result<(), RazeError> {
+        let index = self
+            .unlocked
+            .ok_or_else(|| RazeError::CryptoError("No key slot has been opened".to_string()))?;
+        if self.slots.len() == 1 {
+            return Err(RazeError::CryptoError(
+                "Refusing to remove the last key slot; the archive could never be opened again"
+                    .to_string(),
+            ));
+        }
+        self.slots.remove(index);
+        self.unlocked = None;
+        Ok(())
+    }
+
+    /// Writes the header: the format version, the cipher, the base nonce,
+    /// the slot area and the header MAC.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), RazeError> {
+        if self.slots.is_empty() {
+            return Err(RazeError::CryptoError(
+                "An encrypted stream needs at least one key slot".to_string(),
+            ));
+        }
+        let area_len = self.area_len();
+        let mut header = self.prefix();
+        header.extend_from_slice(&(area_len as u32).to_le_bytes());
+        let area_start = header.len();
+        header.extend_from_slice(&(self.slots.len() as u16).to_le_bytes());
+        for slot in &self.slots {
+            slot.encode(&mut header);
+        }
+        header.resize(area_start + area_len, 0);
+        let mac = self.mac().chain_update(&header).finalize().into_bytes();
+        header.extend_from_slice(&mac);
+        writer.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Adds `slot`, provided it still fits into the header.
+    fn push(&mut self, slot: Slot) -> Result<(), RazeError> {
+        if self.slots.len() == u16::MAX as usize {
+            return Err(RazeError::CryptoError(format!(
+                "An encrypted stream has at most {} key slots",
+                u16::MAX
+            )));
+        }
+        if let Some(area_len) = self.area_len {
+            if self.slots_len() + slot.encoded_len() > area_len {
+                return Err(RazeError::CryptoError(
+                    "No room for another key slot in the encryption header; remove one first"
+                        .to_string(),
+                ));
+            }
+        }
+        self.slots.push(slot);
+        Ok(())
+    }
+
+    /// Returns the number of bytes the slot count and the slots take up.
+    fn slots_len(&self) -> usize {
+        2 + self.slots.iter().map(Slot::encoded_len).sum::<usize>()
+    }
+
+    /// Returns the size of the slot area, which a new header chooses with
+    /// room to spare and an existing one keeps.
+    fn area_len(&self) -> usize {
+        self.area_len
+            .unwrap_or_else(|| self.slots_len() + SLOT_RESERVE)
+    }
+
+    /// Returns the part of the header that never changes: the magic, the
+    /// format version, the cipher and the base nonce.
+    fn prefix(&self) -> Vec<u8> {
+        let mut prefix = MAGIC_ENCRYPTED.to_vec();
+        prefix.push(FORMAT_VERSION);
+        prefix.push(self.cipher.id());
+        prefix.extend_from_slice(&self.base_nonce);
+        prefix
+    }
+
+    /// Hashes the part of the header that never changes, which the wrapped
+    /// keys and every chunk are bound to.
+    fn digest(&self) -> [u8; DIGEST_LEN] {
+        header_digest(&self.prefix())
+    }
+
+    /// Returns a MAC over the header keyed with a key derived from the data key.
+    fn mac(&self) -> Blake2sMac256 {
+        derived_mac(self.data_key.expose(), HEADER_MAC_INFO)
+    }
+}
+
+/// Returns a MAC keyed with a key derived from `data_key` for the purpose `info`.
+fn derived_mac(data_key: &[u8], info: &[u8]) -> Blake2sMac256 {
+    let mut key = Secret::new(&mut [0u8; KEY_LEN]);
+    Hkdf::<Sha256>::new(None, data_key)
+        .expand(info, key.expose_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    <Blake2sMac256 as KeyInit>::new_from_slice(key.expose())
+        .expect("32 bytes is a valid BLAKE2s key length")
+}
+
+/// Derives the per-chunk nonce by XOR-ing the chunk index into the base nonce.
+fn chunk_nonce(base_nonce: &[u8], chunk_index: u64) -> Vec<u8> {
+    let mut nonce_bytes = base_nonce.to_vec();
+    for (byte, index_byte) in nonce_bytes.iter_mut().zip(chunk_index.to_le_bytes()) {
+        *byte ^= index_byte;
+    }
+    nonce_bytes
+}
+
+/// Hashes the immutable part of the encryption header so that every chunk
+/// can be bound to it.
+fn header_digest(header: &[u8]) -> [u8; DIGEST_LEN] {
+    Blake2s256::digest(header).into()
+}
+
+/// Builds the associated data of a chunk: the header digest, the chunk's
+/// length prefix, whether it is the final chunk, and the length of the
+/// padding that follows the final chunk, if any.
+///
+/// Binding the header means that tampering with the cipher, the base nonce
+/// or the format version fails authentication. The key slots are left out so
+/// that they can change without touching the chunks; the header MAC covers
+/// them instead. Sealing the last
+/// chunk differently from all others means that dropping trailing chunks
+/// leaves a stream without a valid final chunk, which is detected instead of
+/// silently decrypting a prefix of the archive. Binding the padding length
+/// likewise means that the padding cannot be stripped or resized.
+fn chunk_aad(
+    digest: &[u8; DIGEST_LEN],
+    len: usize,
+    last: bool,
+    padding: Option<u64>,
+) -> [u8; DIGEST_LEN + 13] {
+    let mut aad = [0u8; DIGEST_LEN + 13];
+    aad[..DIGEST_LEN].copy_from_slice(digest);
+    aad[DIGEST_LEN..DIGEST_LEN + 4].copy_from_slice(&(len as u32).to_le_bytes());
+    aad[DIGEST_LEN + 4] = match (last, padding) {
+        (false, _) => 0,
+        (true, None) => 1,
+        (true, Some(_)) => 2,
+    };
+    aad[DIGEST_LEN + 5..].copy_from_slice(&padding.unwrap_or(0).to_le_bytes());
+    aad
+}
+
+/// A reader that keeps a copy of everything read through it, so that it can
+/// be read again.
+struct Recording<'a, R> {
+    inner: &'a mut R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Recording<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Reads the magic bytes of an encrypted stream from `reader` and returns the
+/// byte that follows them, which is the format version.
+///
+/// In a stream written by Raze 1.1.0 that byte is the first byte of the salt.
+fn read_version<R: Read>(reader: &mut R) -> Result<u8, RazeError> {
     let mut magic = [0u8; 4];
     reader.read_exact(&mut magic)?;
     if magic != MAGIC_ENCRYPTED {
@@ -113,51 +1324,532 @@ pub fn decrypt_stream<R: Read, W: Write>(
             "Invalid encrypted archive format".to_string(),
         ));
     }
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version)?;
+    Ok(version[0])
+}
+
+/// Wraps a [`RazeError`] in an I/O error so it can travel through `Read` and `Write`.
+fn io_error(kind: io::ErrorKind, error: RazeError) -> io::Error {
+    io::Error::new(kind, error)
+}
+
+/// A writer that encrypts everything written to it and passes the ciphertext
+/// on to an inner writer.
+///
+/// The header is written when the writer is created. Plaintext is buffered
+/// into 64KB chunks, each sealed with its own nonce, so the whole stream is
+/// encrypted in a single pass without staging it anywhere. [`EncryptWriter::finish`]
+/// must be called to seal the final chunk, which is marked as such, and to
+/// append the padding chosen with [`EncryptWriter::padded`].
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    cipher: AeadCipher,
+    base_nonce: Vec<u8>,
+    header_digest: [u8; DIGEST_LEN],
+    padding_mac: Blake2sMac256,
+    padding: Padding,
+    chunk_index: u64,
+    written: u64,
+    buffer: Secret<Vec<u8>>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Writes the encryption header into `inner` with a single slot that
+    /// derives its key from `password` using the Argon2id parameters `kdf`.
+    /// The chunks are sealed with `cipher`. Both choices are recorded in the header.
+    pub fn new(
+        inner: W,
+        password: &str,
+        kdf: KdfParams,
+        cipher: Cipher,
+    ) -> Result<Self, RazeError> {
+        let mut slots = KeySlots::new(cipher)?;
+        slots.add_secret(Some(password), None, kdf)?;
+        Self::with_key_slots(inner, &slots)
+    }
+
+    /// Like [`EncryptWriter::new`], but the slot derives its key from
+    /// `key_file`, and from `password` if one is given. The header records
+    /// which of the two are needed to decrypt the stream.
+    pub fn with_key_file(
+        inner: W,
+        password: Option<&str>,
+        key_file: &KeyFile,
+        kdf: KdfParams,
+        cipher: Cipher,
+    ) -> Result<Self, RazeError> {
+        let mut slots = KeySlots::new(cipher)?;
+        slots.add_secret(password, Some(key_file), kdf)?;
+        Self::with_key_slots(inner, &slots)
+    }
+
+    /// Writes the encryption header into `inner` with a slot for each of
+    /// `recipients`, so that only the holders of a matching [`Identity`] can
+    /// decrypt the stream. The chunks are sealed with `cipher`.
+    pub fn for_recipients(
+        inner: W,
+        recipients: &[Recipient],
+        cipher: Cipher,
+    ) -> Result<Self, RazeError> {
+        if recipients.is_empty() || recipients.len() > u16::MAX as usize {
+            return Err(RazeError::CryptoError(format!(
+                "Expected between 1 and {} recipients, got {}",
+                u16::MAX,
+                recipients.len()
+            )));
+        }
+        let mut slots = KeySlots::new(cipher)?;
+        for recipient in recipients {
+            slots.add_recipient(recipient)?;
+        }
+        Self::with_key_slots(inner, &slots)
+    }
+
+    /// Writes the header with `slots` into `inner` and prepares to seal chunks
+    /// with their data key, for streams that open in more than one way.
+    pub fn with_key_slots(mut inner: W, slots: &KeySlots) -> Result<Self, RazeError> {
+        slots.write(&mut inner)?;
+        Ok(Self {
+            inner,
+            cipher: AeadCipher::new(slots.cipher, slots.data_key.expose())?,
+            base_nonce: slots.base_nonce.clone(),
+            header_digest: slots.digest(),
+            padding_mac: derived_mac(slots.data_key.expose(), PADDING_MAC_INFO),
+            padding: Padding::None,
+            chunk_index: 0,
+            written: slots.header_len() as u64,
+            buffer: Secret::new(&mut Vec::with_capacity(CHUNK_SIZE)),
+        })
+    }
 
-    // 2. Read Salt
-    let mut salt = [0u8; SALT_LEN];
-    reader.read_exact(&mut salt)?;
+    /// Pads the finished stream as described by `padding`, so that streams of
+    /// similar length cannot be told apart.
+    pub fn padded(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Seals the buffered plaintext as the final chunk, appends the padding,
+    /// if any, and returns the inner writer.
+    ///
+    /// A final chunk is always written, even if it is empty, so that the end
+    /// of the stream can be authenticated.
+    pub fn finish(mut self) -> Result<W, RazeError> {
+        let padding_len = self.padding_len();
+        self.seal_chunk(true, padding_len)
+            .map_err(unwrap_crypto_error)?;
+        if let Some(len) = padding_len {
+            self.write_padding(len)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
 
-    // 3. Derive Key
-    let key = derive_key(password, &salt)?;
-    let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| RazeError::CryptoError(e.to_string()))?;
+    /// Returns the number of padding bytes needed to bring the finished
+    /// stream to its padded length, or `None` if it is not padded.
+    fn padding_len(&self) -> Option<u64> {
+        if self.padding == Padding::None {
+            return None;
+        }
+        let len =
+            self.written + 4 + (self.buffer.expose().len() + TAG_LEN) as u64 + PADDING_OVERHEAD;
+        Some(self.padding.padded_len(len) - len)
+    }
 
-    // 4. Read base Nonce
-    let mut base_nonce = [0u8; NONCE_LEN];
-    reader.read_exact(&mut base_nonce)?;
+    /// Writes the padding after the final chunk: a marker in place of a chunk
+    /// length, the padding length, random bytes and a MAC over both.
+    fn write_padding(&mut self, len: u64) -> Result<(), RazeError> {
+        let mut mac = self.padding_mac.clone();
+        Mac::update(&mut mac, &len.to_le_bytes());
+        self.inner.write_all(&PADDING_MARKER.to_le_bytes())?;
+        self.inner.write_all(&len.to_le_bytes())?;
 
-    // 5. Decrypt chunks
-    let mut chunk_index: u64 = 0;
-    loop {
+        let mut block = vec![0u8; CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK_SIZE as u64) as usize;
+            fill_random(&mut block[..n])?;
+            Mac::update(&mut mac, &block[..n]);
+            self.inner.write_all(&block[..n])?;
+            remaining -= n as u64;
+        }
+        self.inner.write_all(&mac.finalize().into_bytes())?;
+        self.written += PADDING_OVERHEAD + len;
+        Ok(())
+    }
+
+    /// Encrypts the buffered plaintext as the next chunk and writes it out.
+    fn seal_chunk(&mut self, last: bool, padding: Option<u64>) -> io::Result<()> {
+        let nonce_bytes = chunk_nonce(&self.base_nonce, self.chunk_index);
+        let len = self.buffer.expose().len() + TAG_LEN;
+        let payload = Payload {
+            msg: self.buffer.expose(),
+            aad: &chunk_aad(&self.header_digest, len, last, padding),
+        };
+        let encrypted_data = self
+            .cipher
+            .encrypt(&nonce_bytes, payload)
+            .map_err(|e| io::Error::other(RazeError::CryptoError(e.to_string())))?;
+        self.buffer.expose_mut().zeroize();
+
+        // Write chunk length (4 bytes) and then encrypted data
+        self.inner
+            .write_all(&(encrypted_data.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&encrypted_data)?;
+        self.chunk_index += 1;
+        self.written += 4 + encrypted_data.len() as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only sealed once more data arrives, because until
+        // then it may still turn out to be the final one.
+        if self.buffer.expose().len() == CHUNK_SIZE && !buf.is_empty() {
+            self.seal_chunk(false, None)?;
+        }
+        let n = buf.len().min(CHUNK_SIZE - self.buffer.expose().len());
+        // The buffer never grows beyond the capacity it was created with.
+        self.buffer.expose_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Flushes the inner writer. Buffered plaintext is only sealed once the
+    /// next chunk begins or the writer is finished, so the chunk layout does
+    /// not depend on how often the stream is flushed.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that decrypts a stream written by [`EncryptWriter`], verifying
+/// each chunk as it is read.
+///
+/// Decrypted plaintext only ever exists in memory, one chunk at a time.
+/// Authentication failures are reported as [`io::ErrorKind::InvalidData`]
+/// errors wrapping a [`RazeError::CryptoError`]. A stream that ends before
+/// its final chunk is reported as [`io::ErrorKind::UnexpectedEof`] wrapping a
+/// [`RazeError::TruncationError`]. Padding after the final chunk is verified
+/// and discarded before the final chunk's plaintext is returned.
+pub struct DecryptReader<R: Read> {
+    inner: io::Chain<io::Cursor<Vec<u8>>, R>,
+    cipher: AeadCipher,
+    base_nonce: Vec<u8>,
+    header_digest: [u8; DIGEST_LEN],
+    padding_mac: Blake2sMac256,
+    chunk_index: u64,
+    next_len: Option<usize>,
+    finished: bool,
+    plaintext: Secret<Vec<u8>>,
+    position: usize,
+    legacy: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Reads the encryption header from `inner` and derives the key from `password`.
+    ///
+    /// The first chunk is decrypted straight away, so a wrong password is
+    /// reported here as a [`RazeError::CryptoError`] rather than mid-stream.
+    pub fn new(inner: R, password: &str) -> Result<Self, RazeError> {
+        Self::with_credentials(inner, &Credentials::from_password(password))
+    }
+
+    /// Reads the encryption header from `inner` and recovers the key with
+    /// whichever of the `credentials` opens one of its slots.
+    ///
+    /// The first chunk is decrypted straight away, so wrong credentials are
+    /// reported here as a [`RazeError::CryptoError`] rather than mid-stream.
+    ///
+    /// A stream written by Raze 1.1.0 is recognised by its missing format
+    /// version and opened with the password alone. Its salt begins with the
+    /// format version in one stream out of 256, so a stream whose header
+    /// cannot be read or opened is tried as such a stream too before the
+    /// error is returned. Such a stream carries no final-chunk flag, so a
+    /// warning is logged that truncation at a chunk boundary cannot be
+    /// detected.
+    pub fn with_credentials(mut inner: R, credentials: &Credentials) -> Result<Self, RazeError> {
+        let version = read_version(&mut inner)?;
+        if version != FORMAT_VERSION {
+            return Self::legacy(
+                io::Cursor::new(Vec::new()).chain(inner),
+                version,
+                credentials,
+            );
+        }
+        let mut recording = Recording {
+            inner: &mut inner,
+            bytes: Vec::new(),
+        };
+        let slots = match KeySlots::read_versioned(&mut recording, version, credentials) {
+            Ok(slots) => slots,
+            Err(e) if credentials.password.is_some() => {
+                // Replay what the header took from the stream as the salt and what follows.
+                let replay = io::Cursor::new(recording.bytes).chain(inner);
+                return Self::legacy(replay, version, credentials).map_err(|_| e);
+            },
+            Err(e) => return Err(e),
+        };
+        let mut reader = Self {
+            inner: io::Cursor::new(Vec::new()).chain(inner),
+            cipher: AeadCipher::new(slots.cipher, slots.data_key.expose())?,
+            base_nonce: slots.base_nonce.clone(),
+            header_digest: slots.digest(),
+            padding_mac: derived_mac(slots.data_key.expose(), PADDING_MAC_INFO),
+            chunk_index: 0,
+            next_len: None,
+            finished: false,
+            plaintext: Secret::new(&mut Vec::with_capacity(CHUNK_SIZE + TAG_LEN)),
+            position: 0,
+            legacy: false,
+        };
+        reader.next_len = reader.read_len().map_err(unwrap_crypto_error)?;
+        reader.open_chunk().map_err(unwrap_crypto_error)?;
+        Ok(reader)
+    }
+
+    /// Opens a stream written by Raze 1.1.0, whose header is the magic bytes,
+    /// a salt and the base nonce, followed by AES-256-GCM chunks without
+    /// associated data. `first_byte` is the first byte of the salt, and
+    /// `inner` continues with the rest of it.
+    fn legacy(
+        mut inner: io::Chain<io::Cursor<Vec<u8>>, R>,
+        first_byte: u8,
+        credentials: &Credentials,
+    ) -> Result<Self, RazeError> {
+        let password = credentials.password.as_ref().ok_or_else(|| {
+            RazeError::CryptoError(
+                "The archive was encrypted by Raze 1.1.0 and can only be opened with a password"
+                    .to_string(),
+            )
+        })?;
+        let mut salt = [first_byte; SALT_LEN];
+        inner.read_exact(&mut salt[1..])?;
+        let mut base_nonce = vec![0u8; LEGACY_NONCE_LEN];
+        inner.read_exact(&mut base_nonce)?;
+
+        // Raze 1.1.0 passed the salt to Argon2 in its unpadded Base64 form.
+        let salt =
+            SaltString::encode_b64(&salt).map_err(|e| RazeError::CryptoError(e.to_string()))?;
+        let key = derive_key(
+            password.expose(),
+            None,
+            salt.as_str().as_bytes(),
+            &LEGACY_KDF,
+        )?;
+        let mut reader = Self {
+            inner,
+            cipher: AeadCipher::new(Cipher::Aes256Gcm, key.expose())?,
+            base_nonce,
+            header_digest: [0u8; DIGEST_LEN],
+            padding_mac: derived_mac(key.expose(), PADDING_MAC_INFO),
+            chunk_index: 0,
+            next_len: None,
+            finished: false,
+            plaintext: Secret::new(&mut Vec::with_capacity(CHUNK_SIZE + TAG_LEN)),
+            position: 0,
+            legacy: true,
+        };
+        reader.next_len = reader.read_len().map_err(unwrap_crypto_error)?;
+        if reader.next_len.is_some() {
+            reader.open_chunk().map_err(unwrap_crypto_error)?;
+        } else {
+            reader.finished = true;
+        }
+        warn!(
+            "The archive was encrypted by Raze 1.1.0; \
+             truncation at a chunk boundary cannot be detected"
+        );
+        Ok(reader)
+    }
+
+    /// Reads the length prefix of the next chunk, or `None` at the end of the
+    /// stream. The padding marker is returned like any other length.
+    fn read_len(&mut self) -> io::Result<Option<usize>> {
         let mut len_bytes = [0u8; 4];
-        match reader.read_exact(&mut len_bytes) {
+        match self.inner.read_exact(&mut len_bytes) {
             Ok(_) => (),
-            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e.into()),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
         }
-        let len = u32::from_le_bytes(len_bytes) as usize;
+        Ok(Some(u32::from_le_bytes(len_bytes) as usize))
+    }
 
+    /// Reads and decrypts the next chunk into the plaintext buffer.
+    fn open_chunk(&mut self) -> io::Result<()> {
+        self.plaintext.expose_mut().zeroize();
+        self.position = 0;
+
+        let truncated = |chunk_index: u64| {
+            io_error(
+                io::ErrorKind::UnexpectedEof,
+                RazeError::TruncationError(format!(
+                    "the encrypted stream ends after {} chunks without its final chunk",
+                    chunk_index
+                )),
+            )
+        };
+        let len = self.next_len.ok_or_else(|| truncated(self.chunk_index))?;
+        if len > CHUNK_SIZE + TAG_LEN {
+            return Err(io_error(
+                io::ErrorKind::InvalidData,
+                RazeError::CryptoError(format!("Encrypted chunk of {} bytes is too large", len)),
+            ));
+        }
         let mut encrypted_data = vec![0u8; len];
-        reader.read_exact(&mut encrypted_data)?;
+        self.inner
+            .read_exact(&mut encrypted_data)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => truncated(self.chunk_index),
+                _ => e,
+            })?;
+
+        // Only the chunk followed by the end of the stream or by the padding
+        // may be the final one.
+        self.next_len = self.read_len()?;
+        let padding = match self.next_len {
+            Some(len) if !self.legacy && len == PADDING_MARKER as usize => {
+                let mut len_bytes = [0u8; 8];
+                self.inner
+                    .read_exact(&mut len_bytes)
+                    .map_err(|e| self.padding_error(e))?;
+                Some(u64::from_le_bytes(len_bytes))
+            },
+            _ => None,
+        };
+        let last = self.next_len.is_none() || padding.is_some();
 
         // Recreate the unique nonce for this chunk
-        let mut nonce_bytes = base_nonce;
-        let index_bytes = chunk_index.to_le_bytes();
-        for i in 0..8 {
-            nonce_bytes[i] ^= index_bytes[i];
+        let nonce_bytes = chunk_nonce(&self.base_nonce, self.chunk_index);
+        // The chunk is decrypted in place inside the plaintext buffer, which is
+        // wiped again if authentication fails.
+        let plaintext = self.plaintext.expose_mut();
+        let mut open = |last: bool, padding: Option<u64>| {
+            plaintext.clear();
+            plaintext.extend_from_slice(&encrypted_data);
+            let aad = chunk_aad(&self.header_digest, len, last, padding);
+            let aad: &[u8] = if self.legacy { &[] } else { &aad };
+            let opened = self.cipher.decrypt_in_place(&nonce_bytes, aad, plaintext);
+            if opened.is_err() {
+                plaintext.zeroize();
+            }
+            opened
+        };
+        match open(last, padding) {
+            Ok(()) => (),
+            // A valid intermediate chunk at the end means the rest was cut off.
+            Err(_) if last && !self.legacy && open(false, None).is_ok() => {
+                return Err(truncated(self.chunk_index + 1))
+            },
+            Err(e) => {
+                return Err(io_error(
+                    io::ErrorKind::InvalidData,
+                    RazeError::CryptoError(format!(
+                        "Authentication failed (wrong password or key?): {}",
+                        e
+                    )),
+                ))
+            },
+        }
+        self.finished = last;
+        self.chunk_index += 1;
+        if let Some(len) = padding {
+            self.discard_padding(len)?;
         }
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        Ok(())
+    }
 
-        let decrypted_data = cipher
-            .decrypt(nonce, encrypted_data.as_slice())
-            .map_err(|e| {
-                RazeError::CryptoError(format!("Authentication failed (wrong password?): {}", e))
-            })?;
+    /// Reads the padding after the final chunk, checks its MAC and makes sure
+    /// that nothing follows it.
+    fn discard_padding(&mut self, len: u64) -> io::Result<()> {
+        let mut mac = self.padding_mac.clone();
+        Mac::update(&mut mac, &len.to_le_bytes());
+        let mut block = vec![0u8; CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK_SIZE as u64) as usize;
+            self.inner
+                .read_exact(&mut block[..n])
+                .map_err(|e| self.padding_error(e))?;
+            Mac::update(&mut mac, &block[..n]);
+            remaining -= n as u64;
+        }
+        let mut tag = [0u8; MAC_LEN];
+        self.inner
+            .read_exact(&mut tag)
+            .map_err(|e| self.padding_error(e))?;
+        let trailing = self.inner.read(&mut block[..1])?;
+        if mac.verify_slice(&tag).is_err() || trailing > 0 {
+            return Err(io_error(
+                io::ErrorKind::InvalidData,
+                RazeError::CryptoError(
+                    "Authentication failed: the padding of the stream has been modified"
+                        .to_string(),
+                ),
+            ));
+        }
+        Ok(())
+    }
 
-        writer.write_all(&decrypted_data)?;
-        chunk_index += 1;
+    /// Reports a stream that ends inside its padding as truncated.
+    fn padding_error(&self, error: io::Error) -> io::Error {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => io_error(
+                io::ErrorKind::UnexpectedEof,
+                RazeError::TruncationError(
+                    "the encrypted stream ends inside its padding".to_string(),
+                ),
+            ),
+            _ => error,
+        }
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Skip empty chunks; only the final chunk may legitimately be empty.
+        while self.position == self.plaintext.expose().len() && !self.finished {
+            self.open_chunk()?;
+        }
+        let plaintext = self.plaintext.expose();
+        let n = buf.len().min(plaintext.len() - self.position);
+        buf[..n].copy_from_slice(&plaintext[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
     }
+}
+
+/// Recovers the [`RazeError`] carried by an I/O error raised inside the
+/// encryption adapters, or wraps any other I/O error as [`RazeError::Io`].
+pub(crate) fn unwrap_crypto_error(error: io::Error) -> RazeError {
+    match error.downcast::<RazeError>() {
+        Ok(raze_error) => raze_error,
+        Err(error) => RazeError::Io(error),
+    }
+}
+
+/// Encrypts a stream with a password, using the default key derivation
+/// parameters and the cipher best suited to the current CPU.
+pub fn encrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    password: &str,
+) -> Result<(), RazeError> {
+    let mut encryptor =
+        EncryptWriter::new(writer, password, KdfParams::default(), Cipher::default())?;
+    io::copy(&mut reader, &mut encryptor).map_err(unwrap_crypto_error)?;
+    encryptor.finish()?;
+    Ok(())
+}
 
+/// Decrypts a stream with a password, using the cipher recorded in its header.
+pub fn decrypt_stream<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    password: &str,
+) -> Result<(), RazeError> {
+    let mut decryptor = DecryptReader::new(reader, password)?;
+    io::copy(&mut decryptor, &mut writer).map_err(unwrap_crypto_error)?;
     Ok(())
 }
@@ -0,0 +1,89 @@
+//! # Free Space Module
+//!
+//! This module checks that a filesystem has room for the data about to be
+//! written to it, so that packing and unpacking can fail before any work is
+//! done rather than on a full disk hours later. Free space is queried with
+//! `statvfs` on Unix; elsewhere nothing is checked.
+
+use crate::utils::errors::RazeError;
+use std::io;
+use std::path::Path;
+
+/// The space available to the current user on a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpace {
+    /// The number of bytes that can still be written.
+    pub available: u64,
+    /// The size of the blocks that files are allocated in.
+    pub block_size: u64,
+}
+
+impl FreeSpace {
+    /// Returns the space a file of `len` bytes takes up, rounded up to whole blocks.
+    pub fn allocated(&self, len: u64) -> u64 {
+        len.div_ceil(self.block_size)
+            .saturating_mul(self.block_size)
+    }
+
+    /// Fails with [`RazeError::InsufficientSpace`] unless `needed` bytes fit.
+    /// `path` and `needed` are named in the error, where `needed` is
+    /// described as `what`, e.g. "at least" or "up to".
+    pub fn check(&self, path: &Path, needed: u64, what: &str) -> Result<(), RazeError> {
+        if needed <= self.available {
+            return Ok(());
+        }
+        Err(RazeError::InsufficientSpace(format!(
+            "'{}' needs {} {} bytes, but only {} bytes are free",
+            path.display(),
+            what,
+            needed,
+            self.available
+        )))
+    }
+}
+
+/// Returns the free space on the filesystem holding `path`, or `None` where it
+/// cannot be queried.
+///
+/// `path` need not exist yet: its nearest existing ancestor is queried, which
+/// is where it would be created.
+pub fn free_space(path: &Path) -> io::Result<Option<FreeSpace>> {
+    let mut existing = path;
+    while !existing.exists() {
+        existing = match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+    query(existing)
+}
+
+#[cfg(unix)]
+fn query(path: &Path) -> io::Result<Option<FreeSpace>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stat` is valid
+    // for writes; both outlive the call.
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `statvfs` succeeded, so it has filled in `stat`.
+    let stat = unsafe { stat.assume_init() };
+    // The field types differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    let (blocks, block_size) = (stat.f_bavail as u64, stat.f_frsize as u64);
+    Ok(Some(FreeSpace {
+        available: blocks.saturating_mul(block_size),
+        block_size: block_size.max(1),
+    }))
+}
+
+/// Free space is not queried on non-Unix platforms.
+#[cfg(not(unix))]
+fn query(_path: &Path) -> io::Result<Option<FreeSpace>> {
+    Ok(None)
+}
@@ -111,3 +111,1053 @@ fn test_cli_encryption_flow() {
         "CLI unpack with wrong password should fail"
     );
 }
+
+#[test]
+fn test_cli_split_volumes_list_and_test() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("notes.txt");
+    fs::write(&file_path, "Hello, split Raze!\n".repeat(1000)).unwrap();
+
+    let archive_path = dir.path().join("archive.rz");
+
+    // Test packing into tiny volumes
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("--volume-size")
+        .arg("100")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with volumes failed");
+    let first_volume = dir.path().join("archive.rz.001");
+    assert!(first_volume.exists());
+    assert!(dir.path().join("archive.rz.002").exists());
+
+    // Test listing from the first volume
+    let output = Command::new("./target/debug/raze")
+        .arg("--list")
+        .arg("-a")
+        .arg(&first_volume)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI list command failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("notes.txt"));
+
+    // Test verifying from the first volume
+    let output = Command::new("./target/debug/raze")
+        .arg("--test")
+        .arg("-a")
+        .arg(&first_volume)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI test command failed");
+}
+
+#[test]
+fn test_cli_sfx_flow() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, SFX Raze!").unwrap();
+
+    let sfx_path = dir.path().join("extract-me");
+
+    // Test packing a self-extracting executable
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&sfx_path)
+        .arg("--sfx")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --sfx failed");
+    assert!(sfx_path.exists(), "SFX output should keep its name");
+
+    // Test unpacking the SFX file with the regular unpack mode
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&sfx_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI unpack of SFX file failed");
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, SFX Raze!");
+}
+
+#[test]
+fn test_cli_pack_tar_gz_format() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, gzip Raze!").unwrap();
+
+    // Test packing into a gzip tarball; the matching suffix is appended
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(dir.path().join("partner"))
+        .arg("--format")
+        .arg("tar.gz")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --format failed");
+    let archive_path = dir.path().join("partner.tar.gz");
+    assert!(archive_path.exists());
+    assert!(fs::read(&archive_path).unwrap().starts_with(&[0x1F, 0x8B]));
+
+    // Test unpacking detects the format automatically
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI unpack of tar.gz failed");
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, gzip Raze!");
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_cli_convert_to_zip_and_back() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, ZIP Raze!").unwrap();
+    let archive_path = dir.path().join("test_archive.rz");
+    raze::core::compress::pack(&file_path, &archive_path, None).unwrap();
+
+    // Test converting to ZIP; the `.zip` suffix is appended
+    let output = Command::new("./target/debug/raze")
+        .arg("--convert")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-o")
+        .arg(dir.path().join("partner"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI convert to ZIP failed");
+    let zip_path = dir.path().join("partner.zip");
+    assert!(fs::read(&zip_path).unwrap().starts_with(b"PK\x03\x04"));
+
+    // Test converting the ZIP file back into an archive
+    let output = Command::new("./target/debug/raze")
+        .arg("--convert")
+        .arg("-a")
+        .arg(&zip_path)
+        .arg("-o")
+        .arg(dir.path().join("roundtrip"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI convert from ZIP failed");
+    let unpack_dir = tempdir().unwrap();
+    raze::core::decompress::unpack(dir.path().join("roundtrip.rz"), unpack_dir.path(), None)
+        .unwrap();
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, ZIP Raze!");
+}
+
+#[test]
+fn test_cli_cipher_selection() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, portable Raze!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+
+    // Test packing with an explicitly chosen cipher
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--cipher")
+        .arg("xchacha20poly1305")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --cipher failed");
+    let bytes = fs::read(&archive_path).unwrap();
+    assert_eq!(bytes[5], 2);
+
+    // Test unpacking picks the cipher recorded in the archive
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack of XChaCha20-Poly1305 archive failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, portable Raze!");
+}
+
+#[test]
+fn test_cli_padding() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, padded Raze!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+
+    // Test padding an unencrypted archive is refused
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("--pad")
+        .arg("pow2")
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI pack with --pad and no password should fail"
+    );
+
+    // Test packing with power-of-two padding
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .arg("--pad")
+        .arg("pow2")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --pad failed");
+    assert!(fs::metadata(&archive_path).unwrap().len().is_power_of_two());
+
+    let output = Command::new("./target/debug/raze")
+        .arg("--test")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI test of padded archive failed");
+}
+
+#[test]
+fn test_cli_keygen_and_recipients() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, recipient!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    let identity_path = dir.path().join("backup.key");
+
+    // Test generating an identity prints its public key
+    let output = Command::new("./target/debug/raze")
+        .arg("--keygen")
+        .arg("-o")
+        .arg(&identity_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI keygen failed");
+    let recipient = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert!(recipient.starts_with("raze-pub-"));
+    assert!(fs::read_to_string(&identity_path)
+        .unwrap()
+        .contains(&recipient));
+
+    // Test packing to the recipient needs no secret
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("--recipient")
+        .arg(&recipient)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --recipient failed");
+
+    // Test unpacking without the identity fails
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI unpack without identity should fail"
+    );
+
+    // Test unpacking with the identity file
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--identity")
+        .arg(&identity_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI unpack with --identity failed");
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, recipient!");
+}
+
+#[test]
+fn test_cli_sign_and_verify() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, signature!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    let key_path = dir.path().join("release.key");
+    let trusted_dir = dir.path().join("trusted");
+    fs::create_dir(&trusted_dir).unwrap();
+
+    // Test generating a signing key prints its public key
+    let output = Command::new("./target/debug/raze")
+        .arg("--keygen")
+        .arg("--signing")
+        .arg("-o")
+        .arg(&key_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI keygen --signing failed");
+    let public_key = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert!(public_key.starts_with("raze-sign-"));
+
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .arg("--sign")
+        .arg(&key_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --sign failed");
+
+    // Test the archive verifies against its public key
+    let output = Command::new("./target/debug/raze")
+        .arg("--test")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--verify-key")
+        .arg(&public_key)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI test with --verify-key failed");
+
+    // Test unpacking refuses an archive signed by a key that is not trusted
+    let other_key_path = dir.path().join("other.key");
+    let output = Command::new("./target/debug/raze")
+        .arg("--keygen")
+        .arg("--signing")
+        .arg("-o")
+        .arg(&other_key_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI keygen --signing failed");
+    fs::write(trusted_dir.join("other.pub"), &output.stdout).unwrap();
+
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .arg("--trusted-keys")
+        .arg(&trusted_dir)
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI unpack with an untrusted signer should fail"
+    );
+    assert!(!unpack_dir.path().join("test_file.txt").exists());
+
+    // Test unpacking once the signer is trusted
+    fs::write(trusted_dir.join("release.pub"), &public_key).unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .arg("--trusted-keys")
+        .arg(&trusted_dir)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack with --trusted-keys failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, signature!");
+}
+
+#[test]
+fn test_cli_password_and_keyfile() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, two factors!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    let keyfile_path = dir.path().join("usb.key");
+    fs::write(&keyfile_path, "random bytes kept on a separate stick").unwrap();
+
+    // Test packing with a password and a key file
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--keyfile")
+        .arg(&keyfile_path)
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with --keyfile failed");
+
+    // Test unpacking with only the password names the missing key file
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI unpack without key file should fail"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("key file was not provided"));
+
+    // Test unpacking with both factors
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .arg("--keyfile")
+        .arg(&keyfile_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI unpack with --keyfile failed");
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, two factors!");
+}
+
+#[test]
+fn test_cli_rekey_and_password_slots() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, new password!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    let keyfile_path = dir.path().join("usb.key");
+    fs::write(&keyfile_path, "random bytes kept on a separate stick").unwrap();
+
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI pack failed");
+
+    // Test changing the password
+    let output = Command::new("./target/debug/raze")
+        .arg("--rekey")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--new-password")
+        .arg("password456")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI --rekey failed");
+
+    // Test adding a key file as a second way in
+    let output = Command::new("./target/debug/raze")
+        .arg("--add-password")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password456")
+        .arg("--new-keyfile")
+        .arg(&keyfile_path)
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI --add-password failed");
+
+    // Test removing the password, leaving only the key file
+    let output = Command::new("./target/debug/raze")
+        .arg("--remove-password")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password456")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI --remove-password failed");
+
+    // Test that neither password opens the archive any more
+    for password in ["password123", "password456"] {
+        let output = Command::new("./target/debug/raze")
+            .arg("--test")
+            .arg("-a")
+            .arg(&archive_path)
+            .arg("-p")
+            .arg(password)
+            .output()
+            .unwrap();
+        assert!(
+            !output.status.success(),
+            "{} still opens the archive",
+            password
+        );
+    }
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--keyfile")
+        .arg(&keyfile_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "CLI unpack with the added key file failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, new password!");
+}
+
+#[test]
+fn test_cli_kdf_parameters() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_file.txt");
+    fs::write(&file_path, "Hello, tuned Raze!").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+
+    // Test packing with a profile whose parameters are partly overridden
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("-p")
+        .arg("password123")
+        .arg("--kdf-profile")
+        .arg("interactive")
+        .arg("--kdf-memory")
+        .arg("1M")
+        .arg("--kdf-iterations")
+        .arg("1")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack with KDF flags failed");
+    let bytes = fs::read(&archive_path).unwrap();
+    // The memory cost follows the base nonce, the slot area length, the slot count,
+    // the slot type and the factors.
+    let nonce_len = if bytes[5] == 1 { 12 } else { 24 };
+    let memory = 4 + 1 + 1 + nonce_len + 4 + 2 + 1 + 1;
+    assert_eq!(&bytes[memory..memory + 4], &1024u32.to_le_bytes());
+
+    // Test unpacking reads the parameters from the archive
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("-p")
+        .arg("password123")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack of tuned archive failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("test_file.txt")).unwrap();
+    assert_eq!(content, "Hello, tuned Raze!");
+
+    // Test that an unknown profile is rejected
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("--kdf-profile")
+        .arg("extreme")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_unsafe_paths() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("absolute.tar");
+    let mut builder = tar::Builder::new(File::create(&archive_path).unwrap());
+    let mut header = tar::Header::new_ustar();
+    let name = b"/absolute.txt";
+    header.as_ustar_mut().unwrap().name[..name.len()].copy_from_slice(name);
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &b"Hello"[..]).unwrap();
+    builder.into_inner().unwrap();
+
+    // Test unpacking refuses the absolute path by default
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI unpack of an absolute path should fail"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("/absolute.txt"));
+
+    // Test --unsafe-paths leaves the entry to the tar crate
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--unsafe-paths")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack with --unsafe-paths failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("absolute.txt")).unwrap();
+    assert_eq!(content, "Hello");
+}
+
+#[test]
+fn test_cli_extraction_limits() {
+    let dir = tempdir().unwrap();
+    let source_dir = dir.path().join("source");
+    fs::create_dir(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), "first").unwrap();
+    fs::write(source_dir.join("b.txt"), "second").unwrap();
+    let archive_path = dir.path().join("limits.rz");
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&source_dir)
+        .arg("-o")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI pack failed");
+
+    // Test --max-entries stops an archive with more entries
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--max-entries")
+        .arg("2")
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI unpack beyond --max-entries should fail"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than 2 entries"));
+
+    // Test --no-limits lifts the default limits but keeps explicit ones
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--no-limits")
+        .arg("--max-file-size")
+        .arg("5B")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("b.txt"));
+
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--no-limits")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack with --no-limits failed"
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("source/b.txt")).unwrap();
+    assert_eq!(content, "second");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    let dir = tempdir().unwrap();
+    let source_dir = dir.path().join("source");
+    fs::create_dir(&source_dir).unwrap();
+    let script = source_dir.join("run.sh");
+    fs::write(&script, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o2775)).unwrap();
+    let archive_path = dir.path().join("modes.rz");
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&source_dir)
+        .arg("-o")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI pack failed");
+
+    let unpack = |destination: &Path, extra: &[&str]| {
+        Command::new("./target/debug/raze")
+            .arg("--unpack")
+            .arg("-a")
+            .arg(&archive_path)
+            .arg("-d")
+            .arg(destination)
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    let mode = |destination: &Path| {
+        fs::metadata(destination.join("source/run.sh"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777
+    };
+
+    // Test the setgid bit is cleared by default
+    let default_dir = dir.path().join("default");
+    assert!(unpack(&default_dir, &[]).status.success());
+    assert_eq!(mode(&default_dir), 0o775);
+
+    // Test --preserve-permissions keeps it and --umask clears the given bits
+    let preserve_dir = dir.path().join("preserve");
+    let output = unpack(&preserve_dir, &["--preserve-permissions", "--umask", "022"]);
+    assert!(output.status.success(), "CLI unpack with --umask failed");
+    assert_eq!(mode(&preserve_dir), 0o2755);
+
+    // Test --no-same-permissions overrides an earlier --preserve-permissions
+    let reset_dir = dir.path().join("reset");
+    let output = unpack(
+        &reset_dir,
+        &["--preserve-permissions", "--no-same-permissions"],
+    );
+    assert!(output.status.success());
+    assert_eq!(mode(&reset_dir), 0o775);
+
+    // Test an invalid mask is rejected
+    let output = unpack(&reset_dir, &["--umask", "999"]);
+    assert!(!output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_allow_special_files() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("fifo.tar");
+    let mut builder = tar::Builder::new(File::create(&archive_path).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_path("queue").unwrap();
+    header.set_entry_type(tar::EntryType::Fifo);
+    header.set_size(0);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, std::io::empty()).unwrap();
+    builder.into_inner().unwrap();
+
+    // Test unpacking refuses the FIFO by default
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success(), "CLI unpack of a FIFO should fail");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("queue"));
+
+    // Test --allow-special-files creates it
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--allow-special-files")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "CLI unpack with --allow-special-files failed"
+    );
+    let metadata = fs::symlink_metadata(unpack_dir.path().join("queue")).unwrap();
+    assert!(metadata.file_type().is_fifo());
+}
+
+#[test]
+fn test_cli_atomic() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("notes.txt");
+    fs::write(&file_path, "staged content").unwrap();
+    let archive_path = dir.path().join("notes.rz");
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "CLI pack failed");
+
+    // Test --atomic moves the entries into place and removes the staging directory
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new("./target/debug/raze")
+        .arg("--unpack")
+        .arg("-a")
+        .arg(&archive_path)
+        .arg("-d")
+        .arg(unpack_dir.path())
+        .arg("--atomic")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI unpack with --atomic failed");
+    let names: Vec<_> = fs::read_dir(unpack_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["notes.txt"]);
+}
+
+#[test]
+fn test_cli_no_clobber() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("notes.txt");
+    fs::write(&file_path, "keep me").unwrap();
+    let archive_path = dir.path().join("notes.rz");
+    fs::write(&archive_path, "an existing archive").unwrap();
+
+    // Test --no-clobber refuses to replace the existing archive
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .arg("--no-clobber")
+        .output()
+        .unwrap();
+
+    assert!(
+        !output.status.success(),
+        "CLI pack with --no-clobber should fail"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to replace"));
+    assert_eq!(
+        fs::read_to_string(&archive_path).unwrap(),
+        "an existing archive"
+    );
+
+    // Test packing without it replaces the archive
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&file_path)
+        .arg("-o")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "CLI pack failed");
+    assert_ne!(fs::read(&archive_path).unwrap(), b"an existing archive");
+}
+
+#[test]
+fn test_cli_free_space_check() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir(&source).unwrap();
+    // A sparse file larger than the disk, which takes up no space itself
+    File::create(source.join("sparse.bin"))
+        .unwrap()
+        .set_len(1 << 43)
+        .unwrap();
+    let archive_path = dir.path().join("sparse.rz");
+
+    // Test packing is refused before anything is written
+    let output = Command::new("./target/debug/raze")
+        .arg("--pack")
+        .arg("-s")
+        .arg(&source)
+        .arg("-o")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success(), "CLI pack should fail");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Not enough free space"));
+    assert!(!archive_path.exists());
+
+    // Test --force and --no-space-check skip the check, here leaving the entry limits to stop the extraction
+    let mut header = tar::Header::new_ustar();
+    header.set_path("huge.bin").unwrap();
+    header.set_size(1 << 60);
+    header.set_cksum();
+    let tar_path = dir.path().join("huge.tar");
+    fs::write(&tar_path, header.as_bytes()).unwrap();
+    for flag in [None, Some("--force"), Some("--no-space-check")] {
+        let mut command = Command::new("./target/debug/raze");
+        command
+            .arg("--unpack")
+            .arg("-a")
+            .arg(&tar_path)
+            .arg("-d")
+            .arg(dir.path().join("out"));
+        command.args(flag);
+        let output = command.output().unwrap();
+
+        assert!(!output.status.success(), "CLI unpack should fail");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(
+            stderr.contains("Not enough free space"),
+            flag.is_none(),
+            "{stderr}"
+        );
+    }
+}
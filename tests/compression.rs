@@ -9,7 +9,9 @@
 //! guaranteeing that test runs do not interfere with the actual file system
 //! and are clean upon completion.
 
+use raze::core::compress::PackOptions;
 use raze::core::{compress, decompress};
+use raze::utils::errors::RazeError;
 use std::fs::{self, File};
 use std::io::Write;
 use tempfile::tempdir;
@@ -137,3 +139,141 @@ fn test_encryption_flow() {
     let result = decompress::unpack(&archive_path, unpack_dir_fail.path(), Some("wrongpassword"));
     assert!(result.is_err());
 }
+
+/// Tests that a failed pack leaves an existing archive untouched and no
+/// temporary file behind, and that `no_clobber` refuses to replace it.
+#[test]
+fn test_existing_archives_are_protected() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("notes.txt");
+    fs::write(&file_path, "first version").unwrap();
+    let archive_path = dir.path().join("notes.rz");
+    compress::pack(&file_path, &archive_path, None).unwrap();
+    let original = fs::read(&archive_path).unwrap();
+
+    // The missing stub is only noticed after the output file was created.
+    fs::write(&file_path, "second version").unwrap();
+    let failing = PackOptions {
+        sfx_stub: Some(dir.path().join("missing-stub")),
+        ..PackOptions::default()
+    };
+    assert!(compress::pack_with_options(&file_path, &archive_path, &failing).is_err());
+    assert_eq!(fs::read(&archive_path).unwrap(), original);
+    let mut names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["notes.rz", "notes.txt"]);
+
+    let no_clobber = PackOptions {
+        no_clobber: true,
+        ..PackOptions::default()
+    };
+    match compress::pack_with_options(&file_path, &archive_path, &no_clobber) {
+        Err(RazeError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists),
+        other => panic!("expected the archive to be kept, got {:?}", other),
+    }
+    assert_eq!(fs::read(&archive_path).unwrap(), original);
+
+    // The same applies to the volumes of a split archive.
+    let volumes = PackOptions {
+        volume_size: Some(4096),
+        no_clobber: true,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &volumes).unwrap();
+    assert!(compress::pack_with_options(&file_path, &archive_path, &volumes).is_err());
+
+    // Without it, the archive is replaced once the new one is complete.
+    compress::pack(&file_path, &archive_path, None).unwrap();
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&archive_path, unpack_dir.path(), None).unwrap();
+    let content = fs::read_to_string(unpack_dir.path().join("notes.txt")).unwrap();
+    assert_eq!(content, "second version");
+}
+
+/// Tests that packing a directory into an archive inside it leaves the archive
+/// being written, and any older one it replaces, out of itself.
+#[test]
+fn test_output_inside_the_source_is_left_out() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir(&source).unwrap();
+    fs::write(source.join("notes.txt"), "notes ".repeat(100)).unwrap();
+    // Incompressible content, so that the split archive needs several volumes.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let noise: Vec<u8> = (0..64 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(source.join("noise.bin"), &noise).unwrap();
+
+    let names = |archive_path: &std::path::Path| {
+        let mut names: Vec<_> = decompress::list(archive_path, None)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path.display().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    let expected = ["source", "source/noise.bin", "source/notes.txt"];
+
+    // The second run also finds the archive written by the first.
+    let archive_path = source.join("self.rz");
+    for _ in 0..2 {
+        compress::pack(&source, &archive_path, None).unwrap();
+        assert_eq!(names(&archive_path), expected);
+    }
+
+    let volumes = PackOptions {
+        volume_size: Some(16 * 1024),
+        ..PackOptions::default()
+    };
+    fs::remove_file(&archive_path).unwrap();
+    let split_path = source.join("split.rz");
+    for _ in 0..2 {
+        compress::pack_with_options(&source, &split_path, &volumes).unwrap();
+        let first = source.join("split.rz.001");
+        assert!(source.join("split.rz.003").exists());
+        assert_eq!(names(&first), expected);
+    }
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(source.join("split.rz.001"), unpack_dir.path(), None).unwrap();
+    assert_eq!(
+        fs::read(unpack_dir.path().join("source/noise.bin")).unwrap(),
+        noise
+    );
+}
+
+/// Tests that packing is refused before anything is written when the archive
+/// might not fit into the free space at the output path.
+#[test]
+fn test_packing_is_refused_without_free_space() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir(&source).unwrap();
+    // A sparse file larger than the disk, which takes up no space itself.
+    File::create(source.join("sparse.bin"))
+        .unwrap()
+        .set_len(1 << 43)
+        .unwrap();
+    let archive_path = dir.path().join("sparse.rz");
+    let options = PackOptions {
+        check_space: true,
+        ..PackOptions::default()
+    };
+    let result = compress::pack_with_options(&source, &archive_path, &options);
+    assert!(
+        matches!(result, Err(RazeError::InsufficientSpace(ref message)) if message.contains("sparse.rz")),
+        "{result:?}"
+    );
+    assert!(!archive_path.exists());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
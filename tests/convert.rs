@@ -0,0 +1,209 @@
+//! # Integration Tests for ZIP Conversion
+//!
+//! This module verifies that Raze archives can be converted into ZIP files and
+//! back without losing file contents, permissions or modification times, and
+//! that unsafe entry names in foreign ZIP files are rejected.
+
+#![cfg(feature = "zip")]
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::{convert, decompress};
+use raze::utils::errors::RazeError;
+use raze::utils::security::{Credentials, Identity};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use tar::{Builder, Header};
+use tempfile::tempdir;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// A fixed modification time (2023-11-14 22:13:21 UTC) with an odd number of
+/// seconds, which the DOS timestamp of a ZIP entry alone cannot represent.
+const MTIME: u64 = 1_700_000_001;
+
+/// Writes an uncompressed archive with a script, a data file and a directory,
+/// each carrying explicit permissions and modification times.
+fn write_sample_archive(path: &Path) {
+    let mut builder = Builder::new(File::create(path).unwrap());
+    let mut append = |name: &str, mode: u32, data: &[u8]| {
+        let mut header = Header::new_gnu();
+        header.set_mode(mode);
+        header.set_mtime(MTIME);
+        header.set_size(data.len() as u64);
+        if name.ends_with('/') {
+            header.set_entry_type(tar::EntryType::Directory);
+        }
+        builder.append_data(&mut header, name, data).unwrap();
+    };
+    append("project/", 0o750, b"");
+    append("project/run.sh", 0o755, b"#!/bin/sh\necho converted\n");
+    append("project/data.txt", 0o600, b"Hello, ZIP consumers!");
+    builder.into_inner().unwrap().sync_all().unwrap();
+}
+
+#[test]
+fn test_archive_zip_roundtrip_preserves_metadata() {
+    let dir = tempdir().unwrap();
+    let tar_path = dir.path().join("project.tar");
+    write_sample_archive(&tar_path);
+
+    let zip_path = dir.path().join("project.zip");
+    convert::rz_to_zip(&tar_path, &zip_path, &Credentials::default()).unwrap();
+
+    let mut zip = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    let script = zip.by_name("project/run.sh").unwrap();
+    assert_eq!(script.unix_mode().unwrap() & 0o7777, 0o755);
+    drop(script);
+    assert!(zip.by_name("project/").unwrap().is_dir());
+
+    let rz_path = dir.path().join("project.rz");
+    convert::zip_to_rz(&zip_path, &rz_path, &PackOptions::default()).unwrap();
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&rz_path, unpack_dir.path(), None).unwrap();
+    let project = unpack_dir.path().join("project");
+    assert_eq!(
+        fs::read_to_string(project.join("data.txt")).unwrap(),
+        "Hello, ZIP consumers!"
+    );
+
+    let metadata = fs::metadata(project.join("data.txt")).unwrap();
+    let mtime = metadata
+        .modified()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert_eq!(mtime, MTIME);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |name: &str| {
+            fs::metadata(project.join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+        };
+        assert_eq!(mode("run.sh") & 0o777, 0o755);
+        assert_eq!(mode("data.txt") & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn test_encrypted_conversion() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    fs::write(&file_path, "Only for partners.").unwrap();
+    let archive_path = dir.path().join("secret.rz");
+    compress::pack(&file_path, &archive_path, Some("password123")).unwrap();
+
+    // The encrypted archive is decrypted on the fly.
+    let zip_path = dir.path().join("secret.zip");
+    assert!(convert::rz_to_zip(&archive_path, &zip_path, &Credentials::default()).is_err());
+    let credentials = Credentials::from_password("password123");
+    convert::rz_to_zip(&archive_path, &zip_path, &credentials).unwrap();
+    assert!(convert::is_zip(&zip_path).unwrap());
+
+    // A password given when converting back encrypts the new archive.
+    let options = PackOptions {
+        password: Some("new-password".into()),
+        ..PackOptions::default()
+    };
+    let converted_path = dir.path().join("converted.rz");
+    convert::convert(&zip_path, &converted_path, &options).unwrap();
+    assert!(decompress::is_encrypted(&converted_path).unwrap());
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&converted_path, unpack_dir.path(), Some("new-password")).unwrap();
+    assert_eq!(
+        fs::read_to_string(unpack_dir.path().join("secret.txt")).unwrap(),
+        "Only for partners."
+    );
+}
+
+#[test]
+fn test_recipient_archive_conversion() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    fs::write(&file_path, "Only for Alice.").unwrap();
+    let archive_path = dir.path().join("secret.rz");
+    let alice = Identity::generate().unwrap();
+    let options = PackOptions {
+        recipients: vec![alice.recipient()],
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+
+    let zip_path = dir.path().join("secret.zip");
+    let other = Credentials {
+        identities: vec![Identity::generate().unwrap()],
+        ..Credentials::default()
+    };
+    assert!(convert::rz_to_zip(&archive_path, &zip_path, &other).is_err());
+    let credentials = Credentials {
+        identities: vec![alice],
+        ..Credentials::default()
+    };
+    convert::rz_to_zip(&archive_path, &zip_path, &credentials).unwrap();
+
+    let mut zip = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    let mut content = String::new();
+    zip.by_name("secret.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "Only for Alice.");
+}
+
+#[test]
+fn test_truncated_archive_is_not_converted() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("backup.bin");
+    // Incompressible data keeps the archive several chunks long.
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let data: Vec<u8> = (0..300 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(&file_path, &data).unwrap();
+    let archive_path = dir.path().join("backup.rz");
+    compress::pack(&file_path, &archive_path, Some("password123")).unwrap();
+
+    // Cut the archive off in the middle, as an interrupted download would.
+    let ciphertext = fs::read(&archive_path).unwrap();
+    fs::write(&archive_path, &ciphertext[..ciphertext.len() / 2]).unwrap();
+
+    let zip_path = dir.path().join("backup.zip");
+    let credentials = Credentials::from_password("password123");
+    let result = convert::rz_to_zip(&archive_path, &zip_path, &credentials);
+    assert!(
+        matches!(result, Err(RazeError::TruncationError(_))),
+        "unexpected result: {:?}",
+        result
+    );
+    assert!(!zip_path.exists());
+}
+
+#[test]
+fn test_zip_with_unsafe_path_is_rejected() {
+    let dir = tempdir().unwrap();
+    let zip_path = dir.path().join("evil.zip");
+    let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+    zip.start_file("../escape.txt", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(b"outside").unwrap();
+    zip.finish().unwrap();
+
+    let result = convert::zip_to_rz(
+        &zip_path,
+        dir.path().join("evil.rz"),
+        &PackOptions::default(),
+    );
+    assert!(matches!(result, Err(RazeError::ConversionError(_))));
+}
@@ -0,0 +1,731 @@
+//! # Integration Tests for the Extraction Policy
+//!
+//! This module verifies that extraction refuses entries with absolute paths or
+//! `..` components, links pointing outside the destination, entries placed
+//! beneath symbolic links and special files, reporting each with its own error, that
+//! `ExtractPolicy::permissive` restores the behaviour of the `tar` crate, that
+//! archives exceeding the `ExtractLimits` are stopped, and that stored modes
+//! and owners are applied as `ExtractPermissions` describes. It also verifies
+//! that atomic extraction leaves nothing behind when it fails.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::decompress::{self, UnpackOptions};
+use raze::core::extract::{ExtractLimits, ExtractPermissions, ExtractPolicy};
+use raze::utils::errors::RazeError;
+use raze::utils::security::Credentials;
+use std::fs;
+use std::path::Path;
+use tar::{EntryType, Header};
+use tempfile::tempdir;
+
+/// A tarball entry: its type, path, link target and contents.
+type TarEntry<'a> = (EntryType, &'a str, &'a str, &'a [u8]);
+
+/// Writes a plain tarball holding `entries`, storing the paths verbatim so
+/// that the tarball can contain what a well-behaved archiver would refuse to write.
+fn write_tarball(path: &Path, entries: &[TarEntry]) {
+    let mut builder = tar::Builder::new(fs::File::create(path).unwrap());
+    for &(entry_type, name, link, data) in entries {
+        let mut header = Header::new_ustar();
+        let ustar = header.as_ustar_mut().unwrap();
+        ustar.name[..name.len()].copy_from_slice(name.as_bytes());
+        ustar.linkname[..link.len()].copy_from_slice(link.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_size(data.len() as u64);
+        header.set_mode(if entry_type.is_dir() { 0o755 } else { 0o644 });
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+/// Extracts `archive_path` into `destination` under `policy`.
+fn unpack(archive_path: &Path, destination: &Path, policy: ExtractPolicy) -> Result<(), RazeError> {
+    let options = UnpackOptions {
+        policy,
+        ..UnpackOptions::default()
+    };
+    decompress::unpack_with_options(archive_path, destination, &options)
+}
+
+#[test]
+fn test_absolute_and_parent_paths_are_refused() {
+    let dir = tempdir().unwrap();
+    let destination = dir.path().join("out");
+    let archive_path = dir.path().join("traversal.tar");
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Regular, "fine.txt", "", b"fine"),
+            (EntryType::Regular, "sub/../../evil.txt", "", b"evil"),
+        ],
+    );
+
+    let result = unpack(&archive_path, &destination, ExtractPolicy::default());
+    assert!(
+        matches!(result, Err(RazeError::UnsafePath(ref path)) if path == "sub/../../evil.txt"),
+        "unexpected result: {:?}",
+        result
+    );
+    assert!(!dir.path().join("evil.txt").exists());
+
+    let archive_path = dir.path().join("absolute.tar");
+    write_tarball(
+        &archive_path,
+        &[(EntryType::Regular, "/tmp/absolute.txt", "", b"absolute")],
+    );
+    let result = unpack(&archive_path, &destination, ExtractPolicy::default());
+    assert!(matches!(result, Err(RazeError::UnsafePath(_))));
+
+    // The permissive policy leaves both to the `tar` crate, which strips the
+    // leading slash and skips the `..` entry.
+    unpack(&archive_path, &destination, ExtractPolicy::permissive()).unwrap();
+    assert!(destination.join("tmp/absolute.txt").exists());
+    let archive_path = dir.path().join("traversal.tar");
+    unpack(&archive_path, &destination, ExtractPolicy::permissive()).unwrap();
+    assert!(destination.join("fine.txt").exists());
+    assert!(!dir.path().join("evil.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_escaping_links_are_refused() {
+    let dir = tempdir().unwrap();
+    let cases: &[(&str, &[TarEntry])] = &[
+        ("up", &[(EntryType::Symlink, "a/up", "../..", b"")]),
+        (
+            "absolute",
+            &[(EntryType::Symlink, "passwd", "/etc/passwd", b"")],
+        ),
+        (
+            // Each link stays inside on its own, but `deep/b/..` leads outside.
+            "chain",
+            &[
+                (EntryType::Directory, "deep", "", b""),
+                (EntryType::Symlink, "deep/b", "..", b""),
+                (EntryType::Symlink, "deep/a", "b/..", b""),
+            ],
+        ),
+        ("hard", &[(EntryType::Link, "hard", "../secret", b"")]),
+    ];
+
+    for (name, entries) in cases {
+        let archive_path = dir.path().join(format!("{}.tar", name));
+        write_tarball(&archive_path, entries);
+        let result = unpack(
+            &archive_path,
+            &dir.path().join(name),
+            ExtractPolicy::default(),
+        );
+        assert!(
+            matches!(result, Err(RazeError::LinkEscape { .. })),
+            "{}: unexpected result: {:?}",
+            name,
+            result
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_writing_through_symlinks_is_refused() {
+    let dir = tempdir().unwrap();
+    let outside = dir.path().join("outside");
+    fs::create_dir(&outside).unwrap();
+    let archive_path = dir.path().join("through.tar");
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Symlink, "escape", outside.to_str().unwrap(), b""),
+            (EntryType::Regular, "escape/pwned.txt", "", b"pwned"),
+        ],
+    );
+
+    let policy = ExtractPolicy {
+        allow_escaping_links: true,
+        ..ExtractPolicy::default()
+    };
+    let result = unpack(&archive_path, &dir.path().join("out"), policy);
+    assert!(
+        matches!(result, Err(RazeError::SymlinkTraversal { ref path, ref link })
+            if path == "escape/pwned.txt" && link == "escape"),
+        "unexpected result: {:?}",
+        result
+    );
+    assert!(fs::read_dir(&outside).unwrap().next().is_none());
+
+    // Even a link that stays inside may not be written through.
+    let archive_path = dir.path().join("inside.tar");
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Directory, "real", "", b""),
+            (EntryType::Symlink, "alias", "real", b""),
+            (EntryType::Regular, "alias/file.txt", "", b"data"),
+        ],
+    );
+    let result = unpack(
+        &archive_path,
+        &dir.path().join("inside"),
+        ExtractPolicy::default(),
+    );
+    assert!(matches!(result, Err(RazeError::SymlinkTraversal { .. })));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relative_links_inside_the_destination_are_extracted() {
+    let dir = tempdir().unwrap();
+    let destination = dir.path().join("out");
+    let archive_path = dir.path().join("libs.tar");
+    // The links come before their targets, and the first one dangles while
+    // the second is resolved through it.
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Symlink, "lib/libfoo.so.1", "libfoo.so.1.2", b""),
+            (EntryType::Symlink, "lib/libfoo.so", "libfoo.so.1", b""),
+            (EntryType::Regular, "lib/libfoo.so.1.2", "", b"ELF"),
+            (EntryType::Link, "lib/libfoo.hard", "lib/libfoo.so.1.2", b""),
+            (EntryType::Symlink, "share/lib", "../lib", b""),
+        ],
+    );
+
+    unpack(&archive_path, &destination, ExtractPolicy::default()).unwrap();
+    assert_eq!(fs::read(destination.join("lib/libfoo.so")).unwrap(), b"ELF");
+    assert_eq!(
+        fs::read(destination.join("lib/libfoo.hard")).unwrap(),
+        b"ELF"
+    );
+    assert!(destination.join("share/lib/libfoo.so.1.2").exists());
+
+    // Archives written by Raze keep their symbolic links.
+    let source = dir.path().join("source");
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::write(source.join("docs/readme.txt"), "read me").unwrap();
+    std::os::unix::fs::symlink("docs/readme.txt", source.join("README")).unwrap();
+    let archive_path = dir.path().join("source.rz");
+    compress::pack(&source, &archive_path, None).unwrap();
+    let destination = dir.path().join("roundtrip");
+    decompress::unpack(&archive_path, &destination, None).unwrap();
+    assert_eq!(
+        fs::read_to_string(destination.join("source/README")).unwrap(),
+        "read me"
+    );
+}
+
+/// Asserts that `result` is a `RazeError::LimitExceeded` mentioning `expected`.
+fn assert_limit_exceeded(result: Result<(), RazeError>, expected: &str) {
+    match result {
+        Err(RazeError::LimitExceeded(msg)) => {
+            assert!(msg.contains(expected), "unexpected message: {}", msg)
+        },
+        other => panic!("expected a limit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_archives_exceeding_the_limits_are_stopped() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("sample.tar");
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Directory, "a/b/c", "", b""),
+            (EntryType::Regular, "a/b/c/small.txt", "", b"small"),
+            (EntryType::Regular, "a/large.txt", "", &[b'x'; 100]),
+            (EntryType::Regular, "a/last.txt", "", b"last"),
+        ],
+    );
+    let limits = ExtractLimits {
+        max_total_size: Some(200),
+        max_entries: Some(4),
+        max_file_size: Some(100),
+        max_ratio: None,
+        max_depth: Some(4),
+    };
+    let unpack_with = |name: &str, limits: ExtractLimits| {
+        let options = UnpackOptions {
+            limits,
+            ..UnpackOptions::default()
+        };
+        decompress::unpack_with_options(&archive_path, dir.path().join(name), &options)
+    };
+
+    unpack_with("fits", limits).unwrap();
+    let cases = [
+        (
+            ExtractLimits {
+                max_entries: Some(3),
+                ..limits
+            },
+            "more than 3 entries",
+        ),
+        (
+            ExtractLimits {
+                max_depth: Some(3),
+                ..limits
+            },
+            "'a/b/c/small.txt' is 4 levels deep",
+        ),
+        (
+            ExtractLimits {
+                max_file_size: Some(99),
+                ..limits
+            },
+            "'a/large.txt' is 100 bytes",
+        ),
+        (
+            ExtractLimits {
+                max_total_size: Some(104),
+                ..limits
+            },
+            "extracting 'a/large.txt' would write more than 104 bytes",
+        ),
+    ];
+    for (i, (limits, expected)) in cases.into_iter().enumerate() {
+        let name = format!("case{}", i);
+        assert_limit_exceeded(unpack_with(&name, limits), expected);
+        // Nothing is written for the entry that exceeds a limit.
+        assert!(!dir.path().join(name).join("a/last.txt").exists());
+    }
+}
+
+#[test]
+fn test_highly_compressed_archives_are_stopped() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("bomb");
+    fs::create_dir(&source).unwrap();
+    fs::write(source.join("zeros.bin"), vec![0u8; 16 << 20]).unwrap();
+    let archive_path = dir.path().join("bomb.rz");
+    compress::pack(&source, &archive_path, None).unwrap();
+
+    let destination = dir.path().join("out");
+    assert_limit_exceeded(
+        decompress::unpack(&archive_path, &destination, None),
+        "more than 1000 times its compressed size",
+    );
+    // The file is cut off long before it is complete.
+    let written = fs::metadata(destination.join("bomb/zeros.bin")).map_or(0, |m| m.len());
+    assert!(written < 16 << 20);
+
+    let options = UnpackOptions {
+        limits: ExtractLimits::unlimited(),
+        ..UnpackOptions::default()
+    };
+    let destination = dir.path().join("trusted");
+    decompress::unpack_with_options(&archive_path, &destination, &options).unwrap();
+    assert_eq!(
+        fs::metadata(destination.join("bomb/zeros.bin"))
+            .unwrap()
+            .len(),
+        16 << 20
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_modes_and_owners_are_controlled() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("modes.tar");
+    let mut builder = tar::Builder::new(fs::File::create(&archive_path).unwrap());
+    for (name, entry_type, mode) in [
+        ("setuid", EntryType::Regular, 0o4755),
+        ("setgid", EntryType::Regular, 0o2750),
+        ("shared", EntryType::Directory, 0o1777),
+    ] {
+        let mut header = Header::new_ustar();
+        header.set_path(name).unwrap();
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        header.set_mode(mode);
+        header.set_uid(4242);
+        header.set_gid(4242);
+        header.set_cksum();
+        builder.append(&header, &[][..]).unwrap();
+    }
+    builder.into_inner().unwrap();
+
+    let unpack_with = |name: &str, permissions: ExtractPermissions| {
+        let options = UnpackOptions {
+            permissions,
+            ..UnpackOptions::default()
+        };
+        let destination = dir.path().join(name);
+        decompress::unpack_with_options(&archive_path, &destination, &options).map(|()| destination)
+    };
+    let modes = |destination: &Path| {
+        ["setuid", "setgid", "shared"].map(|name| {
+            fs::metadata(destination.join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        })
+    };
+
+    // Special bits are cleared and entries belong to the extracting user.
+    let user = fs::metadata(dir.path()).unwrap().uid();
+    let destination = unpack_with("default", ExtractPermissions::default()).unwrap();
+    assert_eq!(modes(&destination), [0o755, 0o750, 0o777]);
+    assert_eq!(
+        fs::metadata(destination.join("setuid")).unwrap().uid(),
+        user
+    );
+
+    let preserve = ExtractPermissions {
+        preserve: true,
+        ..ExtractPermissions::default()
+    };
+    let destination = unpack_with("preserve", preserve).unwrap();
+    assert_eq!(modes(&destination), [0o4755, 0o2750, 0o1777]);
+
+    let masked = ExtractPermissions {
+        umask: 0o027,
+        ..ExtractPermissions::default()
+    };
+    let destination = unpack_with("masked", masked).unwrap();
+    assert_eq!(modes(&destination), [0o750, 0o750, 0o750]);
+
+    // Restoring the stored owner only succeeds with the privileges to do so.
+    let same_owner = ExtractPermissions {
+        same_owner: true,
+        ..ExtractPermissions::default()
+    };
+    match unpack_with("owned", same_owner) {
+        Ok(destination) => {
+            assert_eq!(user, 0);
+            let metadata = fs::metadata(destination.join("setuid")).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (4242, 4242));
+        },
+        Err(err) => assert_ne!(user, 0, "unexpected error: {}", err),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_special_files_are_refused_unless_allowed() {
+    use std::os::unix::fs::FileTypeExt;
+    use std::process::Command;
+
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("fifo.tar");
+    write_tarball(
+        &archive_path,
+        &[
+            (EntryType::Regular, "before.txt", "", b"before"),
+            (EntryType::Fifo, "queue", "", b""),
+        ],
+    );
+    let result = unpack(
+        &archive_path,
+        &dir.path().join("refused"),
+        ExtractPolicy::default(),
+    );
+    assert!(
+        matches!(result, Err(RazeError::SpecialFile(ref path)) if path == "queue"),
+        "unexpected result: {:?}",
+        result
+    );
+
+    let allowed = ExtractPolicy {
+        allow_special_files: true,
+        ..ExtractPolicy::default()
+    };
+    let destination = dir.path().join("allowed");
+    unpack(&archive_path, &destination, allowed).unwrap();
+    let metadata = fs::symlink_metadata(destination.join("queue")).unwrap();
+    assert!(metadata.file_type().is_fifo());
+
+    // Packing skips special files unless they are asked for.
+    let source = dir.path().join("source");
+    fs::create_dir(&source).unwrap();
+    fs::write(source.join("data.txt"), "data").unwrap();
+    let status = Command::new("mkfifo")
+        .arg(source.join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let names = |options: &PackOptions| {
+        let archive_path = dir.path().join("special.rz");
+        let _ = fs::remove_file(&archive_path);
+        compress::pack_with_options(&source, &archive_path, options).unwrap();
+        decompress::list(&archive_path, None)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path.display().to_string())
+            .collect::<Vec<_>>()
+    };
+    let skipped = names(&PackOptions::default());
+    assert!(skipped.contains(&"source/data.txt".to_string()));
+    assert!(!skipped.contains(&"source/pipe".to_string()));
+    let archived = names(&PackOptions {
+        special_files: true,
+        ..PackOptions::default()
+    });
+    assert!(archived.contains(&"source/pipe".to_string()));
+}
+
+#[test]
+fn test_atomic_extraction_is_all_or_nothing() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::write(source.join("docs/readme.txt"), "read me").unwrap();
+    // Pseudo-random data spans many encrypted chunks.
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let data: Vec<u8> = (0..1 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(source.join("data.bin"), &data).unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    compress::pack(&source, &archive_path, Some("password123")).unwrap();
+
+    // Entries already in the destination are kept, and directories are merged.
+    let destination = dir.path().join("out");
+    fs::create_dir_all(destination.join("source/docs")).unwrap();
+    fs::write(destination.join("source/docs/local.txt"), "local").unwrap();
+    let atomic = UnpackOptions {
+        credentials: Credentials::from_password("password123"),
+        atomic: true,
+        ..UnpackOptions::default()
+    };
+    decompress::unpack_with_options(&archive_path, &destination, &atomic).unwrap();
+    assert_eq!(fs::read(destination.join("source/data.bin")).unwrap(), data);
+    assert!(destination.join("source/docs/readme.txt").exists());
+    assert!(destination.join("source/docs/local.txt").exists());
+    assert_eq!(fs::read_dir(&destination).unwrap().count(), 1);
+
+    // Damage a late chunk, which is only noticed after earlier entries were written.
+    let mut bytes = fs::read(&archive_path).unwrap();
+    let len = bytes.len();
+    bytes[len - 1000] ^= 0x01;
+    fs::write(&archive_path, bytes).unwrap();
+
+    let partial = dir.path().join("partial");
+    let options = UnpackOptions {
+        atomic: false,
+        ..atomic.clone()
+    };
+    assert!(decompress::unpack_with_options(&archive_path, &partial, &options).is_err());
+    assert!(fs::read_dir(&partial).unwrap().next().is_some());
+
+    let untouched = dir.path().join("untouched");
+    assert!(decompress::unpack_with_options(&archive_path, &untouched, &atomic).is_err());
+    assert!(fs::read_dir(&untouched).unwrap().next().is_none());
+}
+
+#[test]
+fn test_failed_atomic_move_is_rolled_back() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir(&source).unwrap();
+    for i in 0..20 {
+        fs::write(source.join(format!("file{:02}.txt", i)), "new").unwrap();
+    }
+    fs::write(source.join("conflict"), "new").unwrap();
+    let archive_path = dir.path().join("archive.rz");
+    compress::pack(&source, &archive_path, None).unwrap();
+
+    // A file in the archive cannot replace a directory, which is only noticed
+    // while the extracted entries are being moved into place.
+    let destination = dir.path().join("out");
+    fs::create_dir_all(destination.join("source/conflict")).unwrap();
+    fs::write(destination.join("source/conflict/kept.txt"), "old").unwrap();
+    fs::write(destination.join("source/file05.txt"), "old").unwrap();
+    let options = UnpackOptions {
+        atomic: true,
+        ..UnpackOptions::default()
+    };
+    assert!(decompress::unpack_with_options(&archive_path, &destination, &options).is_err());
+
+    // Every entry moved before the failure has been taken back out, and the
+    // file it replaced has been put back.
+    let mut names: Vec<_> = fs::read_dir(destination.join("source"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["conflict", "file05.txt"]);
+    assert_eq!(
+        fs::read_to_string(destination.join("source/file05.txt")).unwrap(),
+        "old"
+    );
+    assert_eq!(
+        fs::read_to_string(destination.join("source/conflict/kept.txt")).unwrap(),
+        "old"
+    );
+    assert_eq!(fs::read_dir(&destination).unwrap().count(), 1);
+}
+
+#[test]
+fn test_unpacking_is_refused_without_free_space() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("huge.tar");
+    write_tarball(
+        &archive_path,
+        &[(EntryType::Regular, "small.txt", "", b"fits")],
+    );
+    // Then a header claiming far more contents than any disk holds, and none of them.
+    let mut tarball = fs::read(&archive_path).unwrap();
+    tarball.truncate(tarball.len() - 1024);
+    let mut header = Header::new_ustar();
+    header.set_path("huge.bin").unwrap();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(1 << 60);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tarball.extend_from_slice(header.as_bytes());
+    fs::write(&archive_path, tarball).unwrap();
+
+    let destination = dir.path().join("out");
+    let options = UnpackOptions {
+        check_space: true,
+        ..UnpackOptions::default()
+    };
+    let result = decompress::unpack_with_options(&archive_path, &destination, &options);
+    assert!(
+        matches!(result, Err(RazeError::InsufficientSpace(ref message)) if message.contains("out")),
+        "{result:?}"
+    );
+    // The archive is refused before its first entry is written.
+    assert!(!destination.join("small.txt").exists());
+    assert!(!destination.join("huge.bin").exists());
+}
+
+/// Tests that a directory swapped for a symbolic link while extracting
+/// cannot redirect entries outside the destination.
+#[cfg(target_os = "linux")]
+#[test]
+fn test_symlinks_swapped_in_during_extraction_are_not_followed() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("many.tar");
+    let names: Vec<_> = (0..50).map(|i| format!("d/{i}.txt")).collect();
+    let entries: Vec<TarEntry> = names
+        .iter()
+        .map(|name| (EntryType::Regular, name.as_str(), "", &b"data"[..]))
+        .collect();
+    write_tarball(&archive_path, &entries);
+    let outside = dir.path().join("outside");
+    fs::create_dir(&outside).unwrap();
+
+    for run in 0..500 {
+        let destination = dir.path().join(format!("out{run}"));
+        fs::create_dir_all(destination.join("d")).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let swapper = {
+            let (done, destination, outside) = (done.clone(), destination.clone(), outside.clone());
+            std::thread::spawn(move || {
+                let (real, swapped) = (destination.join("d.real"), destination.join("d"));
+                // Extraction recreates the directory while it is gone, so steps may fail.
+                while !done.load(Ordering::Relaxed) {
+                    let _ = fs::rename(&swapped, &real);
+                    let _ = std::os::unix::fs::symlink(&outside, &swapped);
+                    let _ = fs::remove_file(&swapped);
+                    let _ = fs::rename(&real, &swapped);
+                }
+            })
+        };
+        // Extraction may fail when it meets the link, but must never follow it.
+        let _ = unpack(&archive_path, &destination, ExtractPolicy::default());
+        done.store(true, Ordering::Relaxed);
+        swapper.join().unwrap();
+        assert!(
+            fs::read_dir(&outside).unwrap().next().is_none(),
+            "an entry escaped in run {run}"
+        );
+        fs::remove_dir_all(&destination).ok();
+    }
+}
+
+/// Tests that a symbolic link already in the destination, in place of an
+/// entry's parent directory, is refused rather than written through.
+#[cfg(unix)]
+#[test]
+fn test_symlinked_parent_directories_are_refused() {
+    let dir = tempdir().unwrap();
+    let outside = dir.path().join("outside");
+    fs::create_dir(&outside).unwrap();
+    let archive_path = dir.path().join("parent.tar");
+    write_tarball(
+        &archive_path,
+        &[(EntryType::Regular, "d/pwned.txt", "", b"pwned")],
+    );
+    let destination = dir.path().join("out");
+    fs::create_dir(&destination).unwrap();
+    std::os::unix::fs::symlink(&outside, destination.join("d")).unwrap();
+
+    let result = unpack(&archive_path, &destination, ExtractPolicy::default());
+    assert!(
+        matches!(result, Err(RazeError::SymlinkTraversal { ref path, ref link })
+            if path == "d/pwned.txt" && link == "d"),
+        "unexpected result: {:?}",
+        result
+    );
+    assert!(fs::read_dir(&outside).unwrap().next().is_none());
+}
+
+/// Tests that a symbolic link swapped in for a file while it is being
+/// replaced is reported as a traversal, and never followed.
+#[cfg(target_os = "linux")]
+#[test]
+fn test_symlinks_swapped_in_for_files_are_refused() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("files.tar");
+    let names: Vec<_> = (0..50).map(|i| format!("{i}.txt")).collect();
+    let entries: Vec<TarEntry> = names
+        .iter()
+        .map(|name| (EntryType::Regular, name.as_str(), "", &b"data"[..]))
+        .collect();
+    write_tarball(&archive_path, &entries);
+    let outside = dir.path().join("outside");
+    fs::create_dir(&outside).unwrap();
+
+    for run in 0..500 {
+        let destination = dir.path().join(format!("out{run}"));
+        fs::create_dir(&destination).unwrap();
+        for name in &names {
+            fs::write(destination.join(name), "old").unwrap();
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let swapper = {
+            let (done, destination, outside) = (done.clone(), destination.clone(), outside.clone());
+            let names = names.clone();
+            std::thread::spawn(move || {
+                // Links only appear where extraction has just removed the old file.
+                while !done.load(Ordering::Relaxed) {
+                    for name in &names {
+                        let _ =
+                            std::os::unix::fs::symlink(outside.join(name), destination.join(name));
+                    }
+                }
+            })
+        };
+        let result = unpack(&archive_path, &destination, ExtractPolicy::default());
+        done.store(true, Ordering::Relaxed);
+        swapper.join().unwrap();
+        match result {
+            Ok(()) => {},
+            Err(RazeError::SymlinkTraversal { path, link }) => assert_eq!(path, link),
+            Err(e) => panic!("unexpected error in run {run}: {e:?}"),
+        }
+        assert!(
+            fs::read_dir(&outside).unwrap().next().is_none(),
+            "an entry escaped in run {run}"
+        );
+        fs::remove_dir_all(&destination).ok();
+    }
+}
@@ -0,0 +1,114 @@
+//! # Integration Tests for Standard Tarball Formats
+//!
+//! This module verifies that Raze detects gzip, xz, bzip2, Zstandard and plain
+//! tar containers by their magic bytes, and that archives written with
+//! `--format` can be read back.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::decompress;
+use raze::core::format::ArchiveFormat;
+use std::fs;
+use tempfile::tempdir;
+
+/// Packs a small directory as `format`, checks the magic bytes of the result
+/// and extracts it again.
+fn roundtrip(format: ArchiveFormat, magic: &[u8]) {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("project");
+    fs::create_dir(&source).unwrap();
+    fs::write(source.join("notes.txt"), "Hello, interoperable Raze!").unwrap();
+    let archive_path = dir.path().join(format!("project.{}", format.extension()));
+
+    let options = PackOptions {
+        format,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&source, &archive_path, &options).unwrap();
+
+    let bytes = fs::read(&archive_path).unwrap();
+    assert_eq!(ArchiveFormat::detect(&bytes), Some(format));
+    assert!(bytes.starts_with(magic) || format == ArchiveFormat::Tar);
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&archive_path, unpack_dir.path(), None).unwrap();
+    let content = fs::read_to_string(unpack_dir.path().join("project/notes.txt")).unwrap();
+    assert_eq!(content, "Hello, interoperable Raze!");
+}
+
+#[test]
+fn test_plain_tar_roundtrip() {
+    roundtrip(ArchiveFormat::Tar, b"");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_tar_gz_roundtrip() {
+    roundtrip(ArchiveFormat::TarGz, &[0x1F, 0x8B]);
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn test_tar_xz_roundtrip() {
+    roundtrip(ArchiveFormat::TarXz, b"\xFD7zXZ\x00");
+}
+
+/// Writes a one-file tarball the way other tools do, without going through Raze.
+#[cfg(any(feature = "gzip", feature = "bzip2"))]
+fn write_foreign_tarball<W: std::io::Write>(writer: W) -> W {
+    let mut builder = tar::Builder::new(writer);
+    let data = b"Sent by a partner.";
+    let mut header = tar::Header::new_ustar();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "partner.txt", &data[..])
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+#[cfg(any(feature = "gzip", feature = "bzip2"))]
+fn assert_extracts_partner_file(archive_path: &std::path::Path) {
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(archive_path, unpack_dir.path(), None).unwrap();
+    let content = fs::read_to_string(unpack_dir.path().join("partner.txt")).unwrap();
+    assert_eq!(content, "Sent by a partner.");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_unpack_foreign_tar_gz() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("partner.tar.gz");
+    let file = fs::File::create(&archive_path).unwrap();
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    write_foreign_tarball(encoder).finish().unwrap();
+
+    assert_extracts_partner_file(&archive_path);
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn test_unpack_foreign_tar_bz2() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("partner.tar.bz2");
+    let file = fs::File::create(&archive_path).unwrap();
+    let encoder = bzip2::write::BzEncoder::new(file, bzip2::Compression::default());
+    write_foreign_tarball(encoder).finish().unwrap();
+
+    assert_extracts_partner_file(&archive_path);
+}
+
+#[test]
+fn test_unrecognized_format_is_reported() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("not-an-archive.rz");
+    fs::write(&archive_path, "just some text").unwrap();
+
+    let result = decompress::unpack(&archive_path, dir.path().join("out"), None);
+    assert!(matches!(
+        result,
+        Err(raze::utils::errors::RazeError::DecompressionError(ref msg))
+            if msg.contains("Unrecognized archive format")
+    ));
+}
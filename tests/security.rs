@@ -0,0 +1,846 @@
+//! # Integration Tests for Streaming Encryption
+//!
+//! This module verifies that `EncryptWriter` and `DecryptReader` encrypt and
+//! decrypt a stream in a single pass across chunk boundaries, and that wrong
+//! passwords, tampered or reordered chunks, truncated streams and tampered
+//! padding are rejected. It also covers how secrets are held in memory, and
+//! that archives encrypted by Raze 1.1.0 can still be read.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::decompress::{self, UnpackOptions};
+use raze::core::recovery;
+use raze::core::rekey::{self, NewKey};
+use raze::utils::errors::RazeError;
+use raze::utils::secret::{self, Secret};
+use raze::utils::security::{
+    self, Cipher, Credentials, DecryptReader, EncryptWriter, Identity, KdfParams, KdfProfile,
+    KeyFile, Padding, Recipient, SlotKind,
+};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use tempfile::tempdir;
+
+/// Length of the `RZCR` header of a password-protected AES-256-GCM stream: magic,
+/// version, cipher id, base nonce, slot area length, the slot area holding the slot
+/// count, one password slot and room for four more, and the header MAC.
+const HEADER_LEN: usize = 4 + 1 + 1 + 12 + 4 + (2 + 78 + 4 * 78) + 32;
+
+/// Offset of the KDF memory cost of the first slot in an AES-256-GCM stream.
+const KDF_OFFSET: usize = 4 + 1 + 1 + 12 + 4 + 2 + 1 + 1;
+
+/// Cheap key derivation parameters that keep the tests fast.
+const FAST_KDF: KdfParams = KdfParams {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// Produces `len` bytes of varied, easily recognizable plaintext.
+fn plaintext(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Encrypts `data` with `password` using AES-256-GCM, writing it in small, uneven pieces.
+fn encrypt(data: &[u8], password: &str) -> Vec<u8> {
+    encrypt_with(data, password, Cipher::Aes256Gcm)
+}
+
+/// Encrypts `data` with `password` and `cipher`, writing it in small, uneven pieces.
+fn encrypt_with(data: &[u8], password: &str, cipher: Cipher) -> Vec<u8> {
+    let mut encryptor = EncryptWriter::new(Vec::new(), password, FAST_KDF, cipher).unwrap();
+    for piece in data.chunks(1000) {
+        encryptor.write_all(piece).unwrap();
+    }
+    encryptor.finish().unwrap()
+}
+
+/// Returns the offsets at which each encrypted chunk begins, followed by the
+/// total length of the stream.
+fn chunk_boundaries(ciphertext: &[u8]) -> Vec<usize> {
+    let mut boundaries = vec![HEADER_LEN];
+    let mut offset = HEADER_LEN;
+    while offset < ciphertext.len() {
+        let len = u32::from_le_bytes(ciphertext[offset..offset + 4].try_into().unwrap());
+        offset += 4 + len as usize;
+        boundaries.push(offset);
+    }
+    boundaries
+}
+
+#[test]
+fn test_streaming_roundtrip_across_chunks() {
+    // Three full 64KB chunks and a partial one.
+    let data = plaintext(3 * 64 * 1024 + 123);
+    let ciphertext = encrypt(&data, "password123");
+    assert!(ciphertext.starts_with(b"RZCR"));
+    assert!(!ciphertext
+        .windows(64)
+        .any(|window| window == &data[1000..1064]));
+
+    let mut decryptor = DecryptReader::new(ciphertext.as_slice(), "password123").unwrap();
+    let mut decrypted = Vec::new();
+    let mut buffer = [0u8; 777];
+    loop {
+        let n = decryptor.read(&mut buffer).unwrap();
+        if n == 0 {
+            break;
+        }
+        decrypted.extend_from_slice(&buffer[..n]);
+    }
+    assert_eq!(decrypted, data);
+}
+
+#[test]
+fn test_wrong_password_and_tampering_are_rejected() {
+    let data = plaintext(2 * 64 * 1024);
+    let mut ciphertext = encrypt(&data, "password123");
+
+    let result = DecryptReader::new(ciphertext.as_slice(), "wrongpassword");
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+
+    // Flip a bit in the second chunk; the first chunk still authenticates.
+    let last = ciphertext.len() - 1;
+    ciphertext[last] ^= 0x01;
+    let result = security::decrypt_stream(ciphertext.as_slice(), Vec::new(), "password123");
+    assert!(
+        matches!(result, Err(RazeError::CryptoError(ref msg)) if msg.contains("Authentication failed")),
+        "unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_truncation_at_every_chunk_boundary() {
+    let data = plaintext(2 * 64 * 1024 + 500);
+    let ciphertext = encrypt(&data, "password123");
+    let boundaries = chunk_boundaries(&ciphertext);
+    assert_eq!(boundaries.len(), 4);
+    assert_eq!(*boundaries.last().unwrap(), ciphertext.len());
+
+    for &cut in &boundaries[..boundaries.len() - 1] {
+        let result = security::decrypt_stream(&ciphertext[..cut], Vec::new(), "password123");
+        assert!(
+            matches!(result, Err(RazeError::TruncationError(_))),
+            "cut at {} bytes: unexpected result: {:?}",
+            cut,
+            result
+        );
+    }
+}
+
+/// Encrypts `data` with `password`, padding the stream as described by `padding`.
+fn encrypt_padded(data: &[u8], password: &str, padding: Padding) -> Vec<u8> {
+    let mut encryptor = EncryptWriter::new(Vec::new(), password, FAST_KDF, Cipher::Aes256Gcm)
+        .unwrap()
+        .padded(padding);
+    encryptor.write_all(data).unwrap();
+    encryptor.finish().unwrap()
+}
+
+#[test]
+fn test_padding_schemes() {
+    assert_eq!(Padding::None.padded_len(1000), 1000);
+    assert_eq!(Padding::Padme.padded_len(1000), 1024);
+    assert_eq!(Padding::Padme.padded_len(1025), 1088);
+    assert_eq!(Padding::Padme.padded_len(1), 1);
+    assert_eq!(Padding::PowerOfTwo.padded_len(1025), 2048);
+    assert_eq!(Padding::PowerOfTwo.padded_len(4096), 4096);
+    for len in [100, 5000, 123_456, 98_765_432, 1 << 40] {
+        let padded = Padding::Padme.padded_len(len);
+        assert!(padded >= len && padded as f64 <= len as f64 * 1.12);
+    }
+    assert_eq!("padme".parse::<Padding>().unwrap(), Padding::Padme);
+    assert_eq!(
+        "power-of-two".parse::<Padding>().unwrap(),
+        Padding::PowerOfTwo
+    );
+    assert!("random".parse::<Padding>().is_err());
+}
+
+#[test]
+fn test_padded_streams_hide_their_length() {
+    let small = plaintext(100_000);
+    let large = plaintext(100_500);
+    let unpadded = encrypt(&small, "password123");
+    let small_ciphertext = encrypt_padded(&small, "password123", Padding::Padme);
+    let large_ciphertext = encrypt_padded(&large, "password123", Padding::Padme);
+    assert_eq!(small_ciphertext.len(), large_ciphertext.len());
+    assert!(small_ciphertext.len() > unpadded.len());
+
+    for (ciphertext, data) in [(&small_ciphertext, &small), (&large_ciphertext, &large)] {
+        let mut decrypted = Vec::new();
+        security::decrypt_stream(ciphertext.as_slice(), &mut decrypted, "password123").unwrap();
+        assert_eq!(&decrypted, data);
+    }
+
+    let ciphertext = encrypt_padded(&small, "password123", Padding::PowerOfTwo);
+    assert_eq!(ciphertext.len(), 128 * 1024);
+    let mut decrypted = Vec::new();
+    security::decrypt_stream(ciphertext.as_slice(), &mut decrypted, "password123").unwrap();
+    assert_eq!(decrypted, small);
+}
+
+#[test]
+fn test_padding_tampering_is_detected() {
+    let data = plaintext(70_000);
+    let ciphertext = encrypt_padded(&data, "password123", Padding::PowerOfTwo);
+    let decrypt = |stream: &[u8]| security::decrypt_stream(stream, Vec::new(), "password123");
+
+    // Modified padding bytes.
+    let mut modified = ciphertext.clone();
+    modified[ciphertext.len() - 100] ^= 0x01;
+    assert!(matches!(decrypt(&modified), Err(RazeError::CryptoError(_))));
+
+    // Stripped padding, which the final chunk is bound to.
+    let b = chunk_boundaries(&ciphertext);
+    let padding_start = b[2];
+    assert_eq!(
+        &ciphertext[padding_start..padding_start + 4],
+        &u32::MAX.to_le_bytes()
+    );
+    assert!(matches!(
+        decrypt(&ciphertext[..padding_start]),
+        Err(RazeError::CryptoError(_))
+    ));
+
+    // Truncated padding and data appended after it.
+    assert!(matches!(
+        decrypt(&ciphertext[..ciphertext.len() - 10]),
+        Err(RazeError::TruncationError(_))
+    ));
+    let mut extended = ciphertext.clone();
+    extended.push(0);
+    assert!(matches!(decrypt(&extended), Err(RazeError::CryptoError(_))));
+}
+
+#[test]
+fn test_padding_requires_encryption() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("report.txt");
+    fs::write(&file_path, "quarterly numbers ".repeat(50)).unwrap();
+    let archive_path = dir.path().join("report.rz");
+
+    let options = PackOptions {
+        padding: Padding::Padme,
+        ..PackOptions::default()
+    };
+    assert!(compress::pack_with_options(&file_path, &archive_path, &options).is_err());
+
+    let options = PackOptions {
+        password: Some("password123".into()),
+        kdf: FAST_KDF,
+        padding: Padding::PowerOfTwo,
+        recovery_percent: Some(10),
+        ..options
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    decompress::test(&archive_path, Some("password123")).unwrap();
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&archive_path, unpack_dir.path(), Some("password123")).unwrap();
+    assert_eq!(
+        fs::read(unpack_dir.path().join("report.txt")).unwrap(),
+        fs::read(&file_path).unwrap()
+    );
+}
+
+#[test]
+fn test_reordered_chunks_are_rejected() {
+    let data = plaintext(3 * 64 * 1024);
+    let ciphertext = encrypt(&data, "password123");
+    let b = chunk_boundaries(&ciphertext);
+
+    // Swap the second and third chunks, which have the same length.
+    let mut reordered = ciphertext[..b[1]].to_vec();
+    reordered.extend_from_slice(&ciphertext[b[2]..b[3]]);
+    reordered.extend_from_slice(&ciphertext[b[1]..b[2]]);
+    let result = security::decrypt_stream(reordered.as_slice(), Vec::new(), "password123");
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+}
+
+#[test]
+fn test_truncated_archive_fails_verification() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("backup.bin");
+    // Incompressible data keeps the archive several chunks long.
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let data: Vec<u8> = (0..300 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(&file_path, &data).unwrap();
+    let archive_path = dir.path().join("backup.rz");
+    let options = PackOptions {
+        password: Some("password123".into()),
+        kdf: FAST_KDF,
+        cipher: Cipher::Aes256Gcm,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+
+    // Drop the final chunk, as an interrupted upload would.
+    let ciphertext = fs::read(&archive_path).unwrap();
+    let boundaries = chunk_boundaries(&ciphertext);
+    assert!(boundaries.len() > 4);
+    fs::write(
+        &archive_path,
+        &ciphertext[..boundaries[boundaries.len() - 2]],
+    )
+    .unwrap();
+
+    let result = decompress::test(&archive_path, Some("password123"));
+    assert!(
+        matches!(result, Err(RazeError::TruncationError(_))),
+        "unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_header_tampering_is_detected() {
+    let data = plaintext(1000);
+    let ciphertext = encrypt(&data, "password123");
+
+    // Any other version byte marks a stream written by Raze 1.1.0, which the
+    // rest of the header does not parse as.
+    let mut tampered = ciphertext.clone();
+    tampered[4] ^= 0x01;
+    assert!(DecryptReader::new(tampered.as_slice(), "password123").is_err());
+
+    // The cipher id, the base nonce, the slot area length, the factors, the KDF parameters,
+    // the wrapped key, the spare room, the header MAC and the first chunk's length prefix
+    // (shortening the chunk) are all authenticated.
+    for offset in [
+        5,
+        7,
+        18,
+        KDF_OFFSET - 1,
+        KDF_OFFSET,
+        KDF_OFFSET + 40,
+        200,
+        HEADER_LEN - 1,
+        HEADER_LEN + 1,
+    ] {
+        let mut tampered = ciphertext.clone();
+        tampered[offset] ^= 0x01;
+        let result = DecryptReader::new(tampered.as_slice(), "password123");
+        assert!(
+            matches!(result, Err(RazeError::CryptoError(_))),
+            "tampering at offset {} was not detected",
+            offset
+        );
+    }
+}
+
+#[test]
+fn test_kdf_parameters_are_recorded_and_capped() {
+    let data = plaintext(1000);
+    let ciphertext = encrypt(&data, "password123");
+
+    // The parameters are recorded in the password slot.
+    assert_eq!(
+        &ciphertext[KDF_OFFSET..KDF_OFFSET + 4],
+        &64u32.to_le_bytes()
+    );
+    assert_eq!(
+        &ciphertext[KDF_OFFSET + 4..KDF_OFFSET + 8],
+        &1u32.to_le_bytes()
+    );
+
+    // Absurd parameters are refused before any key derivation is attempted.
+    let mut hostile = ciphertext.clone();
+    hostile[KDF_OFFSET..KDF_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let result = DecryptReader::new(hostile.as_slice(), "password123");
+    assert!(
+        matches!(result, Err(RazeError::CryptoError(ref msg)) if msg.contains("safety limit")),
+        "unexpected result: {:?}",
+        result.err()
+    );
+
+    // The same limits apply when encrypting.
+    let excessive = KdfParams {
+        iterations: 1000,
+        ..FAST_KDF
+    };
+    assert!(EncryptWriter::new(Vec::new(), "password123", excessive, Cipher::Aes256Gcm).is_err());
+
+    assert_eq!("paranoid".parse::<KdfProfile>(), Ok(KdfProfile::Paranoid));
+    assert_eq!(KdfParams::default(), KdfProfile::Moderate.params());
+}
+
+#[test]
+fn test_xchacha20poly1305_roundtrip() {
+    let data = plaintext(2 * 64 * 1024 + 77);
+    let ciphertext = encrypt_with(&data, "password123", Cipher::XChaCha20Poly1305);
+    // The cipher id follows the magic and the version; the base nonce is 24 bytes long.
+    assert_eq!(ciphertext[5], 2);
+    // Three chunks, each with a length prefix and a tag.
+    assert_eq!(
+        ciphertext.len(),
+        HEADER_LEN + 12 + 3 * (4 + 16) + data.len()
+    );
+
+    let mut decrypted = Vec::new();
+    security::decrypt_stream(ciphertext.as_slice(), &mut decrypted, "password123").unwrap();
+    assert_eq!(decrypted, data);
+
+    // An unknown cipher id is rejected.
+    let mut unknown = ciphertext.clone();
+    unknown[5] = 0xFF;
+    let result = DecryptReader::new(unknown.as_slice(), "password123");
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+
+    assert_eq!(
+        "xchacha20poly1305".parse::<Cipher>(),
+        Ok(Cipher::XChaCha20Poly1305)
+    );
+    assert_eq!("AES256GCM".parse::<Cipher>(), Ok(Cipher::Aes256Gcm));
+    assert!("rot13".parse::<Cipher>().is_err());
+}
+
+#[test]
+fn test_recipients_can_decrypt_but_not_the_writer() {
+    let alice = Identity::generate().unwrap();
+    let bob = Identity::generate().unwrap();
+    let eve = Identity::generate().unwrap();
+    let data = plaintext(64 * 1024 + 10);
+
+    let mut encryptor = EncryptWriter::for_recipients(
+        Vec::new(),
+        &[alice.recipient(), bob.recipient()],
+        Cipher::Aes256Gcm,
+    )
+    .unwrap();
+    encryptor.write_all(&data).unwrap();
+    let ciphertext = encryptor.finish().unwrap();
+
+    // Each recipient can decrypt on its own.
+    for identity in [&alice, &bob] {
+        let credentials = Credentials {
+            identities: vec![eve.clone(), identity.clone()],
+            ..Credentials::default()
+        };
+        let mut decryptor =
+            DecryptReader::with_credentials(ciphertext.as_slice(), &credentials).unwrap();
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, data);
+    }
+
+    // Other identities and passwords cannot.
+    let credentials = Credentials {
+        identities: vec![eve],
+        ..Credentials::default()
+    };
+    let result = DecryptReader::with_credentials(ciphertext.as_slice(), &credentials);
+    assert!(
+        matches!(result, Err(RazeError::CryptoError(ref msg)) if msg.contains("recipient")),
+        "unexpected result: {:?}",
+        result.err()
+    );
+    let result = DecryptReader::new(ciphertext.as_slice(), "password123");
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+
+    // Swapping the slots of the two recipients is caught by the header MAC.
+    // The slots follow the magic, version, cipher id, base nonce, slot area length and count.
+    let first = 4 + 1 + 1 + 12 + 4 + 2;
+    let (second, end) = (first + 81, first + 162);
+    let mut swapped = ciphertext[..first].to_vec();
+    swapped.extend_from_slice(&ciphertext[second..end]);
+    swapped.extend_from_slice(&ciphertext[first..second]);
+    swapped.extend_from_slice(&ciphertext[end..]);
+    let credentials = Credentials {
+        identities: vec![alice],
+        ..Credentials::default()
+    };
+    let result = DecryptReader::with_credentials(swapped.as_slice(), &credentials);
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+}
+
+#[test]
+fn test_identity_files_and_recipient_archives() {
+    let dir = tempdir().unwrap();
+    let identity = Identity::generate().unwrap();
+    let identity_path = dir.path().join("backup.key");
+    security::write_identity_file(&identity_path, &identity).unwrap();
+    // An existing identity file is never overwritten.
+    assert!(security::write_identity_file(&identity_path, &identity).is_err());
+
+    let identities = security::read_identity_file(&identity_path).unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].recipient(), identity.recipient());
+    let recipient: Recipient = identity.recipient().to_string().parse().unwrap();
+    assert_eq!(recipient, identity.recipient());
+    assert!("raze-pub-1234".parse::<Recipient>().is_err());
+    assert!(!format!("{:?}", identity).contains(&identity.to_secret_string()[12..]));
+
+    let file_path = dir.path().join("backup.txt");
+    fs::write(&file_path, "Nightly backup").unwrap();
+    let archive_path = dir.path().join("backup.rz");
+    let options = PackOptions {
+        recipients: vec![recipient],
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    assert!(decompress::is_encrypted(&archive_path).unwrap());
+    assert!(decompress::test(&archive_path, Some("password123")).is_err());
+
+    let options = UnpackOptions {
+        credentials: Credentials {
+            identities,
+            ..Credentials::default()
+        },
+        ..UnpackOptions::default()
+    };
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack_with_options(&archive_path, unpack_dir.path(), &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(unpack_dir.path().join("backup.txt")).unwrap(),
+        "Nightly backup"
+    );
+
+    // With a password as well, either of them opens the archive.
+    let options = PackOptions {
+        password: Some("password123".into()),
+        recipients: vec![identity.recipient()],
+        kdf: FAST_KDF,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    decompress::test(&archive_path, Some("password123")).unwrap();
+    let options = UnpackOptions {
+        credentials: Credentials {
+            identities: vec![identity],
+            ..Credentials::default()
+        },
+        ..UnpackOptions::default()
+    };
+    decompress::test_with_options(&archive_path, &options).unwrap();
+}
+
+#[test]
+fn test_rekeying_rewrites_only_the_header() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("large.bin");
+    fs::write(&file_path, plaintext(300 * 1024)).unwrap();
+    let archive_path = dir.path().join("large.rz");
+    let options = PackOptions {
+        password: Some("old password".into()),
+        kdf: FAST_KDF,
+        cipher: Cipher::Aes256Gcm,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    let before = fs::read(&archive_path).unwrap();
+
+    let new_key = |password: &str| NewKey {
+        password: Some(password.into()),
+        kdf: FAST_KDF,
+        ..NewKey::default()
+    };
+    let old = Credentials::from_password("old password");
+    rekey::rekey(&archive_path, &old, &new_key("new password"), false).unwrap();
+    let after = fs::read(&archive_path).unwrap();
+    assert_eq!(after.len(), before.len());
+    assert_ne!(after[..HEADER_LEN], before[..HEADER_LEN]);
+    assert_eq!(after[HEADER_LEN..], before[HEADER_LEN..]);
+    assert!(decompress::test(&archive_path, Some("old password")).is_err());
+    decompress::test(&archive_path, Some("new password")).unwrap();
+
+    // A second password opens the archive alongside the first, until it is removed.
+    let new = Credentials::from_password("new password");
+    rekey::add_password(&archive_path, &new, &new_key("second password"), false).unwrap();
+    decompress::test(&archive_path, Some("new password")).unwrap();
+    decompress::test(&archive_path, Some("second password")).unwrap();
+    rekey::remove_password(&archive_path, &new, false).unwrap();
+    assert!(decompress::test(&archive_path, Some("new password")).is_err());
+    decompress::test(&archive_path, Some("second password")).unwrap();
+    assert_eq!(
+        fs::read(&archive_path).unwrap()[HEADER_LEN..],
+        before[HEADER_LEN..]
+    );
+
+    // The last slot stays, and the header only has room for so many slots.
+    let second = Credentials::from_password("second password");
+    assert!(rekey::remove_password(&archive_path, &second, false).is_err());
+    let result = (0..10).try_for_each(|i| {
+        rekey::add_password(
+            &archive_path,
+            &second,
+            &new_key(&format!("spare {}", i)),
+            false,
+        )
+    });
+    assert!(
+        matches!(result, Err(RazeError::CryptoError(ref msg)) if msg.contains("No room")),
+        "unexpected result: {:?}",
+        result
+    );
+    decompress::test(&archive_path, Some("spare 3")).unwrap();
+}
+
+/// Re-keying replaces the archive with a complete copy under a temporary
+/// name, keeping the archive's permissions and leaving nothing behind.
+#[cfg(unix)]
+#[test]
+fn test_rekeying_replaces_the_archive_atomically() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("notes.txt");
+    fs::write(&file_path, "Private notes.").unwrap();
+    let archive_path = dir.path().join("notes.rz");
+    let options = PackOptions {
+        password: Some("old password".into()),
+        kdf: FAST_KDF,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    fs::set_permissions(&archive_path, fs::Permissions::from_mode(0o600)).unwrap();
+    let before = fs::metadata(&archive_path).unwrap().ino();
+
+    let new_key = NewKey {
+        password: Some("new password".into()),
+        kdf: FAST_KDF,
+        ..NewKey::default()
+    };
+    let old = Credentials::from_password("old password");
+    rekey::rekey(&archive_path, &old, &new_key, false).unwrap();
+
+    assert_ne!(fs::metadata(&archive_path).unwrap().ino(), before);
+    let mode = fs::metadata(&archive_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let mut names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["notes.rz", "notes.txt"]);
+    decompress::test(&archive_path, Some("new password")).unwrap();
+
+    // Wrong credentials leave the archive as it was.
+    let after = fs::read(&archive_path).unwrap();
+    assert!(rekey::rekey(&archive_path, &old, &new_key, false).is_err());
+    assert_eq!(fs::read(&archive_path).unwrap(), after);
+}
+
+#[test]
+fn test_rekeying_keeps_the_recovery_record_in_step() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("data.bin");
+    fs::write(&file_path, plaintext(100 * 1024)).unwrap();
+    let archive_path = dir.path().join("data.rz");
+    let options = PackOptions {
+        password: Some("old password".into()),
+        recovery_percent: Some(10),
+        kdf: FAST_KDF,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+
+    let new_key = NewKey {
+        password: Some("new password".into()),
+        kdf: FAST_KDF,
+        ..NewKey::default()
+    };
+    rekey::rekey(
+        &archive_path,
+        &Credentials::from_password("old password"),
+        &new_key,
+        false,
+    )
+    .unwrap();
+
+    // Repair sees no damage, so it does not restore the old header.
+    let report = recovery::repair(&archive_path).unwrap();
+    assert_eq!(report.damaged_blocks, 0);
+    decompress::test(&archive_path, Some("new password")).unwrap();
+}
+
+#[test]
+fn test_key_file_factors() {
+    let data = plaintext(1000);
+    let key_file = KeyFile::from_bytes(b"contents of a key file on a separate stick");
+    let open = |ciphertext: &[u8], password: Option<&str>, key_file: Option<&KeyFile>| {
+        let credentials = Credentials {
+            password: password.map(Into::into),
+            key_file: key_file.cloned(),
+            ..Credentials::default()
+        };
+        let mut decrypted = Vec::new();
+        DecryptReader::with_credentials(ciphertext, &credentials)?
+            .read_to_end(&mut decrypted)
+            .map_err(RazeError::Io)?;
+        Ok::<_, RazeError>(decrypted)
+    };
+
+    // Both factors are needed for a two-factor archive, and the error names the missing one.
+    let mut encryptor = EncryptWriter::with_key_file(
+        Vec::new(),
+        Some("password123"),
+        &key_file,
+        FAST_KDF,
+        Cipher::Aes256Gcm,
+    )
+    .unwrap();
+    encryptor.write_all(&data).unwrap();
+    let two_factor = encryptor.finish().unwrap();
+    assert_eq!(
+        open(&two_factor, Some("password123"), Some(&key_file)).unwrap(),
+        data
+    );
+    let result = open(&two_factor, Some("password123"), None);
+    assert!(
+        matches!(result, Err(RazeError::CryptoError(ref msg)) if msg.contains("key file was not provided")),
+        "unexpected result: {:?}",
+        result
+    );
+    let other_key_file = KeyFile::from_bytes(b"some other file");
+    let result = open(&two_factor, Some("password123"), Some(&other_key_file));
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+
+    // A key file can also replace the password entirely.
+    let mut encryptor =
+        EncryptWriter::with_key_file(Vec::new(), None, &key_file, FAST_KDF, Cipher::Aes256Gcm)
+            .unwrap();
+    encryptor.write_all(&data).unwrap();
+    let key_file_only = encryptor.finish().unwrap();
+    assert_eq!(open(&key_file_only, None, Some(&key_file)).unwrap(), data);
+    assert_eq!(
+        open(&key_file_only, Some("ignored"), Some(&key_file)).unwrap(),
+        data
+    );
+    assert!(open(&key_file_only, Some("password123"), None).is_err());
+
+    // Reading a key file from disk digests its contents; empty files are refused.
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("usb.key");
+    fs::write(&path, b"contents of a key file on a separate stick").unwrap();
+    let from_disk = KeyFile::read(&path).unwrap();
+    assert_eq!(open(&key_file_only, None, Some(&from_disk)).unwrap(), data);
+    fs::write(&path, b"").unwrap();
+    assert!(KeyFile::read(&path).is_err());
+}
+
+#[test]
+fn test_passwords_are_held_as_secrets() {
+    let password = Secret::from("correct horse");
+    assert_eq!(password.expose(), "correct horse");
+    assert_eq!(format!("{:?}", password), "Secret(..)");
+    let options = PackOptions {
+        password: Some(password.clone()),
+        kdf: FAST_KDF,
+        ..PackOptions::default()
+    };
+    assert!(!format!("{:?}", options.password).contains("horse"));
+
+    // Secrets stand in for plain passwords throughout.
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("notes.txt");
+    fs::write(&source, "secret notes").unwrap();
+    let archive_path = dir.path().join("notes.rz");
+    compress::pack_with_options(&source, &archive_path, &options).unwrap();
+    drop(options);
+    let credentials = Credentials {
+        password: Some(password),
+        ..Credentials::default()
+    };
+    assert!(!format!("{:?}", credentials.password).contains("horse"));
+    let unpack_options = UnpackOptions {
+        credentials,
+        ..UnpackOptions::default()
+    };
+    decompress::test_with_options(&archive_path, &unpack_options).unwrap();
+}
+
+#[test]
+fn test_secrets_wipe_the_value_they_take() {
+    let mut key = [7u8; 32];
+    let secret = Secret::new(&mut key);
+    assert_eq!(key, [0u8; 32]);
+    assert_eq!(secret.expose(), &[7u8; 32]);
+
+    let mut password = String::from("correct horse");
+    let address = password.as_ptr();
+    let secret = Secret::new(&mut password);
+    assert!(password.is_empty());
+    // The string's buffer is moved rather than copied.
+    assert_eq!(secret.expose().as_ptr(), address);
+
+    // Dropping a secret keeps others on the same page usable.
+    let secrets: Vec<_> = (0..16).map(|_| Secret::new(&mut vec![1u8; 8])).collect();
+    drop(secret);
+    for secret in secrets {
+        assert_eq!(secret.expose(), &[1u8; 8]);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_core_dumps_can_be_disabled() {
+    secret::disable_core_dumps().unwrap();
+    let limits = fs::read_to_string("/proc/self/limits").unwrap();
+    let core = limits
+        .lines()
+        .find(|line| line.starts_with("Max core file size"))
+        .unwrap();
+    let values: Vec<_> = core.split_whitespace().skip(4).take(2).collect();
+    assert_eq!(values, ["0", "0"]);
+}
+
+/// Unpacks an archive encrypted by Raze 1.1.0, whose header has no format
+/// version or key slots and whose chunks carry no final-chunk flag.
+#[test]
+fn test_archive_from_raze_1_1_0_can_be_read() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/raze-1.1.0.rz");
+    assert!(decompress::is_encrypted(&fixture).unwrap());
+    assert_eq!(
+        decompress::slot_kinds(&fixture).unwrap(),
+        Some(vec![SlotKind::Secret {
+            password: true,
+            key_file: false,
+        }])
+    );
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&fixture, unpack_dir.path(), Some("password123")).unwrap();
+    assert_eq!(
+        fs::read_to_string(unpack_dir.path().join("legacy/notes.txt")).unwrap(),
+        "Written by Raze 1.1.0.\n"
+    );
+
+    let result = decompress::test(&fixture, Some("wrong-password"));
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+    let result = decompress::test(&fixture, None);
+    assert!(result.is_err());
+}
+
+/// Unpacks an archive encrypted by Raze 1.1.0 whose salt happens to begin
+/// with the current format version, so that it looks like a current archive.
+#[test]
+fn test_archive_from_raze_1_1_0_with_version_byte_in_salt_can_be_read() {
+    let fixture =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/raze-1.1.0-salt-02.rz");
+    assert_eq!(fs::read(&fixture).unwrap()[4], 2);
+    assert_eq!(
+        decompress::slot_kinds(&fixture).unwrap(),
+        Some(vec![SlotKind::Secret {
+            password: true,
+            key_file: false,
+        }])
+    );
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&fixture, unpack_dir.path(), Some("password123")).unwrap();
+    assert_eq!(
+        fs::read_to_string(unpack_dir.path().join("legacy/notes.txt")).unwrap(),
+        "Written by Raze 1.1.0.\n"
+    );
+
+    let result = decompress::test(&fixture, Some("wrong-password"));
+    assert!(matches!(result, Err(RazeError::CryptoError(_))));
+}
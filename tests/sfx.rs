@@ -0,0 +1,179 @@
+//! # Integration Tests for Self-Extracting Archives
+//!
+//! This module verifies that self-extracting executables produced by `pack`
+//! extract themselves when run, and that the regular `unpack` path can still
+//! read the archive payload embedded in them.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::{decompress, recovery};
+use raze::utils::security::{self, Identity, KeyFile};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+fn sfx_options() -> PackOptions {
+    PackOptions {
+        sfx_stub: Some(PathBuf::from(env!("CARGO_BIN_EXE_raze-sfx"))),
+        ..PackOptions::default()
+    }
+}
+
+#[test]
+fn test_sfx_extracts_itself() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("payload");
+    fs::create_dir(&source).unwrap();
+    fs::write(
+        source.join("readme.txt"),
+        "Hello from a self-extracting archive!",
+    )
+    .unwrap();
+    let sfx_path = dir.path().join("installer");
+
+    compress::pack_with_options(&source, &sfx_path, &sfx_options()).unwrap();
+
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(unpack_dir.path())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "SFX extraction failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("payload/readme.txt")).unwrap();
+    assert_eq!(content, "Hello from a self-extracting archive!");
+
+    // Running without --dest extracts into the current directory.
+    let cwd = tempdir().unwrap();
+    let output = Command::new(&sfx_path)
+        .current_dir(cwd.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(cwd.path().join("payload/readme.txt").exists());
+}
+
+#[test]
+fn test_unpack_reads_encrypted_sfx_payload() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    fs::write(&file_path, "This is a secret message.").unwrap();
+    let sfx_path = dir.path().join("secret.run");
+
+    let options = PackOptions {
+        password: Some("password123".into()),
+        recovery_percent: Some(5),
+        ..sfx_options()
+    };
+    compress::pack_with_options(&file_path, &sfx_path, &options).unwrap();
+
+    assert!(decompress::is_encrypted(&sfx_path).unwrap());
+    assert_eq!(recovery::repair(&sfx_path).unwrap().damaged_blocks, 0);
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&sfx_path, unpack_dir.path(), Some("password123")).unwrap();
+    let content = fs::read_to_string(unpack_dir.path().join("secret.txt")).unwrap();
+    assert_eq!(content, "This is a secret message.");
+}
+
+/// Packs a single file into a self-extracting archive with `options`.
+fn pack_sfx(dir: &Path, options: PackOptions) -> PathBuf {
+    let file_path = dir.join("secret.txt");
+    fs::write(&file_path, "This is a secret message.").unwrap();
+    let sfx_path = dir.join("secret.run");
+    compress::pack_with_options(&file_path, &sfx_path, &options).unwrap();
+    sfx_path
+}
+
+#[test]
+fn test_sfx_opens_key_file_archive() {
+    let dir = tempdir().unwrap();
+    let key_path = dir.path().join("stick.key");
+    fs::write(&key_path, "contents of a key file on a separate stick").unwrap();
+    let options = PackOptions {
+        key_file: Some(KeyFile::read(&key_path).unwrap()),
+        ..sfx_options()
+    };
+    let sfx_path = pack_sfx(dir.path(), options);
+
+    // Without the key file, the stub names what it needs instead of asking
+    // for a password that no slot takes.
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(unpack_dir.path())
+        .env_remove("RAZE_KEYFILE")
+        .env_remove("RAZE_IDENTITY")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("key file"));
+
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(unpack_dir.path())
+        .arg("--keyfile")
+        .arg(&key_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "SFX extraction failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("secret.txt")).unwrap();
+    assert_eq!(content, "This is a secret message.");
+
+    // The key file can also be given through the environment.
+    let env_dir = tempdir().unwrap();
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(env_dir.path())
+        .env("RAZE_KEYFILE", &key_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(env_dir.path().join("secret.txt").exists());
+}
+
+#[test]
+fn test_sfx_opens_recipient_archive() {
+    let dir = tempdir().unwrap();
+    let identity = Identity::generate().unwrap();
+    let identity_path = dir.path().join("alice.key");
+    security::write_identity_file(&identity_path, &identity).unwrap();
+    let options = PackOptions {
+        recipients: vec![identity.recipient()],
+        ..sfx_options()
+    };
+    let sfx_path = pack_sfx(dir.path(), options);
+
+    let unpack_dir = tempdir().unwrap();
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(unpack_dir.path())
+        .env_remove("RAZE_KEYFILE")
+        .env_remove("RAZE_IDENTITY")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("identity"));
+
+    let output = Command::new(&sfx_path)
+        .arg("--dest")
+        .arg(unpack_dir.path())
+        .env("RAZE_IDENTITY", &identity_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "SFX extraction failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = fs::read_to_string(unpack_dir.path().join("secret.txt")).unwrap();
+    assert_eq!(content, "This is a secret message.");
+}
@@ -0,0 +1,288 @@
+//! # Integration Tests for Archive Signatures
+//!
+//! This module verifies that archives signed with Ed25519 are accepted only
+//! when they are signed by a trusted key and have not been modified, for
+//! plain, encrypted and self-extracting archives, and that signed archives are
+//! not re-keyed unless forced.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::decompress::{self, UnpackOptions};
+use raze::core::format::ArchiveFormat;
+use raze::core::recovery;
+use raze::core::rekey::{self, NewKey};
+use raze::core::signature::{self, SigningKey};
+use raze::core::volume;
+use raze::utils::errors::RazeError;
+use raze::utils::security::Credentials;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+/// Packs a small directory into `archive_path` with `options`.
+fn pack_sample(dir: &Path, archive_path: &Path, options: &PackOptions) {
+    let source = dir.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("notes.txt"), "signed content ".repeat(100)).unwrap();
+    compress::pack_with_options(&source, archive_path, options).unwrap();
+}
+
+fn assert_signature_error(result: Result<impl std::fmt::Debug, RazeError>, expected: &str) {
+    match result {
+        Err(RazeError::SignatureError(msg)) => {
+            assert!(msg.contains(expected), "unexpected message: {}", msg)
+        },
+        other => panic!("expected a signature error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_signed_archive_verifies_and_unpacks() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("signed.rz");
+    let key = SigningKey::generate().unwrap();
+    let options = PackOptions {
+        signing_key: Some(key.clone()),
+        ..PackOptions::default()
+    };
+    pack_sample(dir.path(), &archive_path, &options);
+
+    let trusted = vec![key.verifying_key()];
+    assert_eq!(
+        signature::verify(&archive_path, &trusted).unwrap(),
+        key.verifying_key()
+    );
+
+    // The signature trailer does not get in the way of reading the archive.
+    let unpack_dir = tempdir().unwrap();
+    let unpack_options = UnpackOptions {
+        trusted_keys: trusted,
+        ..UnpackOptions::default()
+    };
+    decompress::unpack_with_options(&archive_path, unpack_dir.path(), &unpack_options).unwrap();
+    assert!(unpack_dir.path().join("source/notes.txt").exists());
+    decompress::unpack(&archive_path, tempdir().unwrap().path(), None).unwrap();
+}
+
+#[test]
+fn test_untrusted_or_missing_signatures_are_refused() {
+    let dir = tempdir().unwrap();
+    let signed_path = dir.path().join("signed.rz");
+    let unsigned_path = dir.path().join("unsigned.rz");
+    let key = SigningKey::generate().unwrap();
+    let other = SigningKey::generate().unwrap();
+    let signed = PackOptions {
+        signing_key: Some(key),
+        ..PackOptions::default()
+    };
+    pack_sample(dir.path(), &signed_path, &signed);
+    pack_sample(dir.path(), &unsigned_path, &PackOptions::default());
+
+    let options = UnpackOptions {
+        trusted_keys: vec![other.verifying_key()],
+        ..UnpackOptions::default()
+    };
+    let unpack_dir = tempdir().unwrap();
+    assert_signature_error(
+        decompress::unpack_with_options(&signed_path, unpack_dir.path(), &options),
+        "not a trusted key",
+    );
+    assert_signature_error(
+        decompress::unpack_with_options(&unsigned_path, unpack_dir.path(), &options),
+        "is not signed",
+    );
+    assert_signature_error(
+        decompress::test_with_options(&unsigned_path, &options),
+        "is not signed",
+    );
+    assert!(fs::read_dir(unpack_dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn test_modified_encrypted_archive_fails_verification() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("secret.rz");
+    let key = SigningKey::generate().unwrap();
+    let options = PackOptions {
+        password: Some("password123".into()),
+        signing_key: Some(key.clone()),
+        recovery_percent: Some(5),
+        ..PackOptions::default()
+    };
+    pack_sample(dir.path(), &archive_path, &options);
+
+    let trusted = [key.verifying_key()];
+    signature::verify(&archive_path, &trusted).unwrap();
+
+    // Flip one bit inside the encrypted stream.
+    let mut bytes = fs::read(&archive_path).unwrap();
+    bytes[500] ^= 0x01;
+    fs::write(&archive_path, bytes).unwrap();
+    assert_signature_error(
+        signature::verify(&archive_path, &trusted),
+        "has been modified",
+    );
+
+    // Repairing the damage restores exactly the signed bytes.
+    recovery::repair(&archive_path).unwrap();
+    signature::verify(&archive_path, &trusted).unwrap();
+}
+
+#[test]
+fn test_swapped_sfx_stub_fails_verification() {
+    let dir = tempdir().unwrap();
+    let sfx_path = dir.path().join("installer");
+    let key = SigningKey::generate().unwrap();
+    let options = PackOptions {
+        signing_key: Some(key.clone()),
+        sfx_stub: Some(PathBuf::from(env!("CARGO_BIN_EXE_raze-sfx"))),
+        ..PackOptions::default()
+    };
+    pack_sample(dir.path(), &sfx_path, &options);
+
+    let trusted = [key.verifying_key()];
+    signature::verify(&sfx_path, &trusted).unwrap();
+
+    // Patch one byte of the stub, leaving the archive stream as it was.
+    let mut bytes = fs::read(&sfx_path).unwrap();
+    bytes[1000] ^= 0x01;
+    fs::write(&sfx_path, bytes).unwrap();
+    assert_signature_error(signature::verify(&sfx_path, &trusted), "has been modified");
+}
+
+#[test]
+fn test_signed_archive_is_not_rekeyed_unless_forced() {
+    let dir = tempdir().unwrap();
+    let archive_path = dir.path().join("secret.rz");
+    let key = SigningKey::generate().unwrap();
+    let options = PackOptions {
+        password: Some("password123".into()),
+        signing_key: Some(key.clone()),
+        ..PackOptions::default()
+    };
+    pack_sample(dir.path(), &archive_path, &options);
+    let before = fs::read(&archive_path).unwrap();
+
+    let credentials = Credentials::from_password("password123");
+    let new_key = NewKey {
+        password: Some("new password".into()),
+        ..NewKey::default()
+    };
+    assert_signature_error(
+        rekey::rekey(&archive_path, &credentials, &new_key, false),
+        "is signed",
+    );
+    assert_eq!(fs::read(&archive_path).unwrap(), before);
+
+    // Forcing it re-keys the archive, and the signature no longer verifies.
+    rekey::rekey(&archive_path, &credentials, &new_key, true).unwrap();
+    decompress::test(&archive_path, Some("new password")).unwrap();
+    assert_signature_error(
+        signature::verify(&archive_path, &[key.verifying_key()]),
+        "has been modified",
+    );
+}
+
+#[test]
+fn test_signing_keys_and_trusted_key_directories() {
+    let dir = tempdir().unwrap();
+    let key = SigningKey::generate().unwrap();
+    let key_path = dir.path().join("signing.key");
+    signature::write_signing_key(&key_path, &key).unwrap();
+    assert!(signature::write_signing_key(&key_path, &key).is_err());
+    assert_eq!(
+        signature::read_signing_key(&key_path)
+            .unwrap()
+            .verifying_key(),
+        key.verifying_key()
+    );
+
+    let trusted_dir = dir.path().join("trusted");
+    fs::create_dir(&trusted_dir).unwrap();
+    assert_signature_error(
+        signature::read_trusted_keys(&trusted_dir),
+        "No trusted keys",
+    );
+
+    let other = SigningKey::generate().unwrap();
+    fs::write(
+        trusted_dir.join("team.pub"),
+        format!(
+            "# release keys\n{}\n\n{}\n",
+            key.verifying_key(),
+            other.verifying_key()
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        signature::read_trusted_keys(&trusted_dir).unwrap(),
+        vec![key.verifying_key(), other.verifying_key()]
+    );
+
+    fs::write(trusted_dir.join("broken.pub"), "raze-sign-1234\n").unwrap();
+    assert_signature_error(
+        signature::read_trusted_keys(&trusted_dir),
+        "invalid verifying key",
+    );
+}
+
+/// Tests that a volume of a signed split archive swapped for a forged one
+/// after the signature has been verified is never extracted: the volumes are
+/// read through the handles that were verified.
+#[test]
+fn test_volumes_swapped_after_verification_are_not_extracted() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("data.bin");
+    let data: Vec<u8> = (0..200 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(&source, &data).unwrap();
+    let archive_path = dir.path().join("split.rz");
+    let key = SigningKey::generate().unwrap();
+    let options = PackOptions {
+        signing_key: Some(key.clone()),
+        volume_size: Some(64 * 1024),
+        // Without compression, a changed byte in a volume is a changed byte in the file.
+        format: ArchiveFormat::Tar,
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&source, &archive_path, &options).unwrap();
+    let first = volume::volume_path(&archive_path, 1);
+    let second = volume::volume_path(&archive_path, 2);
+    let genuine = fs::read(&second).unwrap();
+    let mut forged = genuine.clone();
+    let middle = forged.len() / 2;
+    forged[middle] ^= 0xff;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (done, second) = (done.clone(), second.clone());
+        let staged = dir.path().join("staged");
+        std::thread::spawn(move || {
+            // Each swap is a rename, as an attacker would do it, leaving open handles alone.
+            while !done.load(Ordering::Relaxed) {
+                for contents in [&forged, &genuine] {
+                    fs::write(&staged, contents).unwrap();
+                    fs::rename(&staged, &second).unwrap();
+                }
+            }
+        })
+    };
+    let unpack_options = UnpackOptions {
+        trusted_keys: vec![key.verifying_key()],
+        ..UnpackOptions::default()
+    };
+    for run in 0..200 {
+        let unpack_dir = dir.path().join(format!("out{run}"));
+        // A forged volume present while verifying is refused; one swapped in later is not read.
+        if decompress::unpack_with_options(&first, &unpack_dir, &unpack_options).is_ok() {
+            assert!(
+                fs::read(unpack_dir.join("data.bin")).unwrap() == data,
+                "a forged volume was extracted in run {run}"
+            );
+        }
+        fs::remove_dir_all(&unpack_dir).ok();
+    }
+    done.store(true, Ordering::Relaxed);
+    swapper.join().unwrap();
+}
@@ -0,0 +1,166 @@
+//! # Integration Tests for Multi-Volume Archives
+//!
+//! This module verifies that archives split into fixed-size volumes can be
+//! read back as one continuous stream, and that missing or out-of-order
+//! volumes are reported instead of producing corrupted output.
+
+use raze::core::compress::{self, PackOptions};
+use raze::core::decompress;
+use raze::core::volume;
+use raze::utils::errors::RazeError;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// Writes a file large enough to span several small volumes.
+fn write_incompressible_file(path: &Path, len: usize) -> Vec<u8> {
+    // A simple xorshift generator keeps the data incompressible without extra dependencies.
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let data: Vec<u8> = (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(path, &data).expect("Failed to write test file");
+    data
+}
+
+#[test]
+fn test_split_archive_roundtrip() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("data.bin");
+    let data = write_incompressible_file(&file_path, 200 * 1024);
+    let archive_path = dir.path().join("data.rz");
+
+    let options = PackOptions {
+        volume_size: Some(64 * 1024),
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+
+    let first = volume::volume_path(&archive_path, 1);
+    assert!(volume::volume_path(&archive_path, 4).exists());
+    assert!(!archive_path.exists());
+
+    let entries = decompress::list(&first, None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].size, data.len() as u64);
+    decompress::test(&first, None).unwrap();
+
+    let unpack_dir = tempdir().unwrap();
+    decompress::unpack(&first, unpack_dir.path(), None).unwrap();
+    assert_eq!(fs::read(unpack_dir.path().join("data.bin")).unwrap(), data);
+}
+
+#[test]
+fn test_split_encrypted_archive_roundtrip() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.bin");
+    let data = write_incompressible_file(&file_path, 100 * 1024);
+    let archive_path = dir.path().join("secret.rz");
+
+    let options = PackOptions {
+        password: Some("password123".into()),
+        volume_size: Some(32 * 1024),
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+
+    let unpack_dir = tempdir().unwrap();
+    let first = volume::volume_path(&archive_path, 1);
+    decompress::unpack(&first, unpack_dir.path(), Some("password123")).unwrap();
+    assert_eq!(
+        fs::read(unpack_dir.path().join("secret.bin")).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn test_missing_and_out_of_order_volumes() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("data.bin");
+    write_incompressible_file(&file_path, 200 * 1024);
+    let archive_path = dir.path().join("data.rz");
+
+    let options = PackOptions {
+        volume_size: Some(64 * 1024),
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    let first = volume::volume_path(&archive_path, 1);
+    let second = volume::volume_path(&archive_path, 2);
+    let third = volume::volume_path(&archive_path, 3);
+
+    // Swapping two volumes must be detected as out of order.
+    let parked = dir.path().join("parked");
+    fs::rename(&second, &parked).unwrap();
+    fs::rename(&third, &second).unwrap();
+    fs::rename(&parked, &third).unwrap();
+    let result = decompress::test(&first, None);
+    assert!(
+        matches!(result, Err(RazeError::VolumeError(ref msg)) if msg.contains("out of order")),
+        "unexpected result: {:?}",
+        result
+    );
+
+    // Removing a volume must be reported as missing.
+    fs::remove_file(&second).unwrap();
+    let result = decompress::test(&first, None);
+    assert!(
+        matches!(result, Err(RazeError::VolumeError(ref msg)) if msg.contains("missing")),
+        "unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_replacing_a_volume_set() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("data.bin");
+    write_incompressible_file(&file_path, 200 * 1024);
+    let archive_path = dir.path().join("data.rz");
+    let options = PackOptions {
+        volume_size: Some(64 * 1024),
+        ..PackOptions::default()
+    };
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    assert!(volume::volume_path(&archive_path, 4).exists());
+
+    // A smaller set replaces the larger one, leaving none of its volumes behind.
+    let data = write_incompressible_file(&file_path, 80 * 1024);
+    compress::pack_with_options(&file_path, &archive_path, &options).unwrap();
+    assert!(volume::volume_path(&archive_path, 2).exists());
+    assert!(!volume::volume_path(&archive_path, 3).exists());
+    assert!(!volume::volume_path(&archive_path, 4).exists());
+    let unpack_dir = tempdir().unwrap();
+    let first = volume::volume_path(&archive_path, 1);
+    decompress::unpack(&first, unpack_dir.path(), None).unwrap();
+    assert_eq!(fs::read(unpack_dir.path().join("data.bin")).unwrap(), data);
+
+    // If a volume cannot be renamed into place, the old set is put back.
+    let old: Vec<_> = (1..=2)
+        .map(|index| fs::read(volume::volume_path(&archive_path, index)).unwrap())
+        .collect();
+    fs::create_dir(volume::volume_path(&archive_path, 3)).unwrap();
+    write_incompressible_file(&file_path, 200 * 1024);
+    assert!(compress::pack_with_options(&file_path, &archive_path, &options).is_err());
+    for (index, old) in (1..=2).zip(old) {
+        assert_eq!(
+            fs::read(volume::volume_path(&archive_path, index)).unwrap(),
+            old
+        );
+    }
+    assert!(!volume::volume_path(&archive_path, 4).exists());
+    let mut names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        ["data.bin", "data.rz.001", "data.rz.002", "data.rz.003"]
+    );
+}